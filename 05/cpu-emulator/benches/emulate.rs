@@ -0,0 +1,49 @@
+//! Compare the simple interpreter against the predecoded engine.
+
+use cpu_emulator::{Computer, Device, Program, Timer, KBD, TIMER};
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+const CYCLES: u64 = 1_000_000;
+
+fn bench(c: &mut Criterion) {
+    let programs = [
+        ("fill", include_str!("../../../04/fill/Fill.asm")),
+        ("mult", include_str!("../../../04/mult/Mult.asm")),
+        // Counts, and reads the timer each time around the loop.
+        ("timer", "(LOOP)\n@i\nM=M+1\n@TIMER\nD=M\n@LOOP\n0;JMP\n"),
+    ];
+    let devices = || -> Vec<Box<dyn Device>> { vec![Box::new(Timer::new(TIMER, 100).unwrap())] };
+
+    let mut group = c.benchmark_group("emulate");
+    for (name, source) in programs {
+        let program = Program::from_asm_with_devices(source, &devices()).unwrap();
+        let setup = || {
+            let mut computer = Computer::with_devices(&program.words, devices()).unwrap();
+            // Inputs: hold down a key for Fill, and multiply large numbers for
+            // Mult.
+            computer.ram_mut()[usize::from(KBD)] = 1;
+            computer.ram_mut()[0] = 30_000;
+            computer.ram_mut()[1] = 1;
+            computer
+        };
+
+        group.bench_function(BenchmarkId::new("run", name), |b| {
+            b.iter_batched_ref(
+                setup,
+                |computer| computer.run(CYCLES).unwrap(),
+                criterion::BatchSize::LargeInput,
+            )
+        });
+        group.bench_function(BenchmarkId::new("run_fast", name), |b| {
+            b.iter_batched_ref(
+                setup,
+                |computer| computer.run_fast(CYCLES).unwrap(),
+                criterion::BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench);
+criterion_main!(benches);
@@ -0,0 +1,41 @@
+//! The arithmetic logic unit, as specified in `02/ALU.hdl`.
+
+use assembler::Comp;
+
+/// Compute `comp`, given the ALU inputs `x` (always `D`) and `y` (either `A`
+/// or `M`, depending on `comp.a_bit()`).
+pub fn compute(comp: Comp, x: u16, y: u16) -> u16 {
+    let [zx, nx, zy, ny, f, no] = comp.c_bits();
+
+    if comp.is_shift() {
+        return shift(x, y, comp.c_bits());
+    }
+
+    let x = if zx { 0 } else { x };
+    let x = if nx { !x } else { x };
+    let y = if zy { 0 } else { y };
+    let y = if ny { !y } else { y };
+    let out = if f { x.wrapping_add(y) } else { x & y };
+
+    if no {
+        !out
+    } else {
+        out
+    }
+}
+
+/// The shift operations of the extended ISA.
+///
+/// Right shifts are arithmetic (sign-extending), as in the official CPU
+/// emulator.
+fn shift(x: u16, y: u16, c_bits: [bool; 6]) -> u16 {
+    let [_, _, _, use_d, left, _] = c_bits;
+
+    let operand = if use_d { x } else { y };
+
+    if left {
+        operand << 1
+    } else {
+        ((operand as i16) >> 1) as u16
+    }
+}
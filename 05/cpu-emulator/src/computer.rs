@@ -0,0 +1,475 @@
+//! The Hack computer: a CPU, along with its instruction and data memory.
+//!
+//! See `05/Computer.hdl`.
+
+mod fast;
+mod snapshot;
+
+use std::path::Path;
+
+use anyhow::{bail, ensure, Context, Result};
+use assembler::{Dest, Instr, Jump, ADDRESS_LIMIT};
+
+use crate::{
+    alu,
+    device::{self, Device},
+    program::Program,
+    screen::Screen,
+};
+
+/// The number of words in each of ROM and RAM.
+pub const MEMORY_SIZE: usize = ADDRESS_LIMIT as usize;
+
+/// In `Computer::device_at`, for addresses which are plain RAM.
+const NO_DEVICE: u8 = u8::MAX;
+
+pub struct Computer {
+    /// Instruction memory.
+    rom: Box<[u16]>,
+
+    /// Data memory, including the memory-mapped screen and keyboard.
+    ram: Box<[u16]>,
+
+    a: u16,
+    d: u16,
+    pc: u16,
+
+    /// How many instructions have been executed so far.
+    cycles: u64,
+
+    /// The data memory accessed by the last call to `step`.
+    last_access: Access,
+
+    /// ROM, predecoded for `run_fast`. Cleared whenever ROM might change.
+    decoded: Option<Box<fast::Decoded>>,
+
+    /// Memory-mapped devices, including the built-in screen and keyboard.
+    devices: Vec<Box<dyn Device>>,
+
+    /// For each RAM address, the index in `devices` of the device which
+    /// handles it, or `NO_DEVICE`. Devices which behave just like RAM are
+    /// left out.
+    device_at: Box<[u8]>,
+}
+
+impl Computer {
+    /// Load `program` into ROM. Unused ROM addresses are filled with zeros.
+    pub fn new(program: &[u16]) -> Result<Self> {
+        ensure!(
+            program.len() <= MEMORY_SIZE,
+            "program is too long to fit in ROM: {} words",
+            program.len()
+        );
+
+        let mut rom = vec![0; MEMORY_SIZE].into_boxed_slice();
+        rom[..program.len()].copy_from_slice(program);
+
+        let mut computer = Self {
+            rom,
+            ram: vec![0; MEMORY_SIZE].into_boxed_slice(),
+            a: 0,
+            d: 0,
+            pc: 0,
+            cycles: 0,
+            last_access: Access::default(),
+            decoded: None,
+            devices: vec![],
+            device_at: vec![NO_DEVICE; MEMORY_SIZE].into_boxed_slice(),
+        };
+        for device in device::builtin() {
+            computer.add_device(device)?;
+        }
+        Ok(computer)
+    }
+
+    /// Load `program` into ROM, with extra devices as well as the built-in
+    /// ones. Their symbols should be given to the assembler too: see
+    /// `Program::from_file_with_devices`.
+    pub fn with_devices(program: &[u16], devices: Vec<Box<dyn Device>>) -> Result<Self> {
+        let mut computer = Self::new(program)?;
+        for device in devices {
+            computer.add_device(device)?;
+        }
+        Ok(computer)
+    }
+
+    /// Attach a memory-mapped device. Fails if its addresses overlap another
+    /// device's.
+    pub fn add_device(&mut self, device: Box<dyn Device>) -> Result<()> {
+        let range = device.range();
+        ensure!(
+            !range.is_empty() && range.end <= ADDRESS_LIMIT,
+            "invalid device address range {range:?}"
+        );
+        if let Some(other) = self.devices.iter().find(|other| {
+            let other = other.range();
+            other.start < range.end && range.start < other.end
+        }) {
+            bail!(
+                "device at {range:?} overlaps another device at {:?}",
+                other.range()
+            );
+        }
+
+        if !device.is_memory() {
+            let index = u8::try_from(self.devices.len())
+                .ok()
+                .filter(|&index| index != NO_DEVICE)
+                .context("too many devices")?;
+            self.device_at[usize::from(range.start)..usize::from(range.end)].fill(index);
+        }
+        self.devices.push(device);
+        Ok(())
+    }
+
+    pub fn devices(&self) -> &[Box<dyn Device>] {
+        &self.devices
+    }
+
+    /// Load a program from the contents of a `.hack` file.
+    pub fn from_hack(contents: &str) -> Result<Self> {
+        let program = assembler::parse_hack(contents)?;
+        Self::new(&program)
+    }
+
+    /// Load a `.hack` or `.asm` file. See `Program::from_file`.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        Self::new(&Program::from_file(path)?.words)
+    }
+
+    pub fn a(&self) -> u16 {
+        self.a
+    }
+
+    pub fn d(&self) -> u16 {
+        self.d
+    }
+
+    pub fn pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn set_a(&mut self, value: u16) {
+        self.a = value;
+    }
+
+    pub fn set_d(&mut self, value: u16) {
+        self.d = value;
+    }
+
+    pub fn set_pc(&mut self, value: u16) {
+        self.pc = value % ADDRESS_LIMIT;
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn set_cycles(&mut self, value: u64) {
+        self.cycles = value;
+    }
+
+    /// The RAM address that the next instruction will write to, if any.
+    pub fn next_write(&self) -> Option<u16> {
+        let word = self.rom[usize::from(self.pc)];
+        // A C-instruction (or shift) with `M` among its destinations.
+        let writes_m = word & 0x8000 != 0 && word & 0b1000 != 0;
+        writes_m.then_some(self.a % ADDRESS_LIMIT)
+    }
+
+    /// The data memory accessed by the last call to `step`. Values read from
+    /// devices are as the CPU saw them, which may differ from what's stored
+    /// in RAM. Not updated by `run_fast` or `run_until_halted`.
+    pub fn last_access(&self) -> Access {
+        self.last_access
+    }
+
+    pub fn rom(&self) -> &[u16] {
+        &self.rom
+    }
+
+    pub fn rom_mut(&mut self) -> &mut [u16] {
+        self.decoded = None;
+        &mut self.rom
+    }
+
+    pub fn ram(&self) -> &[u16] {
+        &self.ram
+    }
+
+    pub fn ram_mut(&mut self) -> &mut [u16] {
+        &mut self.ram
+    }
+
+    pub fn screen(&self) -> Screen<'_> {
+        Screen::new(&self.ram)
+    }
+
+    /// Execute a single instruction.
+    pub fn step(&mut self) -> Result<()> {
+        let word = self.rom[usize::from(self.pc)];
+        self.last_access = Access::default();
+
+        // A-instruction.
+        if word & 0x8000 == 0 {
+            self.a = word;
+            self.advance_pc(None);
+            return Ok(());
+        }
+
+        let instr = Instr::decode(word)
+            .with_context(|| format!("invalid instruction at ROM[{}]", self.pc))?;
+        let c = instr.c_instr().expect("highest bit is set");
+
+        // `A` is only read before the clock edge, as in `05/CPU.hdl`. So
+        // e.g. `AM=M+1` writes to the old value of `A`.
+        let address = usize::from(self.a % ADDRESS_LIMIT);
+
+        let comp = c.comp();
+        let y = if comp.a_bit() {
+            let value = self.read(address);
+            self.last_access.read = Some(value);
+            value
+        } else {
+            self.a
+        };
+        let out = alu::compute(comp, self.d, y);
+
+        let dest = c.dest();
+        if dest.m {
+            self.last_access.write = Some((address as u16, out));
+            self.write(address, out)?;
+        }
+        let jump_target = self.a;
+        if dest.a {
+            self.a = out;
+        }
+        if dest.d {
+            self.d = out;
+        }
+
+        let jump = c.jump().test(out as i16).then_some(jump_target);
+        self.advance_pc(jump);
+        Ok(())
+    }
+
+    /// Run until `done` returns true, or until `max_cycles` more instructions
+    /// have been executed, whichever comes first.
+    ///
+    /// Returns whether `done` returned true.
+    pub fn run_until(
+        &mut self,
+        max_cycles: u64,
+        mut done: impl FnMut(&Self) -> bool,
+    ) -> Result<bool> {
+        for _ in 0..max_cycles {
+            if done(self) {
+                return Ok(true);
+            }
+            self.step()?;
+        }
+
+        Ok(done(self))
+    }
+
+    /// Execute exactly `cycles` instructions.
+    pub fn run(&mut self, cycles: u64) -> Result<()> {
+        self.run_until(cycles, |_| false)?;
+        Ok(())
+    }
+
+    /// Execute exactly `cycles` instructions, like `run`, but much faster.
+    ///
+    /// ROM is decoded on first use, and after any changes to it.
+    pub fn run_fast(&mut self, cycles: u64) -> Result<()> {
+        self.run_decoded(cycles, false)
+    }
+
+    /// Whether the program has halted, i.e. it's about to jump back to the
+    /// same instruction, or to the `@` just before it, forever. That's the
+    /// usual way to end a program:
+    ///
+    /// ```text
+    /// (END)
+    /// @END
+    /// 0;JMP
+    /// ```
+    pub fn is_halted(&self) -> bool {
+        is_halt(&self.rom, self.pc, self.a)
+    }
+
+    /// Run until the program halts (see `is_halted`), or until `max_cycles`
+    /// more instructions have been executed, whichever comes first. Uses the
+    /// same engine as `run_fast`.
+    ///
+    /// Returns whether the program halted.
+    pub fn run_until_halted(&mut self, max_cycles: u64) -> Result<bool> {
+        self.run_decoded(max_cycles, true)?;
+        Ok(self.is_halted())
+    }
+
+    /// Run the predecoded engine, decoding ROM first if needed.
+    fn run_decoded(&mut self, cycles: u64, stop_at_halt: bool) -> Result<()> {
+        let decoded = match self.decoded.take() {
+            Some(decoded) => decoded,
+            None => Box::new(fast::Decoded::new(&self.rom)),
+        };
+        let result = decoded.run(self, cycles, stop_at_halt);
+        self.decoded = Some(decoded);
+        result
+    }
+
+    /// Read a word of RAM, through its device if it has one.
+    fn read(&mut self, address: usize) -> u16 {
+        let stored = self.ram[address];
+        match self.device_at[address] {
+            NO_DEVICE => stored,
+            index => {
+                let device = &mut self.devices[usize::from(index)];
+                device.read(address as u16, stored, self.cycles)
+            }
+        }
+    }
+
+    /// Write a word of RAM, and pass it on to its device if it has one.
+    fn write(&mut self, address: usize, value: u16) -> Result<()> {
+        self.ram[address] = value;
+        match self.device_at[address] {
+            NO_DEVICE => Ok(()),
+            index => {
+                let device = &mut self.devices[usize::from(index)];
+                device
+                    .write(address as u16, value, self.cycles)
+                    .with_context(|| format!("device at RAM[{address}] failed"))
+            }
+        }
+    }
+
+    /// Go to the next instruction, or to `jump_target` if given.
+    ///
+    /// The program counter is 15 bits, so it wraps around at the end of ROM.
+    fn advance_pc(&mut self, jump_target: Option<u16>) {
+        let next = jump_target.unwrap_or(self.pc.wrapping_add(1));
+        self.pc = next % ADDRESS_LIMIT;
+        self.cycles += 1;
+    }
+}
+
+/// The data memory accessed by a single instruction.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Access {
+    /// The value read from `M`, if the instruction read it.
+    pub read: Option<u16>,
+
+    /// The address and value written to `M`, if the instruction wrote it.
+    pub write: Option<(u16, u16)>,
+}
+
+/// Whether the instruction at `pc` is an unconditional jump, with no other
+/// effects, to itself or to an `@` which loads its own address.
+fn is_halt(rom: &[u16], pc: u16, a: u16) -> bool {
+    let Ok(instr) = Instr::decode(rom[usize::from(pc)]) else {
+        return false;
+    };
+    let Some(c) = instr.c_instr() else {
+        return false;
+    };
+    let jumps_back = a == pc || (pc > 0 && a == pc - 1 && rom[usize::from(a)] == a);
+    c.jump() == Jump::Always && c.dest() == Dest::default() && jumps_back
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load_asm(source: &str) -> Result<Computer> {
+        Computer::new(&Program::from_asm(source)?.words)
+    }
+
+    #[test]
+    fn mult() -> Result<()> {
+        for (x, y) in [(0, 0), (1, 0), (0, 5), (3, 1), (6, 7), (2, 16383)] {
+            let mut computer = load_asm(include_str!("../../../04/mult/Mult.asm"))?;
+            computer.ram_mut()[0] = x;
+            computer.ram_mut()[1] = y;
+
+            computer.run(1000)?;
+            assert_eq!(computer.ram()[2], x * y, "{x} * {y}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn old_a_register() -> Result<()> {
+        let mut computer = load_asm("@100\nAM=A+1\n@7\nD=A\nAD=D-1;JGT\n")?;
+
+        computer.run(2)?;
+        assert_eq!(computer.ram()[100], 101);
+        assert_eq!(computer.a(), 101);
+
+        // Jump to the old value of A (7), not the new one (6).
+        computer.run(3)?;
+        assert_eq!(computer.pc(), 7);
+
+        Ok(())
+    }
+
+    #[test]
+    fn shifts() -> Result<()> {
+        let mut computer = load_asm("@5\nD=A<<\n@32767\nA=!A\nA=A>>\n")?;
+        computer.run(5)?;
+
+        assert_eq!(computer.d(), 10);
+        // Arithmetic shift: -32768 >> 1 == -16384.
+        assert_eq!(computer.a() as i16, -16384);
+
+        Ok(())
+    }
+
+    #[test]
+    fn halt() -> Result<()> {
+        let source = "@3
+D=A
+@x
+M=D
+(END)
+@END
+0;JMP
+";
+        let mut slow = load_asm(source)?;
+        assert!(slow.run_until(100, Computer::is_halted)?);
+        assert_eq!((slow.pc(), slow.cycles()), (5, 5));
+
+        // The fast engine stops at the same place, even partway through a
+        // block, and stays halted.
+        let mut fast = load_asm(source)?;
+        assert!(fast.run_until_halted(100)?);
+        assert_eq!((fast.pc(), fast.cycles()), (5, 5));
+        assert!(fast.run_until_halted(100)?);
+        assert_eq!(fast.cycles(), 5);
+
+        // Running out of cycles first.
+        let mut computer = load_asm(source)?;
+        assert!(!computer.run_until_halted(3)?);
+        assert_eq!(computer.cycles(), 3);
+
+        // Halting after reading a device, which the fast engine does through
+        // `step`.
+        let devices = || -> Vec<Box<dyn Device>> {
+            vec![Box::new(device::Timer::new(crate::TIMER, 1).unwrap())]
+        };
+        let program =
+            Program::from_asm_with_devices("@TIMER\nD=M\n(END)\n@END\n0;JMP\n", &devices())?;
+        let mut computer = Computer::with_devices(&program.words, devices())?;
+        assert!(computer.run_until_halted(100)?);
+        assert_eq!((computer.d(), computer.pc(), computer.cycles()), (1, 3, 3));
+
+        // A conditional jump back isn't a halt, nor is a jump elsewhere.
+        for source in ["(END)\n@END\nD;JEQ\n", "@2\n0;JMP\n@0\n0;JMP\n"] {
+            let mut computer = load_asm(source)?;
+            assert!(!computer.run_until_halted(100)?, "{source}");
+        }
+        Ok(())
+    }
+}
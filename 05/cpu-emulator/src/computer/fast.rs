@@ -0,0 +1,498 @@
+//! A faster execution engine, which decodes ROM once, up front.
+//!
+//! Each instruction is predecoded into an `Op`, with the ALU control bits
+//! turned into masks. ROM is split into blocks, each ending at an
+//! instruction which might jump, so within a block we don't need to check for
+//! jumps or for running out of cycles.
+//!
+//! Memory-mapped devices (other than the screen and keyboard, which are just
+//! RAM) are observable, so accesses to them go through `Computer::step`
+//! instead. That ends a block early, but only when the address in A actually
+//! belongs to a device.
+//!
+//! This must behave exactly like `Computer::step`, which remains the
+//! reference implementation.
+
+use anyhow::Result;
+use assembler::{Instr, ADDRESS_LIMIT};
+
+use super::{Computer, NO_DEVICE};
+
+/// Jump bits, as in the low three bits of a C-instruction.
+const JLT: u8 = 0b100;
+const JEQ: u8 = 0b010;
+const JGT: u8 = 0b001;
+
+/// Destination bits, as in bits 3 to 5 of a C-instruction.
+const DEST_A: u8 = 0b100;
+const DEST_D: u8 = 0b010;
+const DEST_M: u8 = 0b001;
+
+#[derive(Debug, Clone, Copy)]
+enum Op {
+    /// An A-instruction.
+    Load(u16),
+
+    /// A C-instruction. The ALU inputs are masked with `*_and` and then
+    /// `*_xor`, which implements zeroing and negating them.
+    Compute {
+        x_and: u16,
+        x_xor: u16,
+        y_and: u16,
+        y_xor: u16,
+        add: bool,
+        out_xor: u16,
+        y_is_m: bool,
+        dest: u8,
+        jump: u8,
+    },
+
+    /// A shift instruction, from the extended ISA.
+    Shift {
+        use_d: bool,
+        left: bool,
+        y_is_m: bool,
+        dest: u8,
+        jump: u8,
+    },
+
+    /// Left to `Computer::step`, to report the error.
+    Invalid,
+}
+
+/// ROM, predecoded.
+pub(super) struct Decoded {
+    /// Indexed by ROM address.
+    ops: Box<[Op]>,
+
+    /// For each ROM address, the address of the last instruction in its
+    /// block.
+    block_end: Box<[u16]>,
+}
+
+impl Decoded {
+    pub(super) fn new(rom: &[u16]) -> Self {
+        let ops: Box<[Op]> = rom.iter().map(|&word| decode(word)).collect();
+
+        let mut block_end = vec![0; ops.len()].into_boxed_slice();
+        let mut end = ops.len() as u16 - 1;
+        for (address, op) in ops.iter().enumerate().rev() {
+            if ends_block(op) {
+                end = address as u16;
+            }
+            block_end[address] = end;
+        }
+
+        Self { ops, block_end }
+    }
+
+    /// Execute exactly `cycles` instructions, or if `stop_at_halt` is set,
+    /// stop early once the program halts. See `Computer::is_halted`.
+    pub(super) fn run(
+        &self,
+        computer: &mut Computer,
+        cycles: u64,
+        stop_at_halt: bool,
+    ) -> Result<()> {
+        let mut remaining = cycles;
+        while remaining > 0 {
+            match self.run_until_device(computer, &mut remaining, stop_at_halt) {
+                Stop::Done => break,
+                Stop::Device => {
+                    if stop_at_halt && computer.is_halted() {
+                        break;
+                    }
+                    computer.step()?;
+                    remaining -= 1;
+                }
+                Stop::Invalid => {
+                    // Let `step` report the error.
+                    computer.step()?;
+                    unreachable!("invalid instructions fail to execute");
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Run until `remaining` is 0, or the program halts (if `stop_at_halt`
+    /// is set), or the next instruction accesses a device or can't execute.
+    fn run_until_device(
+        &self,
+        computer: &mut Computer,
+        remaining: &mut u64,
+        stop_at_halt: bool,
+    ) -> Stop {
+        let mut state = State {
+            a: computer.a,
+            d: computer.d,
+            ram: &mut computer.ram,
+            device_at: &computer.device_at,
+        };
+        let mut pc = computer.pc;
+        let start = *remaining;
+
+        let stop = loop {
+            if *remaining == 0 {
+                break Stop::Done;
+            }
+            let end = self.block_end[usize::from(pc)];
+            let len = u64::from(end - pc) + 1;
+
+            // Not enough cycles left to finish the block, so stop before the
+            // instruction that might jump.
+            let straight = if len > *remaining {
+                *remaining
+            } else {
+                len - 1
+            };
+            let block = &self.ops[usize::from(pc)..][..straight as usize];
+            let executed = state.execute_all(block);
+            pc += executed;
+            *remaining -= u64::from(executed);
+            if u64::from(executed) < straight {
+                break Stop::Device;
+            }
+            if *remaining == 0 {
+                break Stop::Done;
+            }
+
+            let op = self.ops[usize::from(pc)];
+            if let Op::Invalid = op {
+                break Stop::Invalid;
+            }
+            if state.accesses_device(op) {
+                break Stop::Device;
+            }
+            // Halting means jumping, so it's enough to check at the ends of
+            // blocks.
+            if stop_at_halt && super::is_halt(&computer.rom, pc, state.a) {
+                break Stop::Done;
+            }
+            pc = match state.execute(op) {
+                Some(target) => target % ADDRESS_LIMIT,
+                None => (pc + 1) % ADDRESS_LIMIT,
+            };
+            *remaining -= 1;
+        };
+
+        computer.a = state.a;
+        computer.d = state.d;
+        computer.pc = pc;
+        computer.cycles += start - *remaining;
+        stop
+    }
+}
+
+/// Why `Decoded::run_until_device` stopped.
+enum Stop {
+    /// Out of cycles, or halted.
+    Done,
+
+    /// The next instruction accesses a device.
+    Device,
+
+    /// The next instruction can't execute.
+    Invalid,
+}
+
+/// The registers and RAM, while running.
+struct State<'a> {
+    a: u16,
+    d: u16,
+    ram: &'a mut [u16],
+
+    /// See `Computer::device_at`.
+    device_at: &'a [u8],
+}
+
+impl State<'_> {
+    /// Execute straight-line code, stopping before any instruction which
+    /// accesses a device. Returns how many instructions were executed.
+    #[inline(always)]
+    fn execute_all(&mut self, ops: &[Op]) -> u16 {
+        for (i, &op) in ops.iter().enumerate() {
+            if self.accesses_device(op) {
+                return i as u16;
+            }
+            self.execute(op);
+        }
+        ops.len() as u16
+    }
+
+    /// Whether `op` reads or writes `M`, and A holds a device's address.
+    #[inline(always)]
+    fn accesses_device(&self, op: Op) -> bool {
+        let uses_m = match op {
+            Op::Load(_) | Op::Invalid => false,
+            Op::Compute { y_is_m, dest, .. } | Op::Shift { y_is_m, dest, .. } => {
+                y_is_m || dest & DEST_M != 0
+            }
+        };
+        uses_m && self.device_at[usize::from(self.a % ADDRESS_LIMIT)] != NO_DEVICE
+    }
+
+    /// Execute one instruction, returning the jump target if it jumps.
+    #[inline(always)]
+    fn execute(&mut self, op: Op) -> Option<u16> {
+        let (out, dest, jump) = match op {
+            Op::Load(value) => {
+                self.a = value;
+                return None;
+            }
+            Op::Compute {
+                x_and,
+                x_xor,
+                y_and,
+                y_xor,
+                add,
+                out_xor,
+                y_is_m,
+                dest,
+                jump,
+            } => {
+                let x = (self.d & x_and) ^ x_xor;
+                let y = (self.y(y_is_m) & y_and) ^ y_xor;
+                let out = if add { x.wrapping_add(y) } else { x & y };
+                (out ^ out_xor, dest, jump)
+            }
+            Op::Shift {
+                use_d,
+                left,
+                y_is_m,
+                dest,
+                jump,
+            } => {
+                let operand = if use_d { self.d } else { self.y(y_is_m) };
+                let out = if left {
+                    operand << 1
+                } else {
+                    ((operand as i16) >> 1) as u16
+                };
+                (out, dest, jump)
+            }
+            Op::Invalid => unreachable!("invalid instructions end blocks"),
+        };
+
+        let old_a = self.a;
+        if dest & DEST_M != 0 {
+            self.ram[usize::from(old_a % ADDRESS_LIMIT)] = out;
+        }
+        if dest & DEST_A != 0 {
+            self.a = out;
+        }
+        if dest & DEST_D != 0 {
+            self.d = out;
+        }
+
+        let condition = match out as i16 {
+            0 => JEQ,
+            out if out < 0 => JLT,
+            _ => JGT,
+        };
+        (jump & condition != 0).then_some(old_a)
+    }
+
+    #[inline(always)]
+    fn y(&self, y_is_m: bool) -> u16 {
+        if y_is_m {
+            self.ram[usize::from(self.a % ADDRESS_LIMIT)]
+        } else {
+            self.a
+        }
+    }
+}
+
+fn decode(word: u16) -> Op {
+    if word & 0x8000 == 0 {
+        return Op::Load(word);
+    }
+
+    let Ok(instr) = Instr::decode(word) else {
+        return Op::Invalid;
+    };
+    let comp = instr.c_instr().expect("highest bit is set").comp();
+    let dest = ((word >> 3) & 0b111) as u8;
+    let jump = (word & 0b111) as u8;
+    let y_is_m = comp.a_bit();
+
+    let [zx, nx, zy, ny, f, no] = comp.c_bits();
+    if comp.is_shift() {
+        return Op::Shift {
+            use_d: ny,
+            left: f,
+            y_is_m,
+            dest,
+            jump,
+        };
+    }
+
+    let mask = |bit: bool| if bit { 0xffff } else { 0 };
+    Op::Compute {
+        x_and: !mask(zx),
+        x_xor: mask(nx),
+        y_and: !mask(zy),
+        y_xor: mask(ny),
+        add: f,
+        out_xor: mask(no),
+        y_is_m,
+        dest,
+        jump,
+    }
+}
+
+/// Blocks end with any instruction that might jump, or that can't execute.
+fn ends_block(op: &Op) -> bool {
+    match *op {
+        Op::Load(_) => false,
+        Op::Compute { jump, .. } | Op::Shift { jump, .. } => jump != 0,
+        Op::Invalid => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        device::{Device, Random, Timer},
+        program::Program,
+        RANDOM, TIMER,
+    };
+
+    /// A simple xorshift generator, so tests are repeatable.
+    struct Rng(u64);
+
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+    }
+
+    /// Run two copies of a program, one with `run` and one with `run_fast`,
+    /// in chunks of random sizes, checking that they always agree.
+    fn differential(
+        program: &[u16],
+        ram: &[(usize, u16)],
+        rng: &mut Rng,
+        cycles: u64,
+    ) -> Result<()> {
+        differential_with(|| Computer::new(program), ram, rng, cycles)
+    }
+
+    /// Like `differential`, for computers made by `new`, e.g. with devices.
+    fn differential_with(
+        new: impl Fn() -> Result<Computer>,
+        ram: &[(usize, u16)],
+        rng: &mut Rng,
+        cycles: u64,
+    ) -> Result<()> {
+        let mut slow = new()?;
+        for &(address, value) in ram {
+            slow.ram_mut()[address] = value;
+        }
+        let mut fast = new()?;
+        fast.ram_mut().copy_from_slice(slow.ram());
+
+        while slow.cycles() < cycles {
+            let chunk = rng.next() % 100;
+            slow.run(chunk)?;
+            fast.run_fast(chunk)?;
+
+            assert_eq!(
+                (fast.a, fast.d, fast.pc, fast.cycles),
+                (slow.a, slow.d, slow.pc, slow.cycles)
+            );
+            assert!(fast.ram == slow.ram, "RAM differs at cycle {}", slow.cycles);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn programs() -> Result<()> {
+        let mut rng = Rng(1);
+        let mult = Program::from_asm(include_str!("../../../../04/mult/Mult.asm"))?;
+        differential(&mult.words, &[(0, 123), (1, 45)], &mut rng, 10_000)?;
+
+        let fill = Program::from_asm(include_str!("../../../../04/fill/Fill.asm"))?;
+        differential(&fill.words, &[(0x6000, 1)], &mut rng, 300_000)?;
+        Ok(())
+    }
+
+    #[test]
+    fn random() -> Result<()> {
+        let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+
+        for _ in 0..50 {
+            // A-instructions mostly load small addresses, so that jumps stay
+            // within the program and RAM accesses overlap.
+            let program: Vec<u16> = (0..64)
+                .map(|_| loop {
+                    let word = rng.next() as u16;
+                    if word & 0x8000 == 0 {
+                        break word % 80;
+                    }
+                    if Instr::decode(word).is_ok() {
+                        break word;
+                    }
+                })
+                .collect();
+
+            differential(&program, &[], &mut rng, 5_000)?;
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn devices() -> Result<()> {
+        // Sums random numbers, and the timer, which depend on exactly when
+        // they're read.
+        let source = "\
+            (LOOP)\n\
+            @RANDOM\n\
+            D=M\n\
+            @sum\n\
+            M=D+M\n\
+            @TIMER\n\
+            D=M\n\
+            @sum\n\
+            M=D+M\n\
+            @LOOP\n\
+            0;JMP\n";
+        let devices = || -> Vec<Box<dyn Device>> {
+            vec![
+                Box::new(Random::new(RANDOM, 7)),
+                Box::new(Timer::new(TIMER, 3).unwrap()),
+            ]
+        };
+        let program = Program::from_asm_with_devices(source, &devices())?;
+        let new = || Computer::with_devices(&program.words, devices());
+        differential_with(new, &[], &mut Rng(3), 10_000)
+    }
+
+    #[test]
+    fn invalid() -> Result<()> {
+        // `@1`, `@2`, then an invalid instruction.
+        let program = [1, 2, 0x8000];
+        let mut computer = Computer::new(&program)?;
+
+        let err = computer.run_fast(10).unwrap_err();
+        assert!(err.to_string().contains("ROM[2]"), "{err}");
+        assert_eq!((computer.a(), computer.pc(), computer.cycles()), (2, 2, 2));
+        Ok(())
+    }
+
+    #[test]
+    fn rom_changes() -> Result<()> {
+        let mut computer = Computer::new(&[])?;
+        computer.run_fast(3)?;
+        computer.rom_mut()[3] = 42;
+        computer.run_fast(1)?;
+        assert_eq!(computer.a(), 42);
+        Ok(())
+    }
+}
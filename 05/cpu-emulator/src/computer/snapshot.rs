@@ -0,0 +1,259 @@
+//! Snapshots of the computer's full state, saved as text.
+//!
+//! A snapshot holds the registers, the cycle count, the state of any devices
+//! which have some, and all non-zero memory, e.g.
+//!
+//! ```text
+//! hack-snapshot 1
+//! a 16384
+//! d 65535
+//! pc 12
+//! cycles 3087
+//! device 24578 3 0 0 0
+//! rom 0 16384 60040 ...
+//! ram 0 256 ...
+//! ```
+//!
+//! Memory is written 16 words per line, each starting with its address, and
+//! all-zero lines are left out. Devices are identified by the first address
+//! they handle.
+
+use std::{fs, io::Write, path::Path};
+
+use anyhow::{bail, ensure, Context, Result};
+
+use super::{Computer, MEMORY_SIZE};
+
+const HEADER: &str = "hack-snapshot 1";
+
+/// How many words of memory to write on each line.
+const WORDS_PER_LINE: usize = 16;
+
+impl Computer {
+    /// Save a snapshot to a file.
+    pub fn save_snapshot(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let mut text = vec![];
+        self.write_snapshot(&mut text)?;
+        fs::write(path, text).with_context(|| format!("couldn't write file {}", path.display()))
+    }
+
+    /// Restore a snapshot from a file. See `read_snapshot`.
+    pub fn load_snapshot(&mut self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("couldn't read file {}", path.display()))?;
+        self.read_snapshot(&text)
+            .with_context(|| format!("invalid snapshot {}", path.display()))
+    }
+
+    pub fn write_snapshot(&self, mut out: impl Write) -> Result<()> {
+        writeln!(out, "{HEADER}")?;
+        writeln!(out, "a {}", self.a)?;
+        writeln!(out, "d {}", self.d)?;
+        writeln!(out, "pc {}", self.pc)?;
+        writeln!(out, "cycles {}", self.cycles)?;
+
+        for device in &self.devices {
+            let state = device.state();
+            if !state.is_empty() {
+                write!(out, "device {}", device.range().start)?;
+                for word in state {
+                    write!(out, " {word}")?;
+                }
+                writeln!(out)?;
+            }
+        }
+
+        for (name, memory) in [("rom", &self.rom), ("ram", &self.ram)] {
+            for (i, chunk) in memory.chunks(WORDS_PER_LINE).enumerate() {
+                if chunk.iter().all(|&word| word == 0) {
+                    continue;
+                }
+                write!(out, "{name} {}", i * WORDS_PER_LINE)?;
+                for word in chunk {
+                    write!(out, " {word}")?;
+                }
+                writeln!(out)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Restore a snapshot, replacing all memory and registers.
+    ///
+    /// The computer must have the same devices as the one which was saved, so
+    /// that their state can be restored.
+    pub fn read_snapshot(&mut self, text: &str) -> Result<()> {
+        let mut lines = text.lines().enumerate();
+        match lines.next() {
+            Some((_, HEADER)) => {}
+            _ => bail!("expected {HEADER:?} on the first line"),
+        }
+
+        let mut rom = vec![0; MEMORY_SIZE].into_boxed_slice();
+        let mut ram = vec![0; MEMORY_SIZE].into_boxed_slice();
+        let mut registers = [None; 4];
+        let mut device_states = vec![vec![]; self.devices.len()];
+
+        for (i, line) in lines {
+            let mut parse = || -> Result<()> {
+                let mut words = line.split_whitespace();
+                let Some(key) = words.next() else {
+                    return Ok(());
+                };
+                let values: Vec<u64> = words
+                    .map(|word| {
+                        word.parse()
+                            .with_context(|| format!("invalid number {word:?}"))
+                    })
+                    .collect::<Result<_>>()?;
+                let word = |value: u64| u16::try_from(value).context("value out of range");
+
+                match (key, &values[..]) {
+                    ("a", &[value]) => registers[0] = Some(u64::from(word(value)?)),
+                    ("d", &[value]) => registers[1] = Some(u64::from(word(value)?)),
+                    ("pc", &[value]) => registers[2] = Some(u64::from(word(value)?)),
+                    ("cycles", &[value]) => registers[3] = Some(value),
+                    ("device", [start, state @ ..]) => {
+                        let start = word(*start)?;
+                        let index = self
+                            .devices
+                            .iter()
+                            .position(|device| device.range().start == start)
+                            .with_context(|| format!("no device at {start}"))?;
+                        device_states[index] =
+                            state.iter().map(|&v| word(v)).collect::<Result<_>>()?;
+                    }
+                    ("rom" | "ram", [start, words @ ..]) => {
+                        let memory = if key == "rom" { &mut rom } else { &mut ram };
+                        let start = *start as usize;
+                        ensure!(
+                            start
+                                .checked_add(words.len())
+                                .is_some_and(|end| end <= MEMORY_SIZE),
+                            "{key} address out of range"
+                        );
+                        for (slot, &value) in memory[start..].iter_mut().zip(words) {
+                            *slot = word(value)?;
+                        }
+                    }
+                    _ => bail!("unexpected {key:?}"),
+                }
+                Ok(())
+            };
+            parse().with_context(|| format!("line {}", i + 1))?;
+        }
+
+        let [Some(a), Some(d), Some(pc), Some(cycles)] = registers else {
+            bail!("missing a register or the cycle count");
+        };
+        // If a device rejects its state, put back the ones already restored,
+        // so that a failed load leaves the computer as it was.
+        let old_states: Vec<_> = self.devices.iter().map(|device| device.state()).collect();
+        for (i, state) in device_states.iter().enumerate() {
+            let start = self.devices[i].range().start;
+            if let Err(e) = self.devices[i].set_state(state) {
+                // Put back the ones already restored.
+                for (device, old_state) in self.devices[..i].iter_mut().zip(&old_states) {
+                    device.set_state(old_state)?;
+                }
+                return Err(e.context(format!("couldn't restore device at {start}")));
+            }
+        }
+
+        self.rom = rom;
+        self.ram = ram;
+        self.a = a as u16;
+        self.d = d as u16;
+        self.set_pc(pc as u16);
+        self.cycles = cycles;
+        self.decoded = None;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        device::{Device, Random, Timer},
+        program::Program,
+        RANDOM, TIMER,
+    };
+
+    #[test]
+    fn round_trip() -> Result<()> {
+        let program = Program::from_asm(include_str!("../../../../04/fill/Fill.asm"))?;
+        let mut computer = Computer::new(&program.words)?;
+        computer.ram_mut()[usize::from(crate::KBD)] = 1;
+        computer.run(5000)?;
+
+        let mut snapshot = vec![];
+        computer.write_snapshot(&mut snapshot)?;
+        let text = String::from_utf8(snapshot)?;
+        assert!(text.starts_with("hack-snapshot 1\n"));
+
+        let mut restored = Computer::new(&[])?;
+        restored.read_snapshot(&text)?;
+        assert_eq!(restored.rom(), computer.rom());
+        assert_eq!(restored.ram(), computer.ram());
+        assert_eq!(
+            (restored.a(), restored.d(), restored.pc(), restored.cycles()),
+            (computer.a(), computer.d(), computer.pc(), computer.cycles())
+        );
+
+        // Both carry on in the same way.
+        computer.run(1000)?;
+        restored.run_fast(1000)?;
+        assert_eq!(restored.ram(), computer.ram());
+        Ok(())
+    }
+
+    #[test]
+    fn device_state() -> Result<()> {
+        let program = Program::from_asm_with_devices(
+            "@RANDOM\nD=M\n@RANDOM\nD=M\n",
+            &[Box::new(Random::new(RANDOM, 7))],
+        )?;
+        let mut computer =
+            Computer::with_devices(&program.words, vec![Box::new(Random::new(RANDOM, 7))])?;
+        computer.run(2)?;
+
+        let mut snapshot = vec![];
+        computer.write_snapshot(&mut snapshot)?;
+        let text = String::from_utf8(snapshot)?;
+        assert!(text.contains(&format!("device {RANDOM} ")), "{text}");
+
+        // A different seed is replaced by the saved state.
+        let mut restored = Computer::with_devices(&[], vec![Box::new(Random::new(RANDOM, 99))])?;
+        restored.read_snapshot(&text)?;
+        computer.run(2)?;
+        restored.run(2)?;
+        assert_eq!(restored.d(), computer.d());
+
+        // Without the device, its state can't be restored.
+        let err = Computer::new(&[])?.read_snapshot(&text).unwrap_err();
+        assert!(format!("{err:#}").contains("no device"), "{err:#}");
+
+        // With an extra device, which has no saved state, nothing is restored.
+        let devices = || -> Vec<Box<dyn Device>> {
+            vec![
+                Box::new(Random::new(RANDOM, 99)),
+                Box::new(Timer::new(TIMER, 10).unwrap()),
+            ]
+        };
+        let mut partial = Computer::with_devices(&program.words, devices())?;
+        let err = partial.read_snapshot(&text).unwrap_err();
+        assert!(
+            format!("{err:#}").contains(&format!("couldn't restore device at {TIMER}")),
+            "{err:#}"
+        );
+        let mut fresh = Computer::with_devices(&program.words, devices())?;
+        partial.run(2)?;
+        fresh.run(2)?;
+        assert_eq!(partial.d(), fresh.d());
+        Ok(())
+    }
+}
@@ -0,0 +1,129 @@
+//! Which lines of assembly source were executed, in lcov format.
+
+use std::{
+    collections::BTreeMap,
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+
+use crate::program::Program;
+
+/// Execution counts per line of assembly source, possibly gathered from
+/// several runs of several programs.
+#[derive(Debug, Default)]
+pub struct Coverage {
+    /// Execution counts per source file, by line number. Every line that
+    /// produced an instruction is present, even if it never executed.
+    files: BTreeMap<PathBuf, BTreeMap<usize, u64>>,
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the execution counts from a run of the program loaded from `path`.
+    /// `hits` is indexed by ROM address.
+    ///
+    /// For a `.hack` file, the `.asm` file next to it is used as the source,
+    /// as long as it assembles to the same machine code.
+    pub fn add(&mut self, path: &Path, program: &Program, hits: &[u64]) -> Result<()> {
+        let assembled;
+        let (source_path, source_map) = match &program.source_map {
+            Some(source_map) => (path.to_owned(), source_map),
+            None => {
+                let asm_path = path.with_extension("asm");
+                if !asm_path.exists() {
+                    bail!(
+                        "can't record coverage for {}: no assembly source found",
+                        path.display()
+                    );
+                }
+                assembled = Program::from_file(&asm_path)?;
+                if assembled.words != program.words {
+                    bail!(
+                        "can't record coverage for {}: {} doesn't match it",
+                        path.display(),
+                        asm_path.display()
+                    );
+                }
+                (asm_path, assembled.source_map.as_ref().unwrap())
+            }
+        };
+
+        let source_path = fs::canonicalize(&source_path).unwrap_or(source_path);
+        let lines = self.files.entry(source_path).or_default();
+        for address in 0..program.words.len() {
+            let Some(line) = source_map.get(address as u16) else {
+                break;
+            };
+            let hits = hits.get(address).copied().unwrap_or(0);
+            *lines.entry(line.number).or_default() += hits;
+        }
+
+        Ok(())
+    }
+
+    pub fn write_lcov(&self, mut out: impl Write) -> Result<()> {
+        for (path, lines) in &self.files {
+            writeln!(out, "TN:")?;
+            writeln!(out, "SF:{}", path.display())?;
+            for (number, hits) in lines {
+                writeln!(out, "DA:{number},{hits}")?;
+            }
+            let hit = lines.values().filter(|&&hits| hits != 0).count();
+            writeln!(out, "LF:{}", lines.len())?;
+            writeln!(out, "LH:{hit}")?;
+            writeln!(out, "end_of_record")?;
+        }
+
+        Ok(())
+    }
+
+    /// Write an lcov `.info` file.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let file = File::create(path)
+            .with_context(|| format!("couldn't create file {}", path.display()))?;
+        let mut out = BufWriter::new(file);
+        self.write_lcov(&mut out)?;
+        out.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Computer, MEMORY_SIZE};
+
+    #[test]
+    fn mult() -> Result<()> {
+        let program = Program::from_asm(include_str!("../../../04/mult/Mult.asm"))?;
+        let mut computer = Computer::new(&program.words)?;
+        let mut hits = vec![0; MEMORY_SIZE];
+
+        // R0 is 0, so the loop body never runs.
+        while computer.pc() != 14 {
+            hits[usize::from(computer.pc())] += 1;
+            computer.step()?;
+        }
+
+        let mut coverage = Coverage::new();
+        coverage.add(Path::new("Mult.asm"), &program, &hits)?;
+        let mut out = vec![];
+        coverage.write_lcov(&mut out)?;
+        let lcov = String::from_utf8(out)?;
+
+        assert!(lcov.starts_with("TN:\nSF:Mult.asm\n"), "{lcov}");
+        // `@R2`, the first instruction.
+        assert!(lcov.contains("\nDA:18,1\n"), "{lcov}");
+        // `@R1`, in the loop body.
+        assert!(lcov.contains("\nDA:29,0\n"), "{lcov}");
+        assert!(lcov.ends_with("LF:14\nLH:6\nend_of_record\n"), "{lcov}");
+        Ok(())
+    }
+}
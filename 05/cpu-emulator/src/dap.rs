@@ -0,0 +1,754 @@
+//! A Debug Adapter Protocol server, so the emulator can be driven from
+//! editors which speak DAP, over stdio.
+//!
+//! Breakpoints can be set on lines of the `.asm` file, which are mapped to ROM
+//! addresses through the assembler's source map. For programs produced by the
+//! VM translator, they can also be set on lines of the `.vm` files, using the
+//! marker comment before each command's code, e.g.
+//!
+//! ```text
+//! // *** push constant 7 *** SimpleAdd.vm:1
+//! ```
+//!
+//! When there are such markers, the program is shown and stepped through as
+//! VM source. Otherwise, it's shown as assembly.
+//!
+//! Stack frames are reconstructed from `LCL` and `ARG`, assuming the VM's
+//! calling convention, and each frame's variables show the virtual memory
+//! segments.
+
+mod protocol;
+mod session;
+mod source_map;
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    io::{BufReader, Read, Write},
+    path::PathBuf,
+    sync::mpsc::{self, Receiver, TryRecvError},
+    thread,
+};
+
+use anyhow::{anyhow, bail, Context, Result};
+use serde_json::{json, Value};
+
+use self::{
+    protocol::{read_message, write_message},
+    session::{Session, LCL, SCOPES},
+    source_map::VmLocation,
+};
+use crate::Computer;
+
+/// How many instructions to run between checks for requests, such as
+/// `pause`.
+const CHUNK: u64 = 100_000;
+
+/// The only thread.
+const THREAD_ID: u64 = 1;
+
+pub struct DapServer {
+    /// The program to launch if the `launch` request doesn't name one.
+    default_program: Option<PathBuf>,
+
+    session: Option<Session>,
+    stop_on_entry: bool,
+
+    /// Breakpoint addresses, by the path of the source file they were set in.
+    breakpoints_by_source: HashMap<String, Vec<u16>>,
+    breakpoints: BTreeSet<u16>,
+
+    /// The sequence number of the next message we send.
+    seq: u64,
+}
+
+/// How far to run, when resuming execution.
+#[derive(Debug, Clone, Copy)]
+enum Resume {
+    Continue,
+    Instruction,
+
+    /// Until the VM location changes from this one.
+    VmLine(Option<VmLocation>),
+
+    /// Until `LCL` drops below this, i.e. the current function returns.
+    Out(u16),
+}
+
+impl DapServer {
+    pub fn new(default_program: Option<PathBuf>) -> Self {
+        Self {
+            default_program,
+            session: None,
+            stop_on_entry: false,
+            breakpoints_by_source: HashMap::new(),
+            breakpoints: BTreeSet::new(),
+            seq: 1,
+        }
+    }
+
+    pub fn computer(&self) -> Option<&Computer> {
+        self.session.as_ref().map(|session| &session.computer)
+    }
+
+    /// Handle one debugging session, until the client disconnects or closes
+    /// `input`.
+    pub fn serve(&mut self, input: impl Read + Send + 'static, mut out: impl Write) -> Result<()> {
+        // Read messages on another thread, so we can check for them while
+        // the program runs.
+        let (sender, requests) = mpsc::channel();
+        thread::spawn(move || {
+            let mut input = BufReader::new(input);
+            loop {
+                let message = read_message(&mut input).transpose();
+                let done = !matches!(message, Some(Ok(_)));
+                if let Some(message) = message {
+                    if sender.send(message).is_err() {
+                        break;
+                    }
+                }
+                if done {
+                    break;
+                }
+            }
+        });
+
+        while let Ok(request) = requests.recv() {
+            if !self.handle(&request?, &requests, &mut out)? {
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Handle a request, returning false if the client disconnected.
+    fn handle(
+        &mut self,
+        request: &Value,
+        requests: &Receiver<Result<Value>>,
+        out: &mut impl Write,
+    ) -> Result<bool> {
+        let command = request["command"].as_str().unwrap_or_default();
+        let args = &request["arguments"];
+
+        let resume = match command {
+            "continue" => Some(Resume::Continue),
+            "next" | "stepIn" => Some(match self.session.as_ref() {
+                Some(session) if session.vm.is_used() => {
+                    Resume::VmLine(session.vm.location(session.computer.pc()))
+                }
+                _ => Resume::Instruction,
+            }),
+            "stepOut" => self
+                .session
+                .as_ref()
+                .map(|session| Resume::Out(session.computer.ram()[LCL])),
+            "configurationDone" if !self.stop_on_entry => Some(Resume::Continue),
+            _ => None,
+        };
+
+        let result = match command {
+            "disconnect" => {
+                self.respond(out, request, Ok(Value::Null))?;
+                return Ok(false);
+            }
+            "configurationDone" => Ok(Value::Null),
+            "continue" => self
+                .session()
+                .map(|_| json!({ "allThreadsContinued": true })),
+            "next" | "stepIn" | "stepOut" => self.session().map(|_| Value::Null),
+            _ => self.request(command, args),
+        };
+        let launched = command == "launch" && result.is_ok();
+        let ok = result.is_ok();
+        self.respond(out, request, result)?;
+
+        if launched {
+            self.event(out, "initialized", Value::Null)?;
+        }
+        if command == "configurationDone" && self.stop_on_entry {
+            self.stopped(out, "entry", None)?;
+        }
+        match resume {
+            Some(resume) if ok && self.session.is_some() => self.resume(resume, requests, out),
+            _ => Ok(true),
+        }
+    }
+
+    /// Handle a request which doesn't resume execution, returning the
+    /// response body.
+    fn request(&mut self, command: &str, args: &Value) -> Result<Value> {
+        let body = match command {
+            "initialize" => json!({
+                "supportsConfigurationDoneRequest": true,
+            }),
+            "launch" => {
+                let path = match args["program"].as_str() {
+                    Some(path) => PathBuf::from(path),
+                    None => self
+                        .default_program
+                        .clone()
+                        .context("no program to launch")?,
+                };
+                self.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+                self.session = Some(Session::new(path)?);
+                Value::Null
+            }
+            "setBreakpoints" => {
+                let session = self.session()?;
+                let path = args["source"]["path"]
+                    .as_str()
+                    .context("missing source path")?
+                    .to_owned();
+                let lines = args["breakpoints"]
+                    .as_array()
+                    .map_or(&[][..], Vec::as_slice);
+
+                let mut addresses = vec![];
+                let mut breakpoints = vec![];
+                for line in lines {
+                    let line = line["line"].as_u64().context("missing line")? as usize;
+                    match session.breakpoint(&path, line) {
+                        Ok((address, line)) => {
+                            addresses.push(address);
+                            breakpoints.push(json!({ "verified": true, "line": line }));
+                        }
+                        Err(e) => breakpoints.push(json!({
+                            "verified": false,
+                            "line": line,
+                            "message": format!("{e:#}"),
+                        })),
+                    }
+                }
+
+                self.breakpoints_by_source.insert(path, addresses);
+                self.breakpoints = self
+                    .breakpoints_by_source
+                    .values()
+                    .flatten()
+                    .copied()
+                    .collect();
+                json!({ "breakpoints": breakpoints })
+            }
+            "threads" => json!({ "threads": [{ "id": THREAD_ID, "name": "Hack CPU" }] }),
+            "stackTrace" => {
+                let session = self.session()?;
+                let frames = session.frames();
+                let stack_frames: Vec<_> = frames
+                    .iter()
+                    .enumerate()
+                    .map(|(id, frame)| session.describe_frame(id, frame))
+                    .collect();
+                json!({ "stackFrames": stack_frames, "totalFrames": frames.len() })
+            }
+            "scopes" => {
+                let frame_id = args["frameId"].as_u64().context("missing frame ID")?;
+                let scopes: Vec<_> = SCOPES
+                    .iter()
+                    .enumerate()
+                    .map(|(i, name)| {
+                        json!({
+                            "name": name,
+                            "variablesReference": frame_id * SCOPES.len() as u64 + i as u64 + 1,
+                            "expensive": false,
+                        })
+                    })
+                    .collect();
+                json!({ "scopes": scopes })
+            }
+            "variables" => {
+                let session = self.session()?;
+                let reference = args["variablesReference"]
+                    .as_u64()
+                    .filter(|&reference| reference > 0)
+                    .context("missing variables reference")?
+                    - 1;
+                let frame_id = (reference / SCOPES.len() as u64) as usize;
+                let scope = SCOPES[(reference % SCOPES.len() as u64) as usize];
+
+                let frames = session.frames();
+                let frame = frames.get(frame_id).context("no such frame")?;
+                let variables: Vec<_> = session
+                    .variables(frame_id, frame, scope)
+                    .into_iter()
+                    .map(|(name, value)| {
+                        json!({
+                            "name": name,
+                            "value": (value as i16).to_string(),
+                            "variablesReference": 0,
+                        })
+                    })
+                    .collect();
+                json!({ "variables": variables })
+            }
+            "pause" => Value::Null,
+            _ => bail!("unsupported request {command:?}"),
+        };
+
+        Ok(body)
+    }
+
+    /// Run until a breakpoint, the end of a step, a `pause` request, or the
+    /// program halts, which ends the session. Other requests are handled while
+    /// running, except those which would replace the session.
+    ///
+    /// Returns false if the client disconnected.
+    fn resume(
+        &mut self,
+        resume: Resume,
+        requests: &Receiver<Result<Value>>,
+        out: &mut impl Write,
+    ) -> Result<bool> {
+        let mut first = true;
+
+        loop {
+            let session = self.session.as_mut().expect("launched");
+            for _ in 0..CHUNK {
+                let pc = session.computer.pc();
+                if !first {
+                    if self.breakpoints.contains(&pc) {
+                        self.stopped(out, "breakpoint", None)?;
+                        return Ok(true);
+                    }
+                    if session.done(resume) {
+                        self.stopped(out, "step", None)?;
+                        return Ok(true);
+                    }
+                }
+                first = false;
+
+                // Rather than loop forever, treat halting as exiting.
+                if session.computer.is_halted() {
+                    self.session = None;
+                    self.event(out, "exited", json!({ "exitCode": 0 }))?;
+                    self.event(out, "terminated", Value::Null)?;
+                    return Ok(true);
+                }
+                if let Err(e) = session.computer.step() {
+                    self.stopped(out, "exception", Some(format!("{e:#}")))?;
+                    return Ok(true);
+                }
+            }
+
+            loop {
+                let request = match requests.try_recv() {
+                    Ok(request) => request?,
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => return Ok(false),
+                };
+                match request["command"].as_str().unwrap_or_default() {
+                    "pause" => {
+                        self.respond(out, &request, Ok(Value::Null))?;
+                        self.stopped(out, "pause", None)?;
+                        return Ok(true);
+                    }
+                    // Already running.
+                    "configurationDone" | "continue" | "next" | "stepIn" | "stepOut" => {
+                        self.respond(out, &request, Ok(Value::Null))?;
+                    }
+                    "launch" => {
+                        let err = anyhow!("can't launch a program while one is running");
+                        self.respond(out, &request, Err(err))?;
+                    }
+                    _ => {
+                        if !self.handle(&request, requests, out)? {
+                            return Ok(false);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn session(&self) -> Result<&Session> {
+        self.session.as_ref().context("no program launched")
+    }
+
+    fn respond(
+        &mut self,
+        out: &mut impl Write,
+        request: &Value,
+        result: Result<Value>,
+    ) -> Result<()> {
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": result.is_ok(),
+        });
+        match result {
+            Ok(Value::Null) => {}
+            Ok(body) => response["body"] = body,
+            Err(e) => response["message"] = format!("{e:#}").into(),
+        }
+        self.send(out, response)
+    }
+
+    fn stopped(&mut self, out: &mut impl Write, reason: &str, text: Option<String>) -> Result<()> {
+        let mut body = json!({
+            "reason": reason,
+            "threadId": THREAD_ID,
+            "allThreadsStopped": true,
+        });
+        if let Some(text) = text {
+            body["text"] = text.into();
+        }
+        self.event(out, "stopped", body)
+    }
+
+    fn event(&mut self, out: &mut impl Write, event: &str, body: Value) -> Result<()> {
+        let mut message = json!({ "type": "event", "event": event });
+        if !body.is_null() {
+            message["body"] = body;
+        }
+        self.send(out, message)
+    }
+
+    fn send(&mut self, out: &mut impl Write, mut message: Value) -> Result<()> {
+        message["seq"] = self.seq.into();
+        self.seq += 1;
+        write_message(out, &message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        net::{TcpListener, TcpStream},
+    };
+
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    /// Assembly as the VM translator would produce it, for a `Main.main`
+    /// which calls `Main.double 7`.
+    const MAIN_ASM: &str = "\
+@256
+D=A
+@SP
+M=D
+@LCL
+M=D
+@ARG
+M=D
+(Main.main)
+// *** push constant 7 *** Main.vm:2
+@7
+D=A
+@SP
+A=M
+M=D
+@SP
+M=M+1
+// *** call Main.double 1 *** Main.vm:3
+@Main.main$ret.0
+D=A
+@SP
+A=M
+M=D
+@SP
+M=M+1
+@LCL
+D=M
+@SP
+A=M
+M=D
+@SP
+M=M+1
+@ARG
+D=M
+@SP
+A=M
+M=D
+@SP
+M=M+1
+@THIS
+D=M
+@SP
+A=M
+M=D
+@SP
+M=M+1
+@THAT
+D=M
+@SP
+A=M
+M=D
+@SP
+M=M+1
+@SP
+D=M
+@6
+D=D-A
+@ARG
+M=D
+@SP
+D=M
+@LCL
+M=D
+@Main.double
+0;JMP
+(Main.main$ret.0)
+// *** label END *** Main.vm:4
+(Main.main$END)
+// *** goto END *** Main.vm:5
+@Main.main$END
+0;JMP
+// *** function Main.double 0 *** Main.vm:7
+(Main.double)
+// *** push argument 0 *** Main.vm:8
+@ARG
+A=M
+D=M
+@SP
+A=M
+M=D
+@SP
+M=M+1
+// *** push argument 0 *** Main.vm:9
+@ARG
+A=M
+D=M
+@SP
+A=M
+M=D
+@SP
+M=M+1
+// *** add *** Main.vm:10
+@SP
+AM=M-1
+D=M
+A=A-1
+M=D+M
+(Main.double$HALT)
+@Main.double$HALT
+0;JMP
+";
+
+    /// A scripted client, which sends requests and collects the messages it
+    /// receives.
+    struct Client {
+        stream: BufReader<TcpStream>,
+        seq: u64,
+    }
+
+    impl Client {
+        /// Start a server, with `source` saved to a fresh directory as
+        /// `file_name`, and launch it.
+        fn launch(name: &str, file_name: &str, source: &str) -> Result<(Self, TempDir)> {
+            let dir = TempDir::new(&format!("dap-{name}"))?;
+            let path = dir.join(file_name);
+            fs::write(&path, source)?;
+
+            let listener = TcpListener::bind("127.0.0.1:0")?;
+            let address = listener.local_addr()?;
+            thread::spawn(move || -> Result<()> {
+                let (stream, _) = listener.accept()?;
+                stream.set_nodelay(true)?;
+                let mut server = DapServer::new(None);
+                server.serve(stream.try_clone()?, stream)
+            });
+
+            let stream = TcpStream::connect(address)?;
+            stream.set_nodelay(true)?;
+            let mut client = Self {
+                stream: BufReader::new(stream),
+                seq: 1,
+            };
+            client.request("initialize", json!({ "adapterID": "hack" }))?;
+            let args = json!({ "program": path, "stopOnEntry": true });
+            client.request("launch", args)?;
+            client.event("initialized")?;
+            Ok((client, dir))
+        }
+
+        fn send(&mut self, command: &str, arguments: Value) -> Result<()> {
+            let request = json!({
+                "seq": self.seq,
+                "type": "request",
+                "command": command,
+                "arguments": arguments,
+            });
+            self.seq += 1;
+            write_message(self.stream.get_mut(), &request)
+        }
+
+        /// Send a request, and return the body of its response.
+        fn request(&mut self, command: &str, arguments: Value) -> Result<Value> {
+            self.send(command, arguments)?;
+            let response = self.read()?;
+            assert_eq!(response["type"], "response", "{response}");
+            assert_eq!(response["command"], command);
+            assert_eq!(response["success"], true, "{response}");
+            Ok(response["body"].clone())
+        }
+
+        /// Read the next message, which should be this event, and return its
+        /// body.
+        fn event(&mut self, event: &str) -> Result<Value> {
+            let message = self.read()?;
+            assert_eq!(message["event"], event, "{message}");
+            Ok(message["body"].clone())
+        }
+
+        fn read(&mut self) -> Result<Value> {
+            read_message(&mut self.stream)?.context("connection closed")
+        }
+
+        /// Where the innermost frames are, as `(name, line)`.
+        fn stack(&mut self) -> Result<Vec<(String, u64)>> {
+            let body = self.request("stackTrace", json!({ "threadId": THREAD_ID }))?;
+            let frames = body["stackFrames"].as_array().unwrap();
+            Ok(frames
+                .iter()
+                .map(|frame| {
+                    let name = frame["name"].as_str().unwrap().to_owned();
+                    (name, frame["line"].as_u64().unwrap())
+                })
+                .collect())
+        }
+
+        fn variables(&mut self, frame_id: u64, scope: &str) -> Result<Vec<(String, String)>> {
+            let body = self.request("scopes", json!({ "frameId": frame_id }))?;
+            let scope = body["scopes"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|s| s["name"] == scope)
+                .unwrap();
+            let reference = scope["variablesReference"].clone();
+            let body = self.request("variables", json!({ "variablesReference": reference }))?;
+            Ok(body["variables"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|v| {
+                    let name = v["name"].as_str().unwrap().to_owned();
+                    (name, v["value"].as_str().unwrap().to_owned())
+                })
+                .collect())
+        }
+    }
+
+    fn pair(name: &str, value: &str) -> (String, String) {
+        (name.to_owned(), value.to_owned())
+    }
+
+    #[test]
+    fn vm_source() -> Result<()> {
+        let (mut client, dir) = Client::launch("vm", "Main.asm", MAIN_ASM)?;
+
+        // Line 7 has no code of its own, so moves to the next line.
+        let body = client.request(
+            "setBreakpoints",
+            json!({
+                "source": { "path": dir.join("Main.vm") },
+                "breakpoints": [{ "line": 7 }, { "line": 9 }, { "line": 20 }],
+            }),
+        )?;
+        let verified: Vec<_> = body["breakpoints"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|b| {
+                (
+                    b["verified"].as_bool().unwrap(),
+                    b["line"].as_u64().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(verified, [(true, 8), (true, 9), (false, 20)]);
+
+        client.request("configurationDone", Value::Null)?;
+        assert_eq!(client.event("stopped")?["reason"], "entry");
+
+        client.request("continue", json!({ "threadId": THREAD_ID }))?;
+        assert_eq!(client.event("stopped")?["reason"], "breakpoint");
+        let main = ("Main.main".to_owned(), 3);
+        assert_eq!(
+            client.stack()?,
+            [("Main.double".to_owned(), 8), main.clone()]
+        );
+
+        client.request("continue", json!({ "threadId": THREAD_ID }))?;
+        assert_eq!(client.event("stopped")?["reason"], "breakpoint");
+        assert_eq!(
+            client.stack()?,
+            [("Main.double".to_owned(), 9), main.clone()]
+        );
+
+        // The callee's argument, and what it has pushed so far.
+        assert_eq!(client.variables(0, "argument")?, [pair("argument[0]", "7")]);
+        assert_eq!(client.variables(0, "local")?, [pair("local[0]", "7")]);
+        let registers = client.variables(1, "Registers")?;
+        assert!(registers.contains(&pair("LCL", "256")), "{registers:?}");
+
+        client.request("next", json!({ "threadId": THREAD_ID }))?;
+        assert_eq!(client.event("stopped")?["reason"], "step");
+        assert_eq!(client.stack()?, [("Main.double".to_owned(), 10), main]);
+
+        client.request("disconnect", Value::Null)?;
+        Ok(())
+    }
+
+    #[test]
+    fn asm_source() -> Result<()> {
+        let mult = include_str!("../../../04/mult/Mult.asm");
+        let (mut client, dir) = Client::launch("asm", "Mult.asm", mult)?;
+        let path = dir.join("Mult.asm");
+
+        let body = client.request(
+            "setBreakpoints",
+            json!({ "source": { "path": path }, "breakpoints": [{ "line": 22 }] }),
+        )?;
+        assert_eq!(body["breakpoints"][0]["line"], 23);
+
+        client.request("configurationDone", Value::Null)?;
+        client.event("stopped")?;
+        client.request("continue", json!({ "threadId": THREAD_ID }))?;
+        assert_eq!(client.event("stopped")?["reason"], "breakpoint");
+
+        let body = client.request("stackTrace", json!({ "threadId": THREAD_ID }))?;
+        let frames = body["stackFrames"].as_array().unwrap();
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0]["name"], "LOOP");
+        assert_eq!(frames[0]["line"], 23);
+        assert_eq!(frames[0]["source"]["path"], path.display().to_string());
+
+        client.request("next", json!({ "threadId": THREAD_ID }))?;
+        client.event("stopped")?;
+        assert_eq!(client.stack()?, [("LOOP".to_owned(), 24)]);
+
+        // With R0 = 0, this runs off the end of the program, and wraps around
+        // to the start, forever. Run until paused.
+        let args = json!({ "source": { "path": path }, "breakpoints": [] });
+        client.request("setBreakpoints", args)?;
+        client.request("continue", json!({ "threadId": THREAD_ID }))?;
+        client.send("launch", json!({ "program": path }))?;
+        assert_eq!(client.read()?["success"], false);
+        client.request("pause", json!({ "threadId": THREAD_ID }))?;
+        assert_eq!(client.event("stopped")?["reason"], "pause");
+
+        client.request("disconnect", Value::Null)?;
+        Ok(())
+    }
+
+    #[test]
+    fn halt() -> Result<()> {
+        let source = "@5\nD=A\n(END)\n@END\n0;JMP\n";
+        let (mut client, _dir) = Client::launch("halt", "Halt.asm", source)?;
+        client.request("configurationDone", Value::Null)?;
+        assert_eq!(client.event("stopped")?["reason"], "entry");
+
+        // Halting ends the session.
+        client.request("continue", json!({ "threadId": THREAD_ID }))?;
+        assert_eq!(client.event("exited")?["exitCode"], 0);
+        client.event("terminated")?;
+        client.send("continue", json!({ "threadId": THREAD_ID }))?;
+        assert_eq!(client.read()?["success"], false);
+
+        client.request("disconnect", Value::Null)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,38 @@
+//! Message framing: each message is JSON, after a `Content-Length` header.
+
+use std::io::{BufRead, Write};
+
+use anyhow::{Context, Result};
+use serde_json::Value;
+
+/// Read one message, with its `Content-Length` header. Returns `None` at the
+/// end of the input.
+pub(super) fn read_message(input: &mut impl BufRead) -> Result<Option<Value>> {
+    let mut length = None;
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = Some(value.trim().parse::<usize>()?);
+        }
+    }
+
+    let length = length.context("missing Content-Length header")?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    Ok(Some(serde_json::from_slice(&body)?))
+}
+
+pub(super) fn write_message(out: &mut impl Write, message: &Value) -> Result<()> {
+    let body = message.to_string();
+    write!(out, "Content-Length: {}\r\n\r\n{body}", body.len())?;
+    out.flush()?;
+    Ok(())
+}
@@ -0,0 +1,276 @@
+//! A launched program, and what the debugger shows of it: stack frames,
+//! variables, and where breakpoints go.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result};
+use assembler::{SymbolKind, ADDRESS_LIMIT};
+use serde_json::{json, Value};
+
+use super::{source_map::VmSourceMap, Resume};
+use crate::{program::Program, Computer};
+
+/// Stop reconstructing stack frames after this many, in case the stack is
+/// corrupted.
+const MAX_FRAMES: usize = 1000;
+
+/// The most words to show in a segment whose size isn't known.
+const MAX_SEGMENT: u16 = 64;
+
+/// How many words of `this` and `that` to show.
+const POINTER_SEGMENT: u16 = 8;
+
+/// The scopes of each stack frame, in order. Variable references encode the
+/// frame and the index of the scope.
+pub(super) const SCOPES: [&str; 7] = [
+    "Registers",
+    "local",
+    "argument",
+    "this",
+    "that",
+    "temp",
+    "static",
+];
+
+/// A launched program.
+pub(super) struct Session {
+    program: Program,
+    pub(super) computer: Computer,
+    path: PathBuf,
+    pub(super) vm: VmSourceMap,
+}
+
+/// Addresses of the VM's pointers, and of the `temp` segment.
+const SP: usize = 0;
+pub(super) const LCL: usize = 1;
+const ARG: usize = 2;
+const THIS: usize = 3;
+const THAT: usize = 4;
+const TEMP: u16 = 5;
+
+/// The bits of a C-instruction which always jumps.
+const UNCONDITIONAL_JUMP: u16 = 0x8007;
+
+/// A function's stack frame, as laid out by the VM's calling convention.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Frame {
+    pc: u16,
+    lcl: u16,
+    arg: u16,
+    this: u16,
+    that: u16,
+
+    /// The end of the frame's locals and working stack: `SP` for the
+    /// innermost frame, and otherwise the start of the callee's arguments.
+    stack_end: u16,
+}
+
+impl Session {
+    pub(super) fn new(path: PathBuf) -> Result<Self> {
+        let program = Program::from_file(&path)?;
+        let computer = Computer::new(&program.words)?;
+        let vm = match &program.source_map {
+            Some(_) => {
+                let source = std::fs::read_to_string(&path)
+                    .with_context(|| format!("couldn't read file {}", path.display()))?;
+                VmSourceMap::new(&source, &program)
+            }
+            None => VmSourceMap::default(),
+        };
+
+        Ok(Self {
+            program,
+            computer,
+            path,
+            vm,
+        })
+    }
+
+    /// The ROM address for a breakpoint on a line of source, and the line
+    /// it actually ends up on: the next line with any code.
+    pub(super) fn breakpoint(&self, path: &str, line: usize) -> Result<(u16, usize)> {
+        let source_map = self
+            .program
+            .source_map
+            .as_ref()
+            .context("no source available (load a .asm file instead)")?;
+
+        if path.ends_with(".vm") {
+            let file = file_name(Path::new(path));
+            return self
+                .vm
+                .address(file, line)
+                .with_context(|| format!("no code for {file} at or after line {line}"));
+        }
+
+        (0..self.program.words.len() as u16)
+            .filter_map(|address| Some((address, source_map.get(address)?.number)))
+            .filter(|&(_, number)| number >= line)
+            .min_by_key(|&(_, number)| number)
+            .with_context(|| format!("no code at or after line {line}"))
+    }
+
+    /// Whether to stop, having run part of the way for `resume`.
+    pub(super) fn done(&self, resume: Resume) -> bool {
+        match resume {
+            Resume::Continue => false,
+            Resume::Instruction => true,
+            Resume::VmLine(location) => self.vm.location(self.computer.pc()) != location,
+            Resume::Out(lcl) => self.computer.ram()[LCL] < lcl,
+        }
+    }
+
+    /// Reconstruct the stack frames, innermost first.
+    ///
+    /// Each frame's caller is found from the frame saved by `call`, just
+    /// below `LCL`. Following frames stops once they stop looking like they
+    /// were saved by `call`, which is immediately for programs that don't use
+    /// the VM's calling convention.
+    pub(super) fn frames(&self) -> Vec<Frame> {
+        let ram = self.computer.ram();
+        let mut frame = Frame {
+            pc: self.computer.pc(),
+            lcl: ram[LCL],
+            arg: ram[ARG],
+            this: ram[THIS],
+            that: ram[THAT],
+            stack_end: ram[SP],
+        };
+
+        let mut frames = vec![frame];
+        while frames.len() < MAX_FRAMES && (5..ADDRESS_LIMIT).contains(&frame.lcl) {
+            let saved = |offset: u16| ram[usize::from(frame.lcl - offset)];
+            let caller = Frame {
+                pc: saved(5),
+                lcl: saved(4),
+                arg: saved(3),
+                this: saved(2),
+                that: saved(1),
+                stack_end: frame.arg,
+            };
+            if !self.is_return_address(caller.pc) || caller.lcl >= frame.lcl {
+                break;
+            }
+            frames.push(caller);
+            frame = caller;
+        }
+
+        frames
+    }
+
+    /// Whether an address could have been saved by `call`: the code for
+    /// `call` ends with an unconditional jump to the function.
+    fn is_return_address(&self, address: u16) -> bool {
+        let Some(previous) = address.checked_sub(1) else {
+            return false;
+        };
+        self.program
+            .words
+            .get(usize::from(previous))
+            .is_some_and(|&word| word & UNCONDITIONAL_JUMP == UNCONDITIONAL_JUMP)
+    }
+
+    pub(super) fn describe_frame(&self, id: usize, frame: &Frame) -> Value {
+        // Show callers at their `call`, rather than where they'll return to.
+        let pc = if id == 0 { frame.pc } else { frame.pc - 1 };
+        let name = match self.program.enclosing_label(pc) {
+            // Labels within a VM function are named `Function$label`.
+            Some((label, _)) => label.split('$').next().unwrap_or(label).to_owned(),
+            None => format!("ROM[{pc}]"),
+        };
+        let mut description = json!({
+            "id": id,
+            "name": name,
+            "line": 0,
+            "column": 0,
+            "instructionPointerReference": frame.pc.to_string(),
+        });
+
+        let source = match self.vm.location(pc) {
+            Some(location) => {
+                let file = &self.vm.files[location.file];
+                Some((self.path.with_file_name(file), location.line))
+            }
+            None if self.vm.is_used() => None,
+            None => (self.program.source_map.as_ref())
+                .and_then(|source_map| source_map.get(pc))
+                .map(|line| (self.path.clone(), line.number)),
+        };
+        if let Some((path, line)) = source {
+            description["source"] = json!({
+                "name": file_name(&path),
+                "path": path.display().to_string(),
+            });
+            description["line"] = line.into();
+            description["column"] = 1.into();
+        }
+
+        description
+    }
+
+    /// The names and values in one scope of a frame.
+    pub(super) fn variables(
+        &self,
+        frame_id: usize,
+        frame: &Frame,
+        scope: &str,
+    ) -> Vec<(String, u16)> {
+        let ram = self.computer.ram();
+        let segment = |name: &str, start: u16, len: u16| -> Vec<(String, u16)> {
+            (0..len.min(MAX_SEGMENT))
+                .map_while(|i| {
+                    let value = ram.get(usize::from(start.checked_add(i)?))?;
+                    Some((format!("{name}[{i}]"), *value))
+                })
+                .collect()
+        };
+
+        match scope {
+            "Registers" => {
+                let mut registers = vec![];
+                if frame_id == 0 {
+                    registers.push(("A".to_owned(), self.computer.a()));
+                    registers.push(("D".to_owned(), self.computer.d()));
+                    registers.push(("SP".to_owned(), frame.stack_end));
+                }
+                registers.push(("PC".to_owned(), frame.pc));
+                registers.push(("LCL".to_owned(), frame.lcl));
+                registers.push(("ARG".to_owned(), frame.arg));
+                registers.push(("THIS".to_owned(), frame.this));
+                registers.push(("THAT".to_owned(), frame.that));
+                registers
+            }
+            // We don't know how many locals there are, so this includes the
+            // working stack.
+            "local" => segment(
+                "local",
+                frame.lcl,
+                frame.stack_end.saturating_sub(frame.lcl),
+            ),
+            // Arguments are just below the saved frame.
+            "argument" => segment(
+                "argument",
+                frame.arg,
+                frame.lcl.saturating_sub(5).saturating_sub(frame.arg),
+            ),
+            "this" => segment("this", frame.this, POINTER_SEGMENT),
+            "that" => segment("that", frame.that, POINTER_SEGMENT),
+            "temp" => segment("temp", TEMP, 8),
+            "static" => match &self.program.symbol_table {
+                Some(symbol_table) => symbol_table
+                    .symbols(SymbolKind::Variable)
+                    .into_iter()
+                    .map(|(name, address)| (name.to_owned(), ram[usize::from(address)]))
+                    .collect(),
+                None => vec![],
+            },
+            _ => unreachable!("unknown scope {scope}"),
+        }
+    }
+}
+
+fn file_name(path: &Path) -> &str {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or_default()
+}
@@ -0,0 +1,86 @@
+//! Map ROM addresses back to the VM commands which produced them.
+
+use crate::program::Program;
+
+/// A line of VM source.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct VmLocation {
+    /// An index into `VmSourceMap::files`.
+    pub(super) file: usize,
+
+    /// 1-based, like in a text editor.
+    pub(super) line: usize,
+}
+
+/// Maps ROM addresses to the VM commands which produced them, using the
+/// translator's marker comments.
+#[derive(Debug, Default)]
+pub(super) struct VmSourceMap {
+    /// The names of the `.vm` files.
+    pub(super) files: Vec<String>,
+
+    /// Indexed by ROM address.
+    locations: Vec<Option<VmLocation>>,
+}
+
+impl VmSourceMap {
+    /// Find the markers in a program's assembly source.
+    pub(super) fn new(source: &str, program: &Program) -> Self {
+        let mut files: Vec<String> = vec![];
+        let mut markers = vec![];
+        for (i, line) in source.lines().enumerate() {
+            let Some((file, line)) = parse_marker(line) else {
+                continue;
+            };
+            let file = match files.iter().position(|f| f == file) {
+                Some(index) => index,
+                None => {
+                    files.push(file.to_owned());
+                    files.len() - 1
+                }
+            };
+            markers.push((i + 1, VmLocation { file, line }));
+        }
+
+        let source_map = program.source_map.as_ref();
+        let locations = (0..program.words.len() as u16)
+            .map(|address| {
+                let number = source_map?.get(address)?.number;
+                let i = markers.partition_point(|&(marker, _)| marker < number);
+                Some(markers.get(i.checked_sub(1)?)?.1)
+            })
+            .collect();
+
+        Self { files, locations }
+    }
+
+    pub(super) fn is_used(&self) -> bool {
+        !self.files.is_empty()
+    }
+
+    pub(super) fn location(&self, address: u16) -> Option<VmLocation> {
+        *self.locations.get(usize::from(address))?
+    }
+
+    /// The first ROM address for a line of a `.vm` file, or the next line
+    /// with any code, and that line.
+    pub(super) fn address(&self, file: &str, line: usize) -> Option<(u16, usize)> {
+        let file = self.files.iter().position(|f| f == file)?;
+        self.locations
+            .iter()
+            .enumerate()
+            .filter_map(|(address, location)| Some((address as u16, (*location)?)))
+            .filter(|&(_, location)| location.file == file && location.line >= line)
+            .min_by_key(|&(address, location)| (location.line, address))
+            .map(|(address, location)| (address, location.line))
+    }
+}
+
+/// Parse a marker comment, like `// *** push constant 7 *** SimpleAdd.vm:1`,
+/// returning the file name and line.
+fn parse_marker(line: &str) -> Option<(&str, usize)> {
+    let rest = line.trim().strip_prefix("// ***")?;
+    let (_, location) = rest.rsplit_once("***")?;
+    let (file, line) = location.trim().rsplit_once(':')?;
+    Some((file, line.parse().ok()?))
+}
@@ -0,0 +1,444 @@
+//! An interactive debugger, driven by text commands.
+//!
+//! Locations can be given as numbers or as symbols from the program's
+//! symbol table: labels for breakpoints, and variables or predefined symbols
+//! (e.g. `R2` or `SP`) for watchpoints and RAM ranges.
+//!
+//! Execution is recorded in a bounded undo log, so that recent instructions
+//! can be stepped back through, e.g. to find the last write to a RAM address.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    io::Write,
+};
+
+use anyhow::{bail, Context, Result};
+use assembler::{Instr, SymbolKind};
+use itertools::Itertools;
+
+use crate::{
+    history::{self, History, RamWrite},
+    program::Program,
+    Computer, MEMORY_SIZE,
+};
+
+/// How many cycles `continue` runs for, at most, unless told otherwise.
+const DEFAULT_LIMIT: u64 = 10_000_000;
+
+const HELP: &str = "\
+commands:
+  break LOC        stop before executing the instruction at a label or ROM address
+  delete [LOC]     remove a breakpoint, or all of them
+  watch ADDR       stop when a RAM address (e.g. R2 or SP) changes
+  unwatch [ADDR]   remove a watchpoint, or all of them
+  step [N]         execute N instructions (default 1)
+  continue [N]     run until a breakpoint or watchpoint, for at most N cycles
+  back [N]         undo N instructions (default 1)
+  rcontinue [N]    run backwards until a breakpoint or watchpoint
+  last-write ADDR  run backwards to the instruction that last wrote to ADDR
+  regs             show the registers and the next instruction
+  ram ADDR [N]     show N words of RAM (default 1), starting at ADDR
+  set ADDR VALUE   write a value to RAM
+  vars             show all variables
+  info             list breakpoints and watchpoints
+  quit";
+
+pub struct Debugger {
+    program: Program,
+    computer: Computer,
+    breakpoints: BTreeSet<u16>,
+
+    /// Watched RAM addresses, and their values when last checked.
+    watchpoints: BTreeMap<u16, u16>,
+
+    history: History,
+}
+
+/// Why execution stopped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    Breakpoint(u16),
+    Watchpoint {
+        address: u16,
+        old: u16,
+        new: u16,
+    },
+
+    /// Running backwards, found the last write to the requested address.
+    /// The instruction that wrote it is next.
+    LastWrite(RamWrite),
+
+    /// Running backwards, reached the oldest recorded instruction.
+    HistoryStart,
+
+    /// Reached the end of the program, which jumps to itself forever. See
+    /// `Computer::is_halted`.
+    Halted(u16),
+
+    /// Ran for the requested number of cycles.
+    Limit,
+}
+
+impl Debugger {
+    pub fn new(program: Program) -> Result<Self> {
+        Self::with_history(program, history::DEFAULT_CAPACITY)
+    }
+
+    /// Create a debugger which can undo up to `capacity` instructions.
+    pub fn with_history(program: Program, capacity: usize) -> Result<Self> {
+        let computer = Computer::new(&program.words)?;
+        Ok(Self {
+            program,
+            computer,
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeMap::new(),
+            history: History::new(capacity),
+        })
+    }
+
+    pub fn computer(&self) -> &Computer {
+        &self.computer
+    }
+
+    /// Execute one command, writing any output to `out`.
+    ///
+    /// Returns false if the command was `quit`.
+    pub fn execute(&mut self, line: &str, mut out: impl Write) -> Result<bool> {
+        let mut words = line.split_whitespace();
+        let Some(command) = words.next() else {
+            return Ok(true);
+        };
+        let args: Vec<_> = words.collect();
+        let arg = |i: usize| args.get(i).copied();
+
+        match command {
+            "break" | "b" => {
+                let address = self
+                    .program
+                    .rom_address(arg(0).context("missing location")?)?;
+                self.breakpoints.insert(address);
+                writeln!(out, "breakpoint at {}", self.describe_rom(address))?;
+            }
+            "delete" | "d" => match arg(0) {
+                Some(loc) => {
+                    let address = self.program.rom_address(loc)?;
+                    if !self.breakpoints.remove(&address) {
+                        bail!("no breakpoint at {loc}");
+                    }
+                }
+                None => self.breakpoints.clear(),
+            },
+            "watch" | "w" => {
+                let address = self.program.address(arg(0).context("missing address")?)?;
+                let value = self.computer.ram()[usize::from(address)];
+                self.watchpoints.insert(address, value);
+                writeln!(out, "watching {}", self.describe_ram(address))?;
+            }
+            "unwatch" => match arg(0) {
+                Some(loc) => {
+                    let address = self.program.address(loc)?;
+                    if self.watchpoints.remove(&address).is_none() {
+                        bail!("no watchpoint on {loc}");
+                    }
+                }
+                None => self.watchpoints.clear(),
+            },
+            "step" | "s" => {
+                let n = parse_count(arg(0), 1)?;
+                let stop = self.run(n)?;
+                self.report(stop, &mut out)?;
+            }
+            "continue" | "c" => {
+                let n = parse_count(arg(0), DEFAULT_LIMIT)?;
+                let stop = self.run(n)?;
+                self.report(stop, &mut out)?;
+            }
+            "back" => {
+                let n = parse_count(arg(0), 1)?;
+                let stop = self.run_back(n, None);
+                self.report(stop, &mut out)?;
+            }
+            "rcontinue" | "rc" => {
+                let n = parse_count(arg(0), u64::MAX)?;
+                let stop = self.run_back(n, None);
+                self.report(stop, &mut out)?;
+            }
+            "last-write" => {
+                let address = self.program.address(arg(0).context("missing address")?)?;
+                let stop = self.run_back(u64::MAX, Some(address));
+                self.report(stop, &mut out)?;
+            }
+            "regs" | "r" => self.print_registers(&mut out)?,
+            "ram" | "x" => {
+                let start = self.program.address(arg(0).context("missing address")?)?;
+                let n = parse_count(arg(1), 1)?;
+                let end = (usize::from(start) + n as usize).min(MEMORY_SIZE);
+                for address in usize::from(start)..end {
+                    self.print_ram(address as u16, &mut out)?;
+                }
+            }
+            "set" => {
+                let address = self.program.address(arg(0).context("missing address")?)?;
+                let value = arg(1).context("missing value")?;
+                let value: i16 = value
+                    .parse()
+                    .with_context(|| format!("invalid value {value:?}"))?;
+                self.computer.ram_mut()[usize::from(address)] = value as u16;
+                // Undoing instructions from before this would be misleading.
+                self.history.clear();
+                self.print_ram(address, &mut out)?;
+            }
+            "vars" => {
+                let variables = match &self.program.symbol_table {
+                    Some(symbol_table) => symbol_table.symbols(SymbolKind::Variable),
+                    None => vec![],
+                };
+                if variables.is_empty() {
+                    writeln!(out, "no variables")?;
+                }
+                for (_, address) in variables {
+                    self.print_ram(address, &mut out)?;
+                }
+            }
+            "info" | "i" => {
+                for &address in &self.breakpoints {
+                    writeln!(out, "breakpoint at {}", self.describe_rom(address))?;
+                }
+                for &address in self.watchpoints.keys() {
+                    writeln!(out, "watchpoint on {}", self.describe_ram(address))?;
+                }
+            }
+            "help" | "h" => writeln!(out, "{HELP}")?,
+            "quit" | "q" => return Ok(false),
+            _ => bail!("unknown command {command:?} (try `help`)"),
+        }
+
+        Ok(true)
+    }
+
+    /// Run for at most `max_cycles`, stopping early at breakpoints,
+    /// watchpoints, or once the program halts.
+    ///
+    /// The instruction at the current PC is always executed, even if it has a
+    /// breakpoint, so that we can continue from a breakpoint.
+    pub fn run(&mut self, max_cycles: u64) -> Result<Stop> {
+        for i in 0..max_cycles {
+            let pc = self.computer.pc();
+            if i > 0 && self.breakpoints.contains(&pc) {
+                return Ok(Stop::Breakpoint(pc));
+            }
+            if i > 0 && self.computer.is_halted() {
+                return Ok(Stop::Halted(pc));
+            }
+
+            self.history.step(&mut self.computer)?;
+
+            for (&address, old) in &mut self.watchpoints {
+                let new = self.computer.ram()[usize::from(address)];
+                if new != *old {
+                    let stop = Stop::Watchpoint {
+                        address,
+                        old: *old,
+                        new,
+                    };
+                    *old = new;
+                    return Ok(stop);
+                }
+            }
+        }
+
+        Ok(Stop::Limit)
+    }
+
+    /// Undo instructions, for at most `max_cycles`, stopping early at
+    /// breakpoints and watchpoints, or once a write to `target` is undone.
+    pub fn run_back(&mut self, max_cycles: u64, target: Option<u16>) -> Stop {
+        for _ in 0..max_cycles {
+            let Some(write) = self.history.step_back(&mut self.computer) else {
+                return Stop::HistoryStart;
+            };
+
+            if let Some(write) = write {
+                if Some(write.address) == target {
+                    return Stop::LastWrite(write);
+                }
+                if let Some(value) = self.watchpoints.get_mut(&write.address) {
+                    *value = write.old;
+                    if write.old != write.new {
+                        return Stop::Watchpoint {
+                            address: write.address,
+                            old: write.new,
+                            new: write.old,
+                        };
+                    }
+                }
+            }
+
+            let pc = self.computer.pc();
+            if self.breakpoints.contains(&pc) {
+                return Stop::Breakpoint(pc);
+            }
+        }
+
+        Stop::Limit
+    }
+
+    fn report(&self, stop: Stop, out: &mut impl Write) -> Result<()> {
+        match stop {
+            Stop::Breakpoint(address) => {
+                writeln!(out, "breakpoint at {}", self.describe_rom(address))?;
+            }
+            Stop::Watchpoint { address, old, new } => writeln!(
+                out,
+                "watchpoint: {} changed from {} to {}",
+                self.describe_ram(address),
+                old as i16,
+                new as i16
+            )?,
+            Stop::LastWrite(write) => writeln!(
+                out,
+                "last write to {} at cycle {}: changed from {} to {}",
+                self.describe_ram(write.address),
+                self.computer.cycles(),
+                write.old as i16,
+                write.new as i16
+            )?,
+            Stop::Halted(address) => {
+                writeln!(out, "halted at {}", self.describe_rom(address))?;
+            }
+            Stop::HistoryStart => writeln!(
+                out,
+                "reached the start of the recorded history ({} instructions can be undone)",
+                self.history.capacity()
+            )?,
+            Stop::Limit => {}
+        }
+        self.print_registers(out)
+    }
+
+    fn print_registers(&self, out: &mut impl Write) -> Result<()> {
+        let computer = &self.computer;
+        let pc = computer.pc();
+        let instr = Instr::decode(computer.rom()[usize::from(pc)])
+            .map_or_else(|_| "<invalid>".to_owned(), |instr| instr.to_string());
+
+        writeln!(
+            out,
+            "A={} D={} PC={} ({}) cycles={}",
+            computer.a() as i16,
+            computer.d() as i16,
+            pc,
+            self.describe_rom(pc),
+            computer.cycles()
+        )?;
+        writeln!(out, "next: {instr}")?;
+        Ok(())
+    }
+
+    fn print_ram(&self, address: u16, out: &mut impl Write) -> Result<()> {
+        let value = self.computer.ram()[usize::from(address)];
+        writeln!(out, "{} = {}", self.describe_ram(address), value as i16)?;
+        Ok(())
+    }
+
+    fn describe_rom(&self, address: u16) -> String {
+        match self.program.enclosing_label(address) {
+            Some(_) => format!("{address}, {}", self.program.describe_rom(address)),
+            None => address.to_string(),
+        }
+    }
+
+    fn describe_ram(&self, address: u16) -> String {
+        let names = self.program.ram_names(address);
+        if names.is_empty() {
+            format!("RAM[{address}]")
+        } else {
+            format!("RAM[{address}] {}", names.iter().join("/"))
+        }
+    }
+}
+
+fn parse_count(arg: Option<&str>, default: u64) -> Result<u64> {
+    match arg {
+        Some(arg) => arg
+            .parse()
+            .with_context(|| format!("invalid count {arg:?}")),
+        None => Ok(default),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(debugger: &mut Debugger, command: &str) -> Result<String> {
+        let mut out = vec![];
+        debugger.execute(command, &mut out)?;
+        Ok(String::from_utf8(out)?)
+    }
+
+    #[test]
+    fn mult() -> Result<()> {
+        let program = Program::from_asm(include_str!("../../../04/mult/Mult.asm"))?;
+        let mut debugger = Debugger::new(program)?;
+        output(&mut debugger, "set R0 3")?;
+        output(&mut debugger, "set R1 5")?;
+
+        output(&mut debugger, "break END")?;
+        output(&mut debugger, "watch R2")?;
+
+        // The first write to R2 sets it to zero, which it already is.
+        let out = output(&mut debugger, "continue")?;
+        assert!(
+            out.contains("watchpoint: RAM[2] ARG/R2 changed from 0 to 5"),
+            "{out}"
+        );
+        let out = output(&mut debugger, "c")?;
+        assert!(out.contains("from 5 to 10"), "{out}");
+
+        output(&mut debugger, "unwatch R2")?;
+        let out = output(&mut debugger, "c")?;
+        assert!(out.starts_with("breakpoint at 14, END"), "{out}");
+        assert_eq!(output(&mut debugger, "ram R2")?, "RAM[2] ARG/R2 = 15\n");
+        assert_eq!(debugger.computer().cycles(), 42);
+
+        // Continuing from a breakpoint doesn't stop there again immediately.
+        output(&mut debugger, "step")?;
+        assert_eq!(debugger.computer().pc(), 15);
+
+        // Find where R2 was last written: the third iteration's `M=D+M`.
+        output(&mut debugger, "back")?;
+        let out = output(&mut debugger, "last-write R2")?;
+        assert!(
+            out.starts_with("last write to RAM[2] ARG/R2 at cycle 33: changed from 10 to 15"),
+            "{out}"
+        );
+        assert_eq!(debugger.computer().pc(), 9);
+        assert_eq!(debugger.computer().ram()[2], 10);
+
+        let out = output(&mut debugger, "rcontinue")?;
+        assert!(out.starts_with("reached the start"), "{out}");
+        assert_eq!(debugger.computer().cycles(), 0);
+
+        assert!(output(&mut debugger, "break NOWHERE").is_err());
+        assert!(output(&mut debugger, "break R0").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn variables() -> Result<()> {
+        let program = Program::from_asm("@5\nD=A\n@count\nM=D\n@sum\nM=-1\n")?;
+        let mut debugger = Debugger::new(program)?;
+
+        output(&mut debugger, "step 6")?;
+        assert_eq!(
+            output(&mut debugger, "vars")?,
+            "RAM[16] count = 5\nRAM[17] sum = -1\n"
+        );
+        assert_eq!(
+            output(&mut debugger, "x 15 2")?,
+            "RAM[15] R15 = 0\nRAM[16] count = 5\n"
+        );
+        Ok(())
+    }
+}
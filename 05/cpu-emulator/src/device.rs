@@ -0,0 +1,371 @@
+//! Memory-mapped devices.
+//!
+//! Each device claims a range of RAM addresses, and names them with symbols
+//! for the assembler. RAM still stores the last value written to each
+//! address, which is what debuggers show. Devices see the CPU's reads and
+//! writes, and may replace the values read.
+//!
+//! The screen and keyboard are built in, and behave just like RAM. The extra
+//! devices live after the keyboard, in otherwise unused memory.
+
+use std::{io::Write, ops::Range};
+
+use anyhow::{ensure, Result};
+
+use crate::{keyboard::KBD, screen::SCREEN};
+
+/// Default addresses for the extra devices.
+pub const CONSOLE: u16 = KBD + 1;
+pub const TIMER: u16 = KBD + 2;
+pub const RANDOM: u16 = KBD + 3;
+pub const DEBUG: u16 = KBD + 4;
+
+pub trait Device: Send {
+    /// The RAM addresses this device handles.
+    fn range(&self) -> Range<u16>;
+
+    /// Names for the assembler, for addresses in `range`.
+    fn symbols(&self) -> Vec<(String, u16)>;
+
+    /// Whether the device behaves just like RAM, so that accesses needn't go
+    /// through it.
+    fn is_memory(&self) -> bool {
+        false
+    }
+
+    /// The CPU reads `address`, which holds `stored` in RAM. Returns the value
+    /// read.
+    fn read(&mut self, address: u16, stored: u16, cycle: u64) -> u16 {
+        let _ = (address, cycle);
+        stored
+    }
+
+    /// The CPU has written `value` to `address`.
+    fn write(&mut self, address: u16, value: u16, cycle: u64) -> Result<()> {
+        let _ = (address, value, cycle);
+        Ok(())
+    }
+
+    /// Any state kept outside of RAM, for snapshots.
+    fn state(&self) -> Vec<u16> {
+        vec![]
+    }
+
+    /// Restore state saved by `state`.
+    fn set_state(&mut self, state: &[u16]) -> Result<()> {
+        ensure!(state.is_empty(), "unexpected device state");
+        Ok(())
+    }
+}
+
+/// The devices every computer has.
+pub fn builtin() -> Vec<Box<dyn Device>> {
+    vec![Box::new(ScreenDevice), Box::new(KeyboardDevice)]
+}
+
+/// The screen, which is an ordinary block of RAM. See `Screen` for the
+/// layout.
+pub struct ScreenDevice;
+
+impl Device for ScreenDevice {
+    fn range(&self) -> Range<u16> {
+        SCREEN..KBD
+    }
+
+    fn symbols(&self) -> Vec<(String, u16)> {
+        vec![("SCREEN".into(), SCREEN)]
+    }
+
+    fn is_memory(&self) -> bool {
+        true
+    }
+}
+
+/// The keyboard: one word, holding the key currently pressed, or 0. It's set
+/// from outside the CPU, e.g. by a `KeyScript`.
+pub struct KeyboardDevice;
+
+impl Device for KeyboardDevice {
+    fn range(&self) -> Range<u16> {
+        one_word(KBD)
+    }
+
+    fn symbols(&self) -> Vec<(String, u16)> {
+        vec![("KBD".into(), KBD)]
+    }
+
+    fn is_memory(&self) -> bool {
+        true
+    }
+}
+
+/// A text console: each word written is printed as a character, using the
+/// Hack character set, where 128 is a newline.
+pub struct Console {
+    address: u16,
+    out: Box<dyn Write + Send>,
+}
+
+impl Console {
+    pub fn new(address: u16, out: Box<dyn Write + Send>) -> Self {
+        Self { address, out }
+    }
+}
+
+impl Device for Console {
+    fn range(&self) -> Range<u16> {
+        one_word(self.address)
+    }
+
+    fn symbols(&self) -> Vec<(String, u16)> {
+        vec![("CONSOLE".into(), self.address)]
+    }
+
+    fn write(&mut self, _address: u16, value: u16, _cycle: u64) -> Result<()> {
+        let c = match value {
+            128 => '\n',
+            _ => char::from_u32(value.into()).unwrap_or(char::REPLACEMENT_CHARACTER),
+        };
+        write!(self.out, "{c}")?;
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+/// A timer, which counts ticks of `period` cycles since it was last written.
+pub struct Timer {
+    address: u16,
+    period: u64,
+
+    /// The cycle when the timer was last reset.
+    start: u64,
+}
+
+impl Timer {
+    pub fn new(address: u16, period: u64) -> Result<Self> {
+        ensure!(period > 0, "the timer's period must be positive");
+        Ok(Self {
+            address,
+            period,
+            start: 0,
+        })
+    }
+}
+
+impl Device for Timer {
+    fn range(&self) -> Range<u16> {
+        one_word(self.address)
+    }
+
+    fn symbols(&self) -> Vec<(String, u16)> {
+        vec![("TIMER".into(), self.address)]
+    }
+
+    fn read(&mut self, _address: u16, _stored: u16, cycle: u64) -> u16 {
+        (cycle.saturating_sub(self.start) / self.period) as u16
+    }
+
+    fn write(&mut self, _address: u16, _value: u16, cycle: u64) -> Result<()> {
+        self.start = cycle;
+        Ok(())
+    }
+
+    fn state(&self) -> Vec<u16> {
+        to_words(self.start)
+    }
+
+    fn set_state(&mut self, state: &[u16]) -> Result<()> {
+        ensure!(state.len() == 4, "expected the timer's start cycle");
+        self.start = from_words(state);
+        Ok(())
+    }
+}
+
+/// A source of pseudo-random numbers: each read returns the next one.
+/// Writing a non-zero value reseeds it.
+pub struct Random {
+    address: u16,
+
+    /// The state of a xorshift generator. Never 0.
+    state: u32,
+}
+
+impl Random {
+    pub fn new(address: u16, seed: u32) -> Self {
+        Self {
+            address,
+            state: seed.max(1),
+        }
+    }
+}
+
+impl Device for Random {
+    fn range(&self) -> Range<u16> {
+        one_word(self.address)
+    }
+
+    fn symbols(&self) -> Vec<(String, u16)> {
+        vec![("RANDOM".into(), self.address)]
+    }
+
+    fn read(&mut self, _address: u16, _stored: u16, _cycle: u64) -> u16 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        (self.state >> 16) as u16
+    }
+
+    fn write(&mut self, _address: u16, value: u16, _cycle: u64) -> Result<()> {
+        if value != 0 {
+            self.state = value.into();
+        }
+        Ok(())
+    }
+
+    fn state(&self) -> Vec<u16> {
+        to_words(self.state.into())[..2].to_vec()
+    }
+
+    fn set_state(&mut self, state: &[u16]) -> Result<()> {
+        ensure!(state.len() == 2, "expected the generator's state");
+        let state = from_words(state) as u32;
+        ensure!(state != 0, "the generator's state can't be 0");
+        self.state = state;
+        Ok(())
+    }
+}
+
+/// A debug "print" port: each word written is printed as a number, on its
+/// own line, along with the cycle.
+pub struct DebugPort {
+    address: u16,
+    out: Box<dyn Write + Send>,
+}
+
+impl DebugPort {
+    pub fn new(address: u16, out: Box<dyn Write + Send>) -> Self {
+        Self { address, out }
+    }
+}
+
+impl Device for DebugPort {
+    fn range(&self) -> Range<u16> {
+        one_word(self.address)
+    }
+
+    fn symbols(&self) -> Vec<(String, u16)> {
+        vec![("DEBUG".into(), self.address)]
+    }
+
+    fn write(&mut self, _address: u16, value: u16, cycle: u64) -> Result<()> {
+        writeln!(self.out, "debug: {} (cycle {cycle})", value as i16)?;
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+/// The range of a device with one word, at `address`. Empty if there's no
+/// room after it, which `Computer::add_device` rejects.
+fn one_word(address: u16) -> Range<u16> {
+    address..address.checked_add(1).unwrap_or(address)
+}
+
+/// Split a number into 16-bit words, least significant first.
+fn to_words(value: u64) -> Vec<u16> {
+    (0..4).map(|i| (value >> (16 * i)) as u16).collect()
+}
+
+fn from_words(words: &[u16]) -> u64 {
+    words
+        .iter()
+        .rev()
+        .fold(0, |value, &word| value << 16 | u64::from(word))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io,
+        sync::{Arc, Mutex},
+    };
+
+    use super::*;
+    use crate::{program::Program, Computer};
+
+    /// Output which can still be read after it's given to a device.
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Shared {
+        fn text(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    #[test]
+    fn devices() -> Result<()> {
+        let console = Shared::default();
+        let debug = Shared::default();
+        let devices: Vec<Box<dyn Device>> = vec![
+            Box::new(Console::new(CONSOLE, Box::new(console.clone()))),
+            Box::new(Timer::new(TIMER, 2)?),
+            Box::new(Random::new(RANDOM, 1)),
+            Box::new(DebugPort::new(DEBUG, Box::new(debug.clone()))),
+        ];
+
+        // Print "Hi", a newline, two random numbers, and the timer.
+        let source = "\
+            @72\nD=A\n@CONSOLE\nM=D\n\
+            @105\nD=A\n@CONSOLE\nM=D\n\
+            @128\nD=A\n@CONSOLE\nM=D\n\
+            @RANDOM\nD=M\n@DEBUG\nM=D\n\
+            @RANDOM\nD=M\n@DEBUG\nM=D\n\
+            @TIMER\nD=M\n@DEBUG\nM=D\n";
+        let program = Program::from_asm_with_devices(source, &devices)?;
+        assert_eq!(program.symbol("CONSOLE")?, CONSOLE);
+        let mut computer = Computer::with_devices(&program.words, devices)?;
+        computer.run_fast(program.words.len() as u64)?;
+
+        assert_eq!(console.text(), "Hi\n");
+        let lines: Vec<_> = debug.text().lines().map(str::to_owned).collect();
+        assert_eq!(lines.len(), 3);
+        assert_ne!(lines[0], lines[1]);
+        // The timer is read on cycle 21, with 2 cycles per tick.
+        assert_eq!(lines[2], "debug: 10 (cycle 23)");
+
+        // RAM holds what was written, not what was read.
+        assert_eq!(computer.ram()[usize::from(RANDOM)], 0);
+        Ok(())
+    }
+
+    #[test]
+    fn overlap() -> Result<()> {
+        let mut computer = Computer::new(&[])?;
+        let err = computer
+            .add_device(Box::new(Timer::new(KBD, 1)?))
+            .unwrap_err();
+        assert!(err.to_string().contains("overlaps"), "{err}");
+
+        // Addresses outside RAM.
+        for address in [0x8000, u16::MAX] {
+            let device = Box::new(Random::new(address, 1));
+            assert!(computer.add_device(device).is_err(), "{address}");
+        }
+        assert!(Timer::new(TIMER, 0).is_err());
+
+        // Device names can't clash with other predefined symbols.
+        let devices: Vec<Box<dyn Device>> = vec![Box::new(ScreenDevice)];
+        assert!(Program::from_asm_with_devices("", &devices).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,523 @@
+//! A stub for GDB's remote serial protocol, so the emulator can be driven
+//! from GDB (or other front ends that speak the protocol) over TCP.
+//!
+//! The target has three 16-bit registers: `a`, `d`, and `pc`. Memory is RAM,
+//! with each word occupying two bytes (little-endian), so RAM address `n` is
+//! at byte address `2 * n`. Breakpoints, like the PC, use ROM addresses.
+//!
+//! GDB can't read our symbols directly, so `write_symbols` produces a GDB
+//! script defining a convenience variable for each label and variable. After
+//! `source Program.gdb`, use e.g. `break *$LOOP` or `x/2xb $counter`.
+
+use std::{
+    collections::BTreeSet,
+    io::{self, ErrorKind, Read, Write},
+    net::TcpStream,
+};
+
+use anyhow::{bail, ensure, Context, Result};
+use assembler::SymbolKind;
+use itertools::Itertools;
+
+use crate::{program::Program, Computer, MEMORY_SIZE};
+
+/// How many instructions to run between checks for an interrupt from GDB.
+const CHUNK: u64 = 100_000;
+
+/// Describes our registers, so that GDB doesn't assume some other
+/// architecture.
+const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.nand2tetris.hack">
+    <reg name="a" bitsize="16" type="int16" regnum="0"/>
+    <reg name="d" bitsize="16" type="int16" regnum="1"/>
+    <reg name="pc" bitsize="16" type="code_ptr" regnum="2"/>
+  </feature>
+</target>
+"#;
+
+/// Signal numbers for stop replies.
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+
+pub struct GdbServer {
+    computer: Computer,
+    breakpoints: BTreeSet<u16>,
+
+    /// The length of the program, to check breakpoints against.
+    program_len: usize,
+
+    /// Whether to acknowledge each packet. GDB can turn this off.
+    ack: bool,
+}
+
+impl GdbServer {
+    pub fn new(program: &Program) -> Result<Self> {
+        Ok(Self {
+            computer: Computer::new(&program.words)?,
+            breakpoints: BTreeSet::new(),
+            program_len: program.words.len(),
+            ack: true,
+        })
+    }
+
+    pub fn computer(&self) -> &Computer {
+        &self.computer
+    }
+
+    /// Handle one debugging session, until GDB detaches, kills the program,
+    /// or disconnects.
+    pub fn serve(&mut self, mut stream: TcpStream) -> Result<()> {
+        self.ack = true;
+
+        while let Some(packet) = self.read_packet(&mut stream)? {
+            let reply = match packet.as_bytes().first() {
+                Some(b'c') => self.resume(&mut stream, u64::MAX)?,
+                Some(b's') => self.resume(&mut stream, 1)?,
+                Some(b'D') => {
+                    send(&mut stream, "OK")?;
+                    return Ok(());
+                }
+                Some(b'k') => return Ok(()),
+                // GDB doesn't show error messages by default, only numbers.
+                _ => self.handle(&packet).unwrap_or_else(|_| "E01".into()),
+            };
+            send(&mut stream, &reply)?;
+        }
+
+        Ok(())
+    }
+
+    /// Handle a packet that doesn't resume execution, returning the reply.
+    fn handle(&mut self, packet: &str) -> Result<String> {
+        let Some(command) = packet.get(..1) else {
+            return Ok(String::new());
+        };
+        let args = &packet[1..];
+
+        let reply = match command {
+            "?" => stop_reply(SIGTRAP),
+            "g" => [self.computer.a(), self.computer.d(), self.computer.pc()]
+                .into_iter()
+                .map(hex_word)
+                .collect(),
+            "G" => {
+                let [a, d, pc] = parse_words(args)?[..] else {
+                    bail!("expected 3 registers");
+                };
+                self.computer.set_a(a);
+                self.computer.set_d(d);
+                self.computer.set_pc(pc);
+                "OK".into()
+            }
+            "p" => {
+                let reg = u8::from_str_radix(args, 16)?;
+                hex_word(self.register(reg)?)
+            }
+            "P" => {
+                let (reg, value) = args.split_once('=').context("expected `=`")?;
+                let reg = u8::from_str_radix(reg, 16)?;
+                let [value] = parse_words(value)?[..] else {
+                    bail!("expected one register value");
+                };
+                match reg {
+                    0 => self.computer.set_a(value),
+                    1 => self.computer.set_d(value),
+                    2 => self.computer.set_pc(value),
+                    _ => bail!("no register {reg}"),
+                }
+                "OK".into()
+            }
+            "m" => {
+                let (start, len) = parse_range(args)?;
+                let ram = self.computer.ram();
+                (start..start + len)
+                    .map(|address| ram[address / 2].to_le_bytes()[address % 2])
+                    .map(|byte| format!("{byte:02x}"))
+                    .collect()
+            }
+            "M" => {
+                let (range, data) = args.split_once(':').context("expected `:`")?;
+                let (start, len) = parse_range(range)?;
+                let data = parse_bytes(data)?;
+                if data.len() != len {
+                    bail!("expected {len} bytes");
+                }
+                for (i, byte) in data.into_iter().enumerate() {
+                    let address = start + i;
+                    let word = &mut self.computer.ram_mut()[address / 2];
+                    let shift = 8 * (address % 2);
+                    *word = (*word & !(0xff << shift)) | (u16::from(byte) << shift);
+                }
+                "OK".into()
+            }
+            "Z" | "z" => {
+                let mut parts = args.split(',');
+                let kind = parts.next().context("expected breakpoint type")?;
+                let address = parts.next().context("expected address")?;
+                // Only software and hardware breakpoints, which are the same
+                // to us.
+                if kind != "0" && kind != "1" {
+                    return Ok(String::new());
+                }
+                let address = u16::from_str_radix(address, 16)?;
+                ensure!(
+                    usize::from(address) < self.program_len,
+                    "no instruction at {address}"
+                );
+                if command == "Z" {
+                    self.breakpoints.insert(address);
+                } else {
+                    self.breakpoints.remove(&address);
+                }
+                "OK".into()
+            }
+            "H" => "OK".into(),
+            "q" if args.starts_with("Supported") => {
+                "PacketSize=4000;qXfer:features:read+;QStartNoAckMode+".into()
+            }
+            "q" if args == "Attached" => "1".into(),
+            "q" if args == "C" => "QC1".into(),
+            "q" if args == "fThreadInfo" => "m1".into(),
+            "q" if args == "sThreadInfo" => "l".into(),
+            "q" if args.starts_with("Xfer:features:read:target.xml:") => {
+                let range = &args["Xfer:features:read:target.xml:".len()..];
+                let (offset, len) = parse_range(range)?;
+                let rest = TARGET_XML.get(offset..).unwrap_or_default();
+                if rest.len() <= len {
+                    format!("l{rest}")
+                } else {
+                    format!("m{}", &rest[..len])
+                }
+            }
+            "Q" if args == "StartNoAckMode" => {
+                self.ack = false;
+                "OK".into()
+            }
+            // An empty reply means "unsupported".
+            _ => String::new(),
+        };
+
+        Ok(reply)
+    }
+
+    /// Run for at most `max_cycles`, stopping at breakpoints, when the
+    /// program halts, or when GDB sends an interrupt. Returns the stop reply.
+    ///
+    /// A halted program stops straight away, rather than looping forever.
+    fn resume(&mut self, stream: &mut TcpStream, max_cycles: u64) -> Result<String> {
+        let mut remaining = max_cycles;
+        let mut first = true;
+
+        while remaining > 0 {
+            for _ in 0..remaining.min(CHUNK) {
+                let pc = self.computer.pc();
+                if (!first && self.breakpoints.contains(&pc)) || self.computer.is_halted() {
+                    return Ok(stop_reply(SIGTRAP));
+                }
+                first = false;
+
+                if self.computer.step().is_err() {
+                    return Ok(stop_reply(SIGILL));
+                }
+                remaining -= 1;
+            }
+
+            if interrupted(stream)? {
+                return Ok(stop_reply(SIGINT));
+            }
+        }
+
+        Ok(stop_reply(SIGTRAP))
+    }
+
+    fn register(&self, reg: u8) -> Result<u16> {
+        match reg {
+            0 => Ok(self.computer.a()),
+            1 => Ok(self.computer.d()),
+            2 => Ok(self.computer.pc()),
+            _ => bail!("no register {reg}"),
+        }
+    }
+
+    /// Read the next packet, acknowledging it. Returns `None` if the
+    /// connection was closed.
+    fn read_packet(&self, stream: &mut TcpStream) -> Result<Option<String>> {
+        let mut byte = [0];
+
+        loop {
+            // Skip acknowledgements, and interrupts which arrive too late.
+            loop {
+                if stream.read(&mut byte)? == 0 {
+                    return Ok(None);
+                }
+                if byte[0] == b'$' {
+                    break;
+                }
+            }
+
+            let mut data = vec![];
+            loop {
+                if stream.read(&mut byte)? == 0 {
+                    return Ok(None);
+                }
+                if byte[0] == b'#' {
+                    break;
+                }
+                data.push(byte[0]);
+            }
+            let mut checksum = [0; 2];
+            stream.read_exact(&mut checksum)?;
+
+            let expected = u8::from_str_radix(std::str::from_utf8(&checksum)?, 16).ok();
+            if expected == Some(checksum_of(&data)) {
+                if self.ack {
+                    stream.write_all(b"+")?;
+                }
+                return Ok(Some(String::from_utf8(data)?));
+            }
+            if self.ack {
+                stream.write_all(b"-")?;
+            }
+        }
+    }
+}
+
+/// Check, without blocking, whether GDB has sent an interrupt (Ctrl-C).
+fn interrupted(stream: &mut TcpStream) -> Result<bool> {
+    stream.set_nonblocking(true)?;
+    let mut byte = [0];
+    let result = stream.read(&mut byte);
+    stream.set_nonblocking(false)?;
+
+    match result {
+        Ok(1) => Ok(byte[0] == 0x03),
+        Ok(_) => Ok(false),
+        Err(e) if e.kind() == ErrorKind::WouldBlock => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn send(stream: &mut impl Write, data: &str) -> io::Result<()> {
+    let checksum = checksum_of(data.as_bytes());
+    write!(stream, "${data}#{checksum:02x}")?;
+    stream.flush()
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, &b| sum.wrapping_add(b))
+}
+
+fn stop_reply(signal: u8) -> String {
+    format!("S{signal:02x}")
+}
+
+/// A 16-bit value as GDB expects it: little-endian hex bytes.
+fn hex_word(value: u16) -> String {
+    value
+        .to_le_bytes()
+        .iter()
+        .map(|b| format!("{b:02x}"))
+        .collect()
+}
+
+fn parse_bytes(hex: &str) -> Result<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        bail!("odd number of hex digits");
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| Ok(u8::from_str_radix(&hex[i..i + 2], 16)?))
+        .collect()
+}
+
+fn parse_words(hex: &str) -> Result<Vec<u16>> {
+    let bytes = parse_bytes(hex)?;
+    if !bytes.len().is_multiple_of(2) {
+        bail!("expected whole 16-bit words");
+    }
+    Ok(bytes
+        .chunks(2)
+        .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+        .collect())
+}
+
+/// Parse `start,len`, checking that it's within RAM (in bytes).
+fn parse_range(text: &str) -> Result<(usize, usize)> {
+    let (start, len) = text.split_once(',').context("expected `,`")?;
+    let start = usize::from_str_radix(start, 16)?;
+    let len = usize::from_str_radix(len, 16)?;
+    ensure!(
+        start
+            .checked_add(len)
+            .is_some_and(|end| end <= 2 * MEMORY_SIZE),
+        "out of range"
+    );
+    Ok((start, len))
+}
+
+/// Write a GDB script which defines a convenience variable for each label
+/// (as a ROM address) and each variable (as a byte address in RAM).
+pub fn write_symbols(program: &Program, mut out: impl Write) -> Result<()> {
+    let Some(symbol_table) = &program.symbol_table else {
+        bail!("no symbols available (load a .asm file instead)");
+    };
+
+    // GDB variables can't contain `.` or `$`, which are common in labels
+    // generated by the VM translator.
+    let name = |symbol: &str| symbol.replace(['.', '$'], "_");
+
+    writeln!(out, "# Labels, as ROM addresses.")?;
+    for (label, address) in symbol_table.symbols(SymbolKind::Label) {
+        writeln!(out, "set ${} = {address}", name(label))?;
+    }
+    writeln!(out, "# Variables, as RAM byte addresses.")?;
+    for (variable, address) in symbol_table
+        .symbols(SymbolKind::Variable)
+        .into_iter()
+        .chain(symbol_table.symbols(SymbolKind::Predefined))
+        .sorted_by_key(|&(_, address)| address)
+    {
+        writeln!(out, "set ${} = {}", name(variable), 2 * u32::from(address))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::TcpListener,
+        thread::{self, JoinHandle},
+    };
+
+    use super::*;
+
+    /// A scripted client, which sends packets and returns the replies.
+    struct Client {
+        stream: TcpStream,
+    }
+
+    impl Client {
+        fn request(&mut self, packet: &str) -> Result<String> {
+            send(&mut self.stream, packet)?;
+
+            let mut byte = [0];
+            self.stream.read_exact(&mut byte)?;
+            assert_eq!(byte[0], b'+', "packet should be acknowledged");
+
+            self.stream.read_exact(&mut byte)?;
+            assert_eq!(byte[0], b'$');
+            let mut reply = vec![];
+            loop {
+                self.stream.read_exact(&mut byte)?;
+                if byte[0] == b'#' {
+                    break;
+                }
+                reply.push(byte[0]);
+            }
+            let mut checksum = [0; 2];
+            self.stream.read_exact(&mut checksum)?;
+            self.stream.write_all(b"+")?;
+
+            Ok(String::from_utf8(reply)?)
+        }
+    }
+
+    /// Serve `source` on another thread, which returns the computer once the
+    /// client detaches.
+    fn connect(source: &str) -> Result<(Client, JoinHandle<Result<Computer>>)> {
+        let program = Program::from_asm(source)?;
+        let mut server = GdbServer::new(&program)?;
+
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let address = listener.local_addr()?;
+        let handle = thread::spawn(move || -> Result<Computer> {
+            let (stream, _) = listener.accept()?;
+            server.serve(stream)?;
+            Ok(server.computer)
+        });
+
+        let client = Client {
+            stream: TcpStream::connect(address)?,
+        };
+        Ok((client, handle))
+    }
+
+    #[test]
+    fn session() -> Result<()> {
+        let (mut client, handle) = connect(include_str!("../../../04/mult/Mult.asm"))?;
+        assert!(client
+            .request("qSupported:xmlRegisters=i386")?
+            .contains("qXfer"));
+        assert!(client
+            .request("qXfer:features:read:target.xml:0,1000")?
+            .starts_with("l<?xml"));
+        assert_eq!(client.request("?")?, "S05");
+
+        // R0 = 3, R1 = 5.
+        assert_eq!(client.request("M0,4:03000500")?, "OK");
+        assert_eq!(client.request("m0,6")?, "030005000000");
+        assert_eq!(client.request("mfffe,4")?, "E01");
+        assert_eq!(client.request("m2,ffffffffffffffff")?, "E01");
+        assert_eq!(client.request("M2,ffffffffffffffff:00")?, "E01");
+
+        // Break at `D;JEQ`, which is at ROM address 5, as R0 counts down.
+        assert_eq!(client.request("Z0,5,2")?, "OK");
+        for r0 in ["0300", "0200", "0100", "0000"] {
+            assert_eq!(client.request("c")?, "S05");
+            assert_eq!(client.request("p2")?, "0500");
+            assert_eq!(client.request("p1")?, r0);
+        }
+        assert_eq!(client.request("m4,2")?, "0f00");
+        assert_eq!(client.request("z0,5,2")?, "OK");
+
+        // Jump to END, at ROM address 14.
+        assert_eq!(client.request("s")?, "S05");
+        assert_eq!(client.request("g")?, "0e0000000e00");
+        assert_eq!(client.request("P1=2a00")?, "OK");
+        assert_eq!(client.request("g")?, "0e002a000e00");
+
+        // Breakpoints must be within the program's 14 instructions.
+        assert_eq!(client.request("Z0,e,2")?, "E01");
+        assert_eq!(client.request("Z0,8000,2")?, "E01");
+        assert_eq!(client.request("z0,ffff,2")?, "E01");
+        assert_eq!(client.request("vMustReplyEmpty")?, "");
+        assert_eq!(client.request("D")?, "OK");
+
+        let computer = handle.join().unwrap()?;
+        assert_eq!(computer.ram()[2], 15);
+        assert_eq!(computer.d(), 42);
+        Ok(())
+    }
+
+    #[test]
+    fn halt() -> Result<()> {
+        let (mut client, handle) = connect("@5\nD=A\n(END)\n@END\n0;JMP\n")?;
+
+        // Continuing stops once the program halts, and straight away after.
+        for _ in 0..2 {
+            assert_eq!(client.request("c")?, "S05");
+            assert_eq!(client.request("g")?, "020005000300");
+        }
+        assert_eq!(client.request("D")?, "OK");
+
+        assert!(handle.join().unwrap()?.is_halted());
+        Ok(())
+    }
+
+    #[test]
+    fn symbols() -> Result<()> {
+        let program = Program::from_asm("(Main.main$LOOP)\n@count\nM=1\n@Main.main$LOOP\n0;JMP\n")?;
+        let mut out = vec![];
+        write_symbols(&program, &mut out)?;
+        let script = String::from_utf8(out)?;
+
+        assert!(script.contains("set $Main_main_LOOP = 0\n"), "{script}");
+        assert!(script.contains("set $count = 32\n"), "{script}");
+        assert!(script.contains("set $SP = 0\n"), "{script}");
+        Ok(())
+    }
+}
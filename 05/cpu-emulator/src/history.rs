@@ -0,0 +1,166 @@
+//! An undo log, for stepping backwards through execution.
+//!
+//! Each instruction can change at most one word of RAM, so undoing it only
+//! needs the old registers and the old value of that word. The log holds a
+//! bounded number of entries, discarding the oldest when it's full.
+
+use std::collections::VecDeque;
+
+use anyhow::Result;
+
+use crate::Computer;
+
+/// The default number of instructions that can be undone.
+pub const DEFAULT_CAPACITY: usize = 1_000_000;
+
+pub struct History {
+    entries: VecDeque<Entry>,
+    capacity: usize,
+}
+
+/// The state needed to undo one instruction.
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    a: u16,
+    d: u16,
+    pc: u16,
+
+    /// The RAM address written, and its old value.
+    write: Option<(u16, u16)>,
+}
+
+/// A RAM write that was undone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RamWrite {
+    pub address: u16,
+    pub old: u16,
+    pub new: u16,
+}
+
+impl History {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: VecDeque::with_capacity(capacity.min(DEFAULT_CAPACITY)),
+            capacity,
+        }
+    }
+
+    /// How many instructions can be undone.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// The most instructions that can be undone.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Execute one instruction, recording how to undo it.
+    pub fn step(&mut self, computer: &mut Computer) -> Result<()> {
+        let write = computer
+            .next_write()
+            .map(|address| (address, computer.ram()[usize::from(address)]));
+        let entry = Entry {
+            a: computer.a(),
+            d: computer.d(),
+            pc: computer.pc(),
+            write,
+        };
+
+        computer.step()?;
+
+        if self.capacity == 0 {
+            return Ok(());
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+        Ok(())
+    }
+
+    /// Undo the most recent instruction, returning `None` if there's nothing
+    /// to undo. Otherwise, returns the RAM write that was undone, if any.
+    pub fn step_back(&mut self, computer: &mut Computer) -> Option<Option<RamWrite>> {
+        let entry = self.entries.pop_back()?;
+
+        let write = entry.write.map(|(address, old)| {
+            let word = &mut computer.ram_mut()[usize::from(address)];
+            let new = *word;
+            *word = old;
+            RamWrite { address, old, new }
+        });
+        computer.set_a(entry.a);
+        computer.set_d(entry.d);
+        computer.set_pc(entry.pc);
+        computer.set_cycles(computer.cycles() - 1);
+
+        Some(write)
+    }
+
+    /// Forget everything, e.g. after the computer's state was changed by
+    /// something other than executing instructions.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::program::Program;
+
+    #[test]
+    fn round_trip() -> Result<()> {
+        let program = Program::from_asm(include_str!("../../../04/mult/Mult.asm"))?;
+        let mut computer = Computer::new(&program.words)?;
+        computer.ram_mut()[0] = 3;
+        computer.ram_mut()[1] = 5;
+        let initial_ram = computer.ram().to_vec();
+
+        let mut history = History::new(100);
+        for _ in 0..42 {
+            history.step(&mut computer)?;
+        }
+        assert_eq!(computer.ram()[2], 15);
+
+        let mut writes = vec![];
+        while let Some(write) = history.step_back(&mut computer) {
+            writes.extend(write);
+        }
+        assert_eq!((computer.pc(), computer.a(), computer.d()), (0, 0, 0));
+        assert_eq!(computer.cycles(), 0);
+        assert_eq!(computer.ram(), initial_ram);
+
+        // Three iterations, each adding to R2 and decrementing R0, after R2 is
+        // zeroed.
+        assert_eq!(writes.len(), 7);
+        assert_eq!(
+            writes[0],
+            RamWrite {
+                address: 0,
+                old: 1,
+                new: 0
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn bounded() -> Result<()> {
+        let mut computer = Computer::new(&[])?;
+        let mut history = History::new(10);
+        for _ in 0..25 {
+            history.step(&mut computer)?;
+        }
+        assert_eq!(history.len(), 10);
+
+        while history.step_back(&mut computer).is_some() {}
+        assert_eq!(computer.cycles(), 15);
+        Ok(())
+    }
+}
@@ -0,0 +1,227 @@
+//! Scripted keyboard input.
+//!
+//! A key script is a list of events, one per line, which are applied in
+//! order:
+//!
+//! ```text
+//! // Hold down 'A' for a while.
+//! at 1000 press A
+//! at 5000 release
+//!
+//! // Press space once the program reaches the LOOP label.
+//! at LOOP press SPACE
+//! at 9000 release
+//! ```
+//!
+//! An event triggers when the cycle count reaches the given number, or when
+//! the program counter reaches the address of the given label. Each event
+//! only becomes active once the previous one has triggered.
+//!
+//! Keys are single characters (optionally quoted, e.g. `' '`), or the names
+//! of the special keys on the Hack keyboard, e.g. `NEWLINE` or `F1`.
+
+use anyhow::{bail, Context, Result};
+
+use crate::{program::Program, Computer};
+
+/// The memory-mapped keyboard register.
+pub const KBD: u16 = 0x6000;
+
+/// The special keys, and their key codes.
+const NAMED_KEYS: [(&str, u16); 14] = [
+    ("SPACE", 32),
+    ("NEWLINE", 128),
+    ("BACKSPACE", 129),
+    ("LEFT", 130),
+    ("UP", 131),
+    ("RIGHT", 132),
+    ("DOWN", 133),
+    ("HOME", 134),
+    ("END", 135),
+    ("PAGEUP", 136),
+    ("PAGEDOWN", 137),
+    ("INSERT", 138),
+    ("DELETE", 139),
+    ("ESC", 140),
+];
+
+/// Function keys `F1` to `F12` have consecutive codes, starting here.
+const F1: u16 = 141;
+
+pub struct KeyScript {
+    events: Vec<Event>,
+
+    /// The index of the next event to trigger.
+    next: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Event {
+    trigger: Trigger,
+
+    /// The key code to write to the keyboard register, or 0 for a release.
+    key: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Trigger {
+    Cycle(u64),
+    Pc(u16),
+}
+
+impl KeyScript {
+    /// Parse a key script. Labels are resolved using the program's symbol
+    /// table.
+    pub fn parse(text: &str, program: &Program) -> Result<Self> {
+        let events = text
+            .lines()
+            .enumerate()
+            .filter_map(|(i, line)| {
+                let line = line.split("//").next().unwrap().trim();
+                (!line.is_empty()).then_some((i + 1, line))
+            })
+            .map(|(line_number, line)| {
+                Event::parse(line, program)
+                    .with_context(|| format!("key script line {line_number}: {line:?}"))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self { events, next: 0 })
+    }
+
+    /// Apply any events which have triggered. Call this before each step.
+    pub fn update(&mut self, computer: &mut Computer) {
+        while let Some(event) = self.events.get(self.next) {
+            let triggered = match event.trigger {
+                Trigger::Cycle(cycle) => computer.cycles() >= cycle,
+                Trigger::Pc(pc) => computer.pc() == pc,
+            };
+            if !triggered {
+                break;
+            }
+
+            computer.ram_mut()[usize::from(KBD)] = event.key;
+            self.next += 1;
+        }
+    }
+
+    /// Have all the events triggered?
+    pub fn is_finished(&self) -> bool {
+        self.next == self.events.len()
+    }
+}
+
+impl Event {
+    fn parse(line: &str, program: &Program) -> Result<Self> {
+        let Some(rest) = line.strip_prefix("at ") else {
+            bail!("expected `at <cycle or label> press <key>` or `at <cycle or label> release`");
+        };
+        let (when, action) = rest
+            .trim_start()
+            .split_once(char::is_whitespace)
+            .context("missing `press` or `release`")?;
+
+        let trigger = match when.parse() {
+            Ok(cycle) => Trigger::Cycle(cycle),
+            Err(_) => Trigger::Pc(program.label(when)?),
+        };
+
+        let action = action.trim();
+        let key = if action == "release" {
+            0
+        } else if let Some(key) = action.strip_prefix("press ") {
+            parse_key(key.trim())?
+        } else {
+            bail!("expected `press <key>` or `release`, got {action:?}");
+        };
+
+        Ok(Self { trigger, key })
+    }
+}
+
+/// The key code for a key, as written in a key script.
+pub(crate) fn parse_key(key: &str) -> Result<u16> {
+    let unquoted = key
+        .strip_prefix('\'')
+        .and_then(|key| key.strip_suffix('\''))
+        .unwrap_or(key);
+
+    let mut chars = unquoted.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        if c == ' ' || c.is_ascii_graphic() {
+            return Ok(c as u16);
+        }
+    }
+
+    if let Some(&(_, code)) = NAMED_KEYS.iter().find(|(name, _)| *name == key) {
+        return Ok(code);
+    }
+    if let Some(n) = key.strip_prefix('F').and_then(|n| n.parse::<u16>().ok()) {
+        if (1..=12).contains(&n) {
+            return Ok(F1 + n - 1);
+        }
+    }
+
+    bail!("unknown key {key:?}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Screen;
+
+    #[test]
+    fn keys() -> Result<()> {
+        for (key, code) in [
+            ("A", 65),
+            ("'a'", 97),
+            ("' '", 32),
+            ("NEWLINE", 128),
+            ("F12", 152),
+        ] {
+            assert_eq!(parse_key(key)?, code, "{key}");
+        }
+        for key in ["", "AB", "F13", "'AB'"] {
+            assert!(parse_key(key).is_err(), "{key}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn fill() -> Result<()> {
+        let program = Program::from_asm(include_str!("../../../04/fill/Fill.asm"))?;
+        let mut computer = Computer::new(&program.words)?;
+
+        // Release the key once the screen has been filled for the first time.
+        let script = "
+            at 0 press x
+            at END release // Back to white.
+            at 300000 press NEWLINE
+        ";
+        let mut keys = KeyScript::parse(script, &program)?;
+
+        let run = |computer: &mut Computer, keys: &mut KeyScript, cycles| -> Result<()> {
+            for _ in 0..cycles {
+                keys.update(computer);
+                computer.step()?;
+            }
+            Ok(())
+        };
+        run(&mut computer, &mut keys, 300_000)?;
+        assert!(Screen::new(computer.ram()).is_all_white());
+        assert_eq!(computer.ram()[usize::from(KBD)], 0);
+        assert!(!keys.is_finished());
+
+        run(&mut computer, &mut keys, 1)?;
+        assert_eq!(computer.ram()[usize::from(KBD)], 128);
+        assert!(keys.is_finished());
+
+        // Only labels can trigger events, not RAM addresses.
+        for when in ["KBD", "R3", "NOPE"] {
+            let script = format!("at {when} press A");
+            assert!(KeyScript::parse(&script, &program).is_err(), "{when}");
+        }
+
+        Ok(())
+    }
+}
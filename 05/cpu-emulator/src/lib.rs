@@ -0,0 +1,44 @@
+//! An emulator for the Hack computer.
+//!
+//! Executes the machine code produced by the assembler.
+
+mod alu;
+mod computer;
+mod coverage;
+mod dap;
+mod debugger;
+mod device;
+mod gdb;
+mod history;
+mod keyboard;
+mod profiler;
+mod program;
+mod script;
+mod screen;
+#[cfg(test)]
+#[path = "../../../06/assembler/src/temp_dir.rs"]
+mod temp_dir;
+mod trace;
+mod tui;
+mod watchdog;
+
+pub use crate::{
+    computer::{Access, Computer, MEMORY_SIZE},
+    coverage::Coverage,
+    dap::DapServer,
+    debugger::{Debugger, Stop},
+    device::{
+        Console, DebugPort, Device, KeyboardDevice, Random, ScreenDevice, Timer, CONSOLE, DEBUG,
+        RANDOM, TIMER,
+    },
+    gdb::{write_symbols as write_gdb_symbols, GdbServer},
+    history::{History, DEFAULT_CAPACITY as DEFAULT_HISTORY},
+    keyboard::{KeyScript, KBD},
+    profiler::Profiler,
+    program::Program,
+    screen::Screen,
+    script::{run_script, run_script_with_coverage},
+    trace::{TraceFilter, TraceFormat, Tracer},
+    tui::Tui,
+    watchdog::Watchdog,
+};
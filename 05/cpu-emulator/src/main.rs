@@ -0,0 +1,554 @@
+//! Command-line interface for the CPU emulator.
+
+use std::{
+    env,
+    ffi::OsStr,
+    fs::{self, File},
+    io::{self, BufRead, BufWriter, Write},
+    net::TcpListener,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, ensure, Context, Result};
+use cpu_emulator::{
+    Computer, Console, Coverage, DapServer, DebugPort, Debugger, Device, GdbServer, KeyScript,
+    Profiler, Program, Random, Timer, TraceFilter, TraceFormat, Tracer, Tui, Watchdog, MEMORY_SIZE,
+};
+
+/// How many cycles each tick of the `timer` device lasts.
+const TIMER_PERIOD: u64 = 1000;
+
+/// The seed for the `random` device, so that runs are repeatable.
+const RANDOM_SEED: u32 = 0x2545_f491;
+
+/// Expects one argument: a `.hack` file, or a `.asm` file (which will be
+/// assembled first, using the extended ISA), or a `.tst` test script.
+///
+/// Programs are run until they halt, by jumping to themselves as in
+/// `(END) @END 0;JMP`, or until they've used up a budget of cycles. Then the
+/// reason they stopped, the CPU registers and the first 16 words of RAM are
+/// printed.
+///
+/// Test scripts are run to completion, producing an output file, which is
+/// compared against the script's compare file.
+///
+/// Options:
+/// * `--cycles N`: the budget: the most instructions to execute. Defaults to
+///   1,000,000. Ignored for test scripts.
+/// * `--run-past-halt`: use up the whole budget, even after the program
+///   halts.
+/// * `--detect-hangs`: also stop if the program gets stuck in a loop which
+///   doesn't write to RAM, reporting "no progress". This checks every cycle,
+///   so it's slower. Not supported with `--keys` or `--device`, since input
+///   might get the program unstuck.
+/// * `--load-snapshot FILE`: start from a snapshot saved by
+///   `--save-snapshot`, rather than from the program's initial state. The
+///   program file still provides the symbols.
+/// * `--save-snapshot FILE`: after running, save the computer's full state.
+/// * `--screen PATH`: save the screen as a `.pbm` or `.png` image after
+///   running.
+/// * `--screen-every N`: instead, save the screen every `N` cycles, as
+///   numbered frames: `PATH` with `-000001`, `-000002`, etc. inserted before
+///   the extension.
+/// * `--device NAME`: attach an extra memory-mapped device, which the
+///   program can refer to by its symbol. May be repeated. One of:
+///   * `console` (`CONSOLE`): prints each word written as a character.
+///   * `timer` (`TIMER`): counts thousands of cycles since it was last
+///     written.
+///   * `random` (`RANDOM`): each read gives a new pseudo-random number, from
+///     a fixed seed. Write to it to reseed.
+///   * `debug` (`DEBUG`): prints each word written as a number, to stderr.
+/// * `--assert`: check the program's `//! assert` comments whenever the PC
+///   reaches them, and stop with an error if one fails. Any other output is
+///   still written.
+/// * `--keys FILE`: press and release keys as described by a key script. See
+///   `KeyScript` for the syntax.
+/// * `--profile FILE`: count the cycles spent under each label, print a
+///   table, and write call stacks to `FILE` in the folded format used by
+///   flame graph tools.
+/// * `--coverage FILE`: write an lcov `.info` file recording which lines of
+///   assembly source were executed. Also works for test scripts, which may
+///   load `.hack` files as long as the matching `.asm` file is next to them.
+/// * `--trace FILE`: write a trace of every executed instruction, as JSON
+///   Lines (`.jsonl`) or VCD (`.vcd`).
+/// * `--trace-filter RANGES`: only trace instructions at these ROM addresses,
+///   e.g. `LOOP` (up to the next label) or `10..END`. Separate several ranges
+///   with commas.
+/// * `--debug`: instead of running the program, start an interactive
+///   debugger, which reads commands from stdin. Type `help` for a list.
+/// * `--gdb PORT`: instead, wait for GDB to connect on `127.0.0.1:PORT`,
+///   and let it control the program using its remote serial protocol.
+/// * `--dap`: instead, speak the Debug Adapter Protocol over stdin and
+///   stdout, for editors. The program file is optional, since the `launch`
+///   request can name one instead.
+/// * `--tui`: instead, run the program in a terminal user interface, which
+///   shows the screen, registers, code and RAM, and passes keys typed to the
+///   keyboard.
+/// * `--watch LIST`: the RAM addresses or symbols for the TUI to show,
+///   separated by commas. Defaults to `R0` to `R15` and any variables.
+/// * `--gdb-symbols FILE`: write a GDB script defining the program's labels
+///   and variables. See `GdbServer` for how addresses are mapped.
+/// * `--history N`: how many instructions the debugger can step back through.
+///   Defaults to 1,000,000.
+fn main() -> Result<()> {
+    let args = Args::parse(env::args().skip(1))?;
+
+    let is_script = Path::new(&args.path).extension() == Some(OsStr::new("tst"));
+    let devices = args
+        .devices
+        .iter()
+        .map(|name| device(name))
+        .collect::<Result<Vec<_>>>()?;
+    ensure!(
+        devices.is_empty()
+            || !(is_script || args.debug || args.gdb.is_some() || args.dap || args.tui),
+        "--device is only supported when running a program, not test scripts or debuggers"
+    );
+
+    if args.dap {
+        let default_program = (!args.path.is_empty()).then(|| PathBuf::from(&args.path));
+        let mut server = DapServer::new(default_program);
+        return server.serve(io::stdin(), io::stdout().lock());
+    }
+
+    if is_script {
+        match &args.coverage {
+            Some(coverage_path) => {
+                let mut coverage = Coverage::new();
+                let result = cpu_emulator::run_script_with_coverage(&args.path, &mut coverage);
+                coverage.save(coverage_path)?;
+                result?;
+            }
+            None => cpu_emulator::run_script(&args.path)?,
+        }
+        println!("End of script - Comparison ended successfully");
+        return Ok(());
+    }
+
+    let program = Program::from_file_with_devices(&args.path, &devices)?;
+    if let Some(path) = &args.gdb_symbols {
+        let file = File::create(path).with_context(|| format!("couldn't create file {path}"))?;
+        let mut out = BufWriter::new(file);
+        cpu_emulator::write_gdb_symbols(&program, &mut out)?;
+        out.flush()?;
+    }
+    if args.debug {
+        return debug(program, args.history);
+    }
+    if args.tui {
+        let watches = match &args.watch {
+            Some(list) => Some(
+                list.split(',')
+                    .map(|text| program.address(text.trim()))
+                    .collect::<Result<_>>()?,
+            ),
+            None => None,
+        };
+        let mut tui = Tui::new(program)?;
+        if let Some(watches) = watches {
+            tui.set_watches(watches);
+        }
+        return tui.run();
+    }
+    if let Some(port) = args.gdb {
+        let listener = TcpListener::bind(("127.0.0.1", port))
+            .with_context(|| format!("couldn't listen on port {port}"))?;
+        println!("waiting for GDB on {}", listener.local_addr()?);
+        let (stream, _) = listener.accept()?;
+
+        let mut server = GdbServer::new(&program)?;
+        server.serve(stream)?;
+        return Ok(());
+    }
+
+    let mut computer = Computer::with_devices(&program.words, devices)?;
+    if let Some(path) = &args.load_snapshot {
+        computer.load_snapshot(path)?;
+    }
+    let end_cycle = computer.cycles() + args.cycles;
+    let mut keys = match &args.keys {
+        Some(path) => {
+            let text = fs::read_to_string(path)
+                .with_context(|| format!("couldn't read key script {path}"))?;
+            Some(KeyScript::parse(&text, &program)?)
+        }
+        None => None,
+    };
+    if args.screen_every.is_some() && args.screen.is_none() {
+        bail!("--screen-every requires --screen");
+    }
+
+    let mut profiler = args.profile.as_ref().map(|_| Profiler::new(&program));
+    let mut hits = args.coverage.as_ref().map(|_| vec![0; MEMORY_SIZE]);
+    let mut tracer = match &args.trace {
+        Some(path) => {
+            let format = TraceFormat::from_path(path)?;
+            let filter = args
+                .trace_filter
+                .as_ref()
+                .map(|filter| TraceFilter::parse(filter, &program))
+                .transpose()?;
+            let file =
+                File::create(path).with_context(|| format!("couldn't create file {path}"))?;
+            Some(Tracer::new(BufWriter::new(file), format, filter)?)
+        }
+        None if args.trace_filter.is_some() => bail!("--trace-filter requires --trace"),
+        None => None,
+    };
+
+    // Without anything to observe on every cycle, use the fast engine, and
+    // only stop for screen frames.
+    let check_assertions = args.assert && !program.assertions.is_empty();
+    ensure!(
+        !args.detect_hangs || (keys.is_none() && args.devices.is_empty()),
+        "--detect-hangs is not supported with --keys or --device"
+    );
+    let mut watchdog = args.detect_hangs.then(Watchdog::new);
+    let per_cycle = check_assertions
+        || keys.is_some()
+        || profiler.is_some()
+        || hits.is_some()
+        || tracer.is_some()
+        || watchdog.is_some();
+
+    let mut frame = 0;
+    let mut frame_cycle = None;
+    let mut status = Status::OutOfCycles;
+    let mut failure = None;
+    while computer.cycles() < end_cycle {
+        if per_cycle {
+            let failed = check_assertions
+                .then(|| program.failed_assertion(&computer))
+                .flatten();
+            if let Some(assertion) = failed {
+                let explanation = assertion.explain(computer.a(), computer.d(), computer.ram());
+                failure = Some(format!(
+                    "{}:{}: assertion failed: {} ({explanation}, at cycle {})",
+                    args.path,
+                    assertion.line,
+                    assertion.text,
+                    computer.cycles()
+                ));
+                status = Status::AssertionFailed;
+            } else if !args.run_past_halt && computer.is_halted() {
+                status = Status::Halted;
+            } else if watchdog.as_mut().is_some_and(|w| w.is_stuck(&computer)) {
+                status = Status::NoProgress;
+            }
+
+            // Once stopped, just save the last frame.
+            if status == Status::OutOfCycles {
+                if let Some(keys) = &mut keys {
+                    keys.update(&mut computer);
+                }
+                if let Some(profiler) = &mut profiler {
+                    profiler.record(&computer);
+                }
+                if let Some(hits) = &mut hits {
+                    hits[usize::from(computer.pc())] += 1;
+                }
+                match &mut tracer {
+                    Some(tracer) => tracer.step(&mut computer)?,
+                    None => computer.step()?,
+                }
+            }
+        } else {
+            let remaining = end_cycle - computer.cycles();
+            let chunk = args.screen_every.map_or(remaining, |every| {
+                (every - computer.cycles() % every).min(remaining)
+            });
+            if args.run_past_halt {
+                computer.run_fast(chunk)?;
+            } else if computer.run_until_halted(chunk)? {
+                status = Status::Halted;
+            }
+        }
+
+        if let (Some(screen_path), Some(every)) = (&args.screen, args.screen_every) {
+            // The last frame may have been saved already, if the program
+            // stopped without running any more cycles.
+            let last = computer.cycles() == end_cycle || status != Status::OutOfCycles;
+            let due = computer.cycles() % every == 0 || last;
+            if due && frame_cycle != Some(computer.cycles()) {
+                frame += 1;
+                frame_cycle = Some(computer.cycles());
+                computer.screen().save(frame_path(screen_path, frame))?;
+            }
+        }
+        if status != Status::OutOfCycles {
+            break;
+        }
+    }
+    if let (Some(screen_path), None) = (&args.screen, args.screen_every) {
+        computer.screen().save(screen_path)?;
+    }
+    if let Some(tracer) = tracer {
+        tracer.into_inner().flush()?;
+    }
+    if let Some(path) = &args.save_snapshot {
+        computer.save_snapshot(path)?;
+    }
+
+    let pc = program.describe_rom(computer.pc());
+    match status {
+        // Report where the halt loop starts, which is where it jumps to.
+        Status::Halted => println!("halted at {}", program.describe_rom(computer.a())),
+        Status::NoProgress => println!("no progress: looping at {pc} without writing to RAM"),
+        Status::OutOfCycles => println!("ran out of cycles, at {pc}"),
+        Status::AssertionFailed => println!("assertion failed, at {pc}"),
+    }
+    println!(
+        "A={} D={} PC={} cycles={}",
+        computer.a(),
+        computer.d(),
+        computer.pc(),
+        computer.cycles()
+    );
+    for (i, value) in computer.ram()[..16].iter().enumerate() {
+        println!("R{i}={value}");
+    }
+
+    if let (Some(profiler), Some(path)) = (&profiler, &args.profile) {
+        println!();
+        profiler.report(&program, io::stdout().lock())?;
+
+        let file = File::create(path).with_context(|| format!("couldn't create file {path}"))?;
+        let mut out = BufWriter::new(file);
+        profiler.write_folded(&program, &mut out)?;
+        out.flush()?;
+    }
+
+    if let (Some(hits), Some(path)) = (&hits, &args.coverage) {
+        let mut coverage = Coverage::new();
+        coverage.add(Path::new(&args.path), &program, hits)?;
+        coverage.save(path)?;
+    }
+
+    if let Some(failure) = failure {
+        bail!(failure);
+    }
+
+    Ok(())
+}
+
+/// Why a program stopped running.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    /// It jumped to itself. See `Computer::is_halted`.
+    Halted,
+
+    /// It's stuck in a loop which doesn't write to RAM. See `Watchdog`.
+    NoProgress,
+
+    /// It used up its budget of cycles.
+    OutOfCycles,
+
+    /// It reached an instruction with an assertion which doesn't hold.
+    AssertionFailed,
+}
+
+/// Create an extra device, by name. See `main`.
+fn device(name: &str) -> Result<Box<dyn Device>> {
+    let device: Box<dyn Device> = match name {
+        "console" => Box::new(Console::new(cpu_emulator::CONSOLE, Box::new(io::stdout()))),
+        "timer" => Box::new(Timer::new(cpu_emulator::TIMER, TIMER_PERIOD)?),
+        "random" => Box::new(Random::new(cpu_emulator::RANDOM, RANDOM_SEED)),
+        "debug" => Box::new(DebugPort::new(cpu_emulator::DEBUG, Box::new(io::stderr()))),
+        _ => bail!("unknown device {name:?}: expected console, timer, random, or debug"),
+    };
+    Ok(device)
+}
+
+/// Read debugger commands from stdin until `quit` or end of input.
+fn debug(program: Program, history: usize) -> Result<()> {
+    let mut debugger = Debugger::with_history(program, history)?;
+    let mut stdin = io::stdin().lock();
+    let mut stdout = io::stdout().lock();
+    let mut line = String::new();
+
+    loop {
+        write!(stdout, "(hack) ")?;
+        stdout.flush()?;
+
+        line.clear();
+        if stdin.read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        match debugger.execute(&line, &mut stdout) {
+            Ok(true) => {}
+            Ok(false) => return Ok(()),
+            Err(e) => eprintln!("error: {e:#}"),
+        }
+    }
+}
+
+/// Insert a frame number before the extension of `path`.
+fn frame_path(path: &str, frame: u64) -> PathBuf {
+    let path = Path::new(path);
+    let stem = path.file_stem().and_then(OsStr::to_str).unwrap_or_default();
+    let ext = path.extension().and_then(OsStr::to_str).unwrap_or_default();
+    path.with_file_name(format!("{stem}-{frame:06}.{ext}"))
+}
+
+/// Command-line arguments.
+struct Args {
+    path: String,
+    cycles: u64,
+    run_past_halt: bool,
+    detect_hangs: bool,
+    load_snapshot: Option<String>,
+    save_snapshot: Option<String>,
+    screen: Option<String>,
+    screen_every: Option<u64>,
+    devices: Vec<String>,
+    keys: Option<String>,
+    assert: bool,
+    profile: Option<String>,
+    coverage: Option<String>,
+    trace: Option<String>,
+    trace_filter: Option<String>,
+    debug: bool,
+    history: usize,
+    gdb: Option<u16>,
+    gdb_symbols: Option<String>,
+    dap: bool,
+    tui: bool,
+    watch: Option<String>,
+}
+
+impl Args {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self> {
+        let mut paths = vec![];
+        let mut cycles = 1_000_000;
+        let mut run_past_halt = false;
+        let mut detect_hangs = false;
+        let mut load_snapshot = None;
+        let mut save_snapshot = None;
+        let mut screen = None;
+        let mut screen_every = None;
+        let mut devices = vec![];
+        let mut keys = None;
+        let mut assert = false;
+        let mut profile = None;
+        let mut coverage = None;
+        let mut trace = None;
+        let mut trace_filter = None;
+        let mut debug = false;
+        let mut history = cpu_emulator::DEFAULT_HISTORY;
+        let mut gdb = None;
+        let mut gdb_symbols = None;
+        let mut dap = false;
+        let mut tui = false;
+        let mut watch = None;
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--cycles" => cycles = parse_number(&arg, args.next())?,
+                "--run-past-halt" => run_past_halt = true,
+                "--detect-hangs" => detect_hangs = true,
+                "--load-snapshot" => {
+                    let value = args.next().context("missing value for --load-snapshot")?;
+                    load_snapshot = Some(value);
+                }
+                "--save-snapshot" => {
+                    let value = args.next().context("missing value for --save-snapshot")?;
+                    save_snapshot = Some(value);
+                }
+                "--screen" => {
+                    let value = args.next().context("missing value for --screen")?;
+                    screen = Some(value);
+                }
+                "--screen-every" => {
+                    let every = parse_number(&arg, args.next())?;
+                    ensure!(every > 0, "--screen-every must be positive");
+                    screen_every = Some(every);
+                }
+                "--device" => {
+                    let value = args.next().context("missing value for --device")?;
+                    devices.push(value);
+                }
+                "--keys" => {
+                    let value = args.next().context("missing value for --keys")?;
+                    keys = Some(value);
+                }
+                "--assert" => assert = true,
+                "--profile" => {
+                    let value = args.next().context("missing value for --profile")?;
+                    profile = Some(value);
+                }
+                "--coverage" => {
+                    let value = args.next().context("missing value for --coverage")?;
+                    coverage = Some(value);
+                }
+                "--trace" => {
+                    let value = args.next().context("missing value for --trace")?;
+                    trace = Some(value);
+                }
+                "--trace-filter" => {
+                    let value = args.next().context("missing value for --trace-filter")?;
+                    trace_filter = Some(value);
+                }
+                "--debug" => debug = true,
+                "--gdb" => {
+                    let port = parse_number(&arg, args.next())?;
+                    gdb = Some(u16::try_from(port).context("invalid port for --gdb")?);
+                }
+                "--gdb-symbols" => {
+                    let value = args.next().context("missing value for --gdb-symbols")?;
+                    gdb_symbols = Some(value);
+                }
+                "--dap" => dap = true,
+                "--tui" => tui = true,
+                "--watch" => {
+                    let value = args.next().context("missing value for --watch")?;
+                    watch = Some(value);
+                }
+                "--history" => history = parse_number(&arg, args.next())? as usize,
+                _ if arg.starts_with("--") => bail!("unrecognized option {arg:?}"),
+                _ => paths.push(arg),
+            }
+        }
+
+        let n = paths.len();
+        if dap {
+            // The program can be given by the `launch` request instead.
+            ensure!(n <= 1, "expected at most one program file, got {n}");
+        } else {
+            ensure!(n == 1, "expected one program file, got {n}");
+        }
+        let path = paths.pop().unwrap_or_default();
+
+        Ok(Self {
+            path,
+            cycles,
+            run_past_halt,
+            detect_hangs,
+            load_snapshot,
+            save_snapshot,
+            screen,
+            screen_every,
+            devices,
+            keys,
+            assert,
+            profile,
+            coverage,
+            trace,
+            trace_filter,
+            debug,
+            history,
+            gdb,
+            gdb_symbols,
+            dap,
+            tui,
+            watch,
+        })
+    }
+}
+
+/// Parse the value of a numeric option.
+fn parse_number(option: &str, value: Option<String>) -> Result<u64> {
+    let value = value.with_context(|| format!("missing value for {option}"))?;
+    value
+        .parse()
+        .with_context(|| format!("invalid value for {option}: {value:?}"))
+}
@@ -0,0 +1,238 @@
+//! Count where a program spends its cycles.
+//!
+//! Cycles are counted per ROM address, and aggregated by the enclosing label.
+//!
+//! Call stacks are recovered for code that follows the VM's calling
+//! convention: jumping to a function label (e.g. `Main.main`) is a call,
+//! whose return address is in `RAM[SP - 5]`, and jumping to that return
+//! address is the matching return. Other code simply runs in the frame of
+//! whichever function called it, if any.
+
+use std::{collections::HashMap, io::Write};
+
+use anyhow::Result;
+use itertools::Itertools;
+
+use crate::{program::Program, Computer, MEMORY_SIZE};
+
+/// Where code before the first label is attributed.
+const START: &str = "<start>";
+
+pub struct Profiler {
+    /// Indexed by ROM address.
+    counts: Vec<u64>,
+
+    /// The ROM address of each function label.
+    functions: HashMap<u16, String>,
+
+    /// The current call stack.
+    frames: Vec<Frame>,
+
+    /// Each distinct call stack (as function addresses), and its index in
+    /// `stacks`.
+    stack_ids: HashMap<Vec<u16>, usize>,
+    stacks: Vec<Vec<u16>>,
+    stack_id: usize,
+
+    /// Cycles per (call stack, ROM address).
+    samples: HashMap<(usize, u16), u64>,
+
+    /// The PC at the previous cycle.
+    prev_pc: Option<u16>,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Frame {
+    function: u16,
+    return_address: u16,
+}
+
+impl Profiler {
+    pub fn new(program: &Program) -> Self {
+        let functions = program
+            .labels()
+            .iter()
+            .filter(|(label, _)| is_function(label))
+            .map(|(label, address)| (*address, label.clone()))
+            .collect();
+
+        Self {
+            counts: vec![0; MEMORY_SIZE],
+            functions,
+            frames: vec![],
+            stack_ids: HashMap::from([(vec![], 0)]),
+            stacks: vec![vec![]],
+            stack_id: 0,
+            samples: HashMap::new(),
+            prev_pc: None,
+        }
+    }
+
+    /// Count the instruction that is about to execute. Call this before each
+    /// step.
+    pub fn record(&mut self, computer: &Computer) {
+        let pc = computer.pc();
+        let jumped = self.prev_pc.is_some_and(|prev| pc != prev.wrapping_add(1));
+        if jumped {
+            self.track_calls(computer);
+        }
+        self.prev_pc = Some(pc);
+
+        self.counts[usize::from(pc)] += 1;
+        *self.samples.entry((self.stack_id, pc)).or_default() += 1;
+    }
+
+    fn track_calls(&mut self, computer: &Computer) {
+        let pc = computer.pc();
+        if self.functions.contains_key(&pc) {
+            let ram = computer.ram();
+            let sp = ram[0];
+            let return_address = ram[usize::from(sp.wrapping_sub(5) % 0x8000)];
+            self.frames.push(Frame {
+                function: pc,
+                return_address,
+            });
+        } else if let Some(depth) = self
+            .frames
+            .iter()
+            .rposition(|frame| frame.return_address == pc)
+        {
+            self.frames.truncate(depth);
+        } else {
+            return;
+        }
+
+        let stack = self.frames.iter().map(|frame| frame.function).collect_vec();
+        let next_id = self.stacks.len();
+        self.stack_id = *self.stack_ids.entry(stack.clone()).or_insert_with(|| {
+            self.stacks.push(stack);
+            next_id
+        });
+    }
+
+    /// Cycles per label, most first.
+    pub fn by_label<'a>(&self, program: &'a Program) -> Vec<(&'a str, u64)> {
+        let mut totals: HashMap<&str, u64> = HashMap::new();
+        for (address, &count) in self.counts.iter().enumerate() {
+            if count != 0 {
+                *totals.entry(label(program, address as u16)).or_default() += count;
+            }
+        }
+
+        totals
+            .into_iter()
+            .sorted_by_key(|&(label, count)| (std::cmp::Reverse(count), label))
+            .collect()
+    }
+
+    /// Print the cycles spent under each label, most first.
+    pub fn report(&self, program: &Program, mut out: impl Write) -> Result<()> {
+        let total: u64 = self.counts.iter().sum();
+        writeln!(out, "{:>12}  {:>6}  label", "cycles", "%")?;
+        for (label, count) in self.by_label(program) {
+            let percent = 100.0 * count as f64 / total as f64;
+            writeln!(out, "{count:>12}  {percent:>6.2}  {label}")?;
+        }
+        Ok(())
+    }
+
+    /// Write the call stacks in the "folded" format used by flame graph tools:
+    /// one line per stack, with frames separated by `;`, followed by a count.
+    ///
+    /// The innermost frame is the label enclosing the instruction, unless
+    /// that's the function itself.
+    pub fn write_folded(&self, program: &Program, mut out: impl Write) -> Result<()> {
+        let mut folded: HashMap<String, u64> = HashMap::new();
+        for (&(stack_id, pc), &count) in &self.samples {
+            let mut frames = self.stacks[stack_id]
+                .iter()
+                .map(|function| self.functions[function].as_str())
+                .collect_vec();
+            let leaf = label(program, pc);
+            if frames.last() != Some(&leaf) {
+                frames.push(leaf);
+            }
+            *folded.entry(frames.join(";")).or_default() += count;
+        }
+
+        for (stack, count) in folded.into_iter().sorted() {
+            writeln!(out, "{stack} {count}")?;
+        }
+        Ok(())
+    }
+}
+
+/// VM functions are named `File.function`. Labels generated inside functions
+/// contain a `$`.
+fn is_function(label: &str) -> bool {
+    label.contains('.') && !label.contains('$')
+}
+
+fn label(program: &Program, address: u16) -> &str {
+    program
+        .enclosing_label(address)
+        .map_or(START, |(label, _)| label)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn profile(source: &str, cycles: u64) -> Result<(Program, Profiler)> {
+        let program = Program::from_asm(source)?;
+        let mut computer = Computer::new(&program.words)?;
+        let mut profiler = Profiler::new(&program);
+        for _ in 0..cycles {
+            profiler.record(&computer);
+            computer.step()?;
+        }
+        Ok((program, profiler))
+    }
+
+    #[test]
+    fn mult() -> Result<()> {
+        let source = include_str!("../../../04/mult/Mult.asm");
+        // R0 is 0, so the loop exits straight away, and then we run off the
+        // end of the program into empty ROM.
+        let (program, profiler) = profile(source, 20)?;
+        assert_eq!(
+            profiler.by_label(&program),
+            [("END", 14), ("LOOP", 4), ("START", 2)]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn calls() -> Result<()> {
+        // A cut-down version of the VM's calling convention: push the return
+        // address, and leave room for the saved segment pointers.
+        let source = "
+            @261
+            D=A
+            @SP
+            M=D
+            @RET
+            D=A
+            @256
+            M=D
+            @Foo.bar
+            0;JMP
+        (RET)
+            @RET
+            0;JMP
+        (Foo.bar)
+            @256
+            A=M
+        (Foo.bar$LOOP)
+            0;JMP
+        ";
+        let (program, profiler) = profile(source, 17)?;
+        let mut out = vec![];
+        profiler.write_folded(&program, &mut out)?;
+        assert_eq!(
+            String::from_utf8(out)?,
+            "<start> 10\nFoo.bar 2\nFoo.bar;Foo.bar$LOOP 1\nRET 4\n"
+        );
+        Ok(())
+    }
+}
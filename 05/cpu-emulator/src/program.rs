@@ -0,0 +1,254 @@
+//! Programs to load into ROM, along with any debugging information.
+
+use std::{collections::HashMap, ffi::OsStr, fs, path::Path};
+
+use anyhow::{bail, ensure, Context, Result};
+use assembler::{Assertion, Assertions, Isa, SourceMap, SymbolKind, SymbolTable};
+
+use crate::{
+    device::{self, Device},
+    Computer,
+};
+
+pub struct Program {
+    /// The machine code.
+    pub words: Vec<u16>,
+
+    /// Only available when the program was loaded from assembly source.
+    pub symbol_table: Option<SymbolTable>,
+    pub source_map: Option<SourceMap>,
+
+    /// Conditions from `//! assert` comments, keyed by ROM address. Empty
+    /// unless the program was loaded from assembly source.
+    pub assertions: Assertions,
+
+    /// Predefined symbols, including devices, which are available even
+    /// without a symbol table.
+    predefined: SymbolTable,
+
+    /// Labels, sorted by address.
+    labels: Vec<(String, u16)>,
+
+    /// Names of RAM addresses: variables first, then predefined symbols.
+    ram_names: HashMap<u16, Vec<String>>,
+}
+
+impl Program {
+    /// Load a `.hack` file, or a `.asm` file (which is assembled first, using
+    /// the extended ISA).
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        Self::from_file_with_devices(path, &[])
+    }
+
+    /// Load a program, as with `from_file`, for a computer with extra
+    /// devices. Their symbols are available to the assembler.
+    pub fn from_file_with_devices(
+        path: impl AsRef<Path>,
+        devices: &[Box<dyn Device>],
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("couldn't read file {}", path.display()))?;
+
+        match path.extension().and_then(OsStr::to_str) {
+            Some("hack") => {
+                let predefined = predefined_symbols(devices)?;
+                Ok(Self {
+                    words: assembler::parse_hack(&contents)?,
+                    symbol_table: None,
+                    source_map: None,
+                    assertions: Assertions::default(),
+                    ram_names: ram_names(&predefined),
+                    predefined,
+                    labels: vec![],
+                })
+            }
+            Some("asm") => Self::from_asm_with_devices(&contents, devices),
+            _ => bail!("must have .hack or .asm extension: {}", path.display()),
+        }
+    }
+
+    /// Assemble a program, using the extended ISA.
+    pub fn from_asm(source: &str) -> Result<Self> {
+        Self::from_asm_with_devices(source, &[])
+    }
+
+    /// Assemble a program, as with `from_asm`, for a computer with extra
+    /// devices.
+    pub fn from_asm_with_devices(source: &str, devices: &[Box<dyn Device>]) -> Result<Self> {
+        let predefined = predefined_symbols(devices)?;
+        let assembled =
+            assembler::assemble_with_symbols(source.as_bytes(), Isa::Extended, predefined.clone())?;
+        let labels = assembled
+            .symbol_table
+            .symbols(SymbolKind::Label)
+            .into_iter()
+            .map(|(label, address)| (label.to_owned(), address))
+            .collect();
+        let ram_names = ram_names(&assembled.symbol_table);
+
+        Ok(Self {
+            words: assembled.words,
+            symbol_table: Some(assembled.symbol_table),
+            source_map: Some(assembled.source_map),
+            assertions: assembled.assertions,
+            predefined,
+            labels,
+            ram_names,
+        })
+    }
+
+    /// Look up the address of a symbol, such as a label.
+    ///
+    /// Predefined symbols, like `R2` or `SP`, are always available.
+    pub fn symbol(&self, name: &str) -> Result<u16> {
+        let Some(symbol_table) = &self.symbol_table else {
+            return self.predefined.lookup_symbol(name).with_context(|| {
+                format!("can't look up symbol {name:?}: no symbols available (load a .asm file instead)")
+            });
+        };
+
+        symbol_table
+            .lookup_symbol(name)
+            .with_context(|| format!("unknown symbol {name:?}"))
+    }
+
+    /// Look up the ROM address of a label. Unlike `symbol`, this rejects
+    /// variables and predefined symbols, which are RAM addresses.
+    pub fn label(&self, name: &str) -> Result<u16> {
+        let Some(symbol_table) = &self.symbol_table else {
+            bail!("can't look up label {name:?}: no symbols available (load a .asm file instead)");
+        };
+
+        match symbol_table.lookup_kind(name) {
+            Some(SymbolKind::Label) => self.symbol(name),
+            Some(_) => bail!("{name:?} is a RAM address, not a label"),
+            None => bail!("unknown label {name:?}"),
+        }
+    }
+
+    /// Parse an address, given as a number or a symbol.
+    pub fn address(&self, text: &str) -> Result<u16> {
+        let address = match text.parse() {
+            Ok(address) => address,
+            Err(_) => self.symbol(text)?,
+        };
+        ensure!(
+            address < assembler::ADDRESS_LIMIT,
+            "address out of range: {text}"
+        );
+        Ok(address)
+    }
+
+    /// Parse a ROM address, given as a number or a label.
+    pub fn rom_address(&self, text: &str) -> Result<u16> {
+        let address = match text.parse() {
+            Ok(address) => address,
+            Err(_) => self.label(text)?,
+        };
+        ensure!(
+            address < assembler::ADDRESS_LIMIT,
+            "address out of range: {text}"
+        );
+        Ok(address)
+    }
+
+    /// Labels, sorted by address.
+    pub fn labels(&self) -> &[(String, u16)] {
+        &self.labels
+    }
+
+    /// The last label at or before a ROM address, and the offset from it.
+    pub fn enclosing_label(&self, address: u16) -> Option<(&str, u16)> {
+        let i = self.labels.partition_point(|&(_, a)| a <= address);
+        let (label, start) = self.labels.get(i.checked_sub(1)?)?;
+        Some((label, address - start))
+    }
+
+    /// Describe a ROM address relative to its enclosing label, e.g. `LOOP+3`.
+    pub fn describe_rom(&self, address: u16) -> String {
+        match self.enclosing_label(address) {
+            Some((label, 0)) => label.to_owned(),
+            Some((label, offset)) => format!("{label}+{offset}"),
+            None => address.to_string(),
+        }
+    }
+
+    /// The first assertion at the PC which doesn't hold, if any.
+    pub fn failed_assertion(&self, computer: &Computer) -> Option<&Assertion> {
+        self.assertions
+            .get(computer.pc())
+            .iter()
+            .find(|assertion| !assertion.holds(computer.a(), computer.d(), computer.ram()))
+    }
+
+    /// The names of a RAM address: variables first, then predefined symbols.
+    pub fn ram_names(&self, address: u16) -> &[String] {
+        self.ram_names.get(&address).map_or(&[], Vec::as_slice)
+    }
+}
+
+/// The registers, and the names of the built-in devices and `devices`.
+fn predefined_symbols(devices: &[Box<dyn Device>]) -> Result<SymbolTable> {
+    let mut symbol_table = SymbolTable::without_devices();
+    for device in device::builtin().iter().chain(devices) {
+        for (symbol, address) in device.symbols() {
+            symbol_table.new_device(symbol, address)?;
+        }
+    }
+    Ok(symbol_table)
+}
+
+fn ram_names(symbol_table: &SymbolTable) -> HashMap<u16, Vec<String>> {
+    let mut names: HashMap<_, Vec<_>> = HashMap::new();
+    for kind in [SymbolKind::Variable, SymbolKind::Predefined] {
+        for (name, address) in symbol_table.symbols(kind) {
+            names.entry(address).or_default().push(name.to_owned());
+        }
+    }
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn assertions() -> Result<()> {
+        let source = "\
+            //! assert D == 0
+            @5
+            D=A   //! assert D == 5
+            //! assert RAM[x] < D
+            @x
+            M=D
+            (LOOP)
+            //! assert RAM[x] == 5
+            @LOOP
+            0;JMP
+        ";
+        let program = Program::from_asm(source)?;
+        let lines: Vec<_> = program
+            .assertions
+            .iter()
+            .map(|(address, assertion)| (address, assertion.line))
+            .collect();
+        assert_eq!(lines, [(0, 1), (2, 3), (2, 4), (4, 8)]);
+
+        let mut computer = Computer::new(&program.words)?;
+        for _ in 0..10 {
+            assert!(program.failed_assertion(&computer).is_none());
+            computer.step()?;
+        }
+
+        // `x` holds 5, which isn't less than D.
+        computer.set_pc(2);
+        let failed = program.failed_assertion(&computer).unwrap();
+        assert_eq!(failed.text, "RAM[x] < D");
+        assert_eq!(
+            failed.explain(computer.a(), computer.d(), computer.ram()),
+            "5 < 5"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,152 @@
+//! The memory-mapped screen, and saving it as an image.
+//!
+//! The screen is 512 x 256 black-and-white pixels. Each row is 32 consecutive
+//! words of RAM, starting at `SCREEN`; within a word, the least significant
+//! bit is the leftmost pixel. A set bit is a black pixel.
+
+use std::{
+    ffi::OsStr,
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use anyhow::{bail, Context, Result};
+
+/// The RAM address of the top-left corner of the screen.
+pub const SCREEN: u16 = 0x4000;
+
+pub const WIDTH: usize = 512;
+pub const HEIGHT: usize = 256;
+
+const WORDS_PER_ROW: usize = WIDTH / 16;
+
+/// A view of the screen's memory.
+pub struct Screen<'a> {
+    words: &'a [u16],
+}
+
+impl<'a> Screen<'a> {
+    /// The screen region of `ram`.
+    pub fn new(ram: &'a [u16]) -> Self {
+        let start = usize::from(SCREEN);
+        let words = &ram[start..start + WORDS_PER_ROW * HEIGHT];
+        Self { words }
+    }
+
+    /// Is the pixel at column `x`, row `y` black?
+    pub fn pixel(&self, x: usize, y: usize) -> bool {
+        let word = self.words[y * WORDS_PER_ROW + x / 16];
+        (word >> (x % 16)) & 1 != 0
+    }
+
+    /// Is every pixel black?
+    pub fn is_all_black(&self) -> bool {
+        self.words.iter().all(|&w| w == 0xffff)
+    }
+
+    /// Is every pixel white?
+    pub fn is_all_white(&self) -> bool {
+        self.words.iter().all(|&w| w == 0)
+    }
+
+    /// Save the screen as a `.pbm` or `.png` image, depending on the
+    /// extension of `path`.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+
+        let write = match path.extension().and_then(OsStr::to_str) {
+            Some("pbm") => Self::write_pbm,
+            Some("png") => Self::write_png,
+            _ => bail!("must have .pbm or .png extension: {}", path.display()),
+        };
+
+        let file = File::create(path)
+            .with_context(|| format!("couldn't create file {}", path.display()))?;
+        write(self, BufWriter::new(file))
+    }
+
+    /// Write a binary ("P4") PBM image.
+    pub fn write_pbm(&self, mut out: impl Write) -> Result<()> {
+        write!(out, "P4\n{WIDTH} {HEIGHT}\n")?;
+        // In PBM, 1 is black.
+        out.write_all(&self.packed_rows(true))?;
+        out.flush()?;
+        Ok(())
+    }
+
+    /// Write a 1-bit grayscale PNG image.
+    pub fn write_png(&self, out: impl Write) -> Result<()> {
+        let mut encoder = png::Encoder::new(out, WIDTH as u32, HEIGHT as u32);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::One);
+
+        // In grayscale, 0 is black.
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.packed_rows(false))?;
+        writer.finish()?;
+        Ok(())
+    }
+
+    /// Pack the pixels eight per byte, leftmost pixel in the most significant
+    /// bit, as both PBM and PNG expect.
+    fn packed_rows(&self, black: bool) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(WIDTH / 8 * HEIGHT);
+
+        for y in 0..HEIGHT {
+            for byte_x in (0..WIDTH).step_by(8) {
+                let mut byte = 0;
+                for x in byte_x..byte_x + 8 {
+                    byte <<= 1;
+                    byte |= (self.pixel(x, y) == black) as u8;
+                }
+                bytes.push(byte);
+            }
+        }
+
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assembler::Isa;
+
+    use super::*;
+    use crate::Computer;
+
+    #[test]
+    fn fill() -> Result<()> {
+        let source = include_str!("../../../04/fill/Fill.asm");
+        let assembled = assembler::assemble(source.as_bytes(), Isa::Strict)?;
+        let mut computer = Computer::new(&assembled.words)?;
+
+        // Hold down a key.
+        computer.ram_mut()[0x6000] = 65;
+        computer.run(200_000)?;
+        assert!(Screen::new(computer.ram()).is_all_black());
+
+        // Release it.
+        computer.ram_mut()[0x6000] = 0;
+        computer.run(200_000)?;
+        assert!(Screen::new(computer.ram()).is_all_white());
+
+        Ok(())
+    }
+
+    #[test]
+    fn pbm() -> Result<()> {
+        let mut ram = vec![0; 0x8000];
+        // The top-left pixel, and the rightmost pixel of the first word.
+        ram[usize::from(SCREEN)] = 0b1000_0000_0000_0001;
+
+        let mut pbm = vec![];
+        Screen::new(&ram).write_pbm(&mut pbm)?;
+
+        let header = b"P4\n512 256\n";
+        assert_eq!(&pbm[..header.len()], header);
+        assert_eq!(pbm[header.len()..][..2], [0b1000_0000, 0b0000_0001]);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,116 @@
+//! Test scripts, in the CPU emulator subset of the nand2tetris test-scripting
+//! language.
+//!
+//! For example:
+//!
+//! ```text
+//! load Mult.hack,
+//! output-file Mult.out,
+//! compare-to Mult.cmp,
+//! output-list RAM[0]%D2.6.2 RAM[1]%D2.6.2 RAM[2]%D2.6.2;
+//!
+//! set RAM[0] 2,
+//! set RAM[1] 3;
+//! repeat 20 {
+//!     ticktock;
+//! }
+//! output;
+//! ```
+//!
+//! As an extension, `save-snapshot FILE` and `load-snapshot FILE` save and
+//! restore the computer's full state.
+//!
+//! Unlike the nand2tetris CPU emulator, `repeat {` without a count, which
+//! runs until the user stops it, isn't supported, since nothing would end it.
+
+mod parse;
+mod run;
+
+pub use self::run::{run_script, run_script_with_coverage};
+
+/// A parsed test script.
+#[derive(Debug)]
+pub struct Script {
+    commands: Vec<Command>,
+}
+
+#[derive(Debug)]
+enum Command {
+    /// Load a `.hack` (or `.asm`) file into ROM, and reset the computer.
+    Load(String),
+    OutputFile(String),
+    CompareTo(String),
+    OutputList(Vec<Column>),
+    Set(Variable, u16),
+    Repeat(u64, Vec<Command>),
+    While(Condition, Vec<Command>),
+
+    /// Execute one instruction.
+    TickTock,
+
+    /// Write the current values of the output-list variables.
+    Output,
+
+    Echo(String),
+
+    /// Save the computer's full state to a file. An extension to the
+    /// nand2tetris language.
+    SaveSnapshot(String),
+
+    /// Restore the computer's state from a file saved by `save-snapshot`,
+    /// e.g. to skip a long setup phase. A program needn't be loaded first.
+    LoadSnapshot(String),
+}
+
+/// A value that can be read (and possibly written) by a script.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Variable {
+    A,
+    D,
+    Pc,
+    Ram(u16),
+    Rom(u16),
+
+    /// The number of instructions executed so far. Read-only.
+    Time,
+}
+
+/// One column of the output table, e.g. `RAM[0]%D2.6.2`.
+#[derive(Debug)]
+struct Column {
+    variable: Variable,
+
+    /// How the variable was written in the script; used for the header.
+    name: String,
+
+    format: Format,
+    pad_left: usize,
+    len: usize,
+    pad_right: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Format {
+    Decimal,
+    Hex,
+    Binary,
+    String,
+}
+
+/// E.g., `RAM[0] <> 0`.
+#[derive(Debug)]
+struct Condition {
+    variable: Variable,
+    op: CompareOp,
+    value: i32,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum CompareOp {
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+}
@@ -0,0 +1,354 @@
+//! Parse a test script from text.
+
+use std::str::FromStr;
+
+use anyhow::{bail, ensure, Context, Result};
+
+use super::{Column, Command, CompareOp, Condition, Format, Script, Variable};
+
+/// The format used for output-list entries without an explicit `%` suffix.
+const DEFAULT_FORMAT: (Format, usize, usize, usize) = (Format::Decimal, 1, 6, 1);
+
+impl FromStr for Script {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let tokens = tokenize(s)?;
+        let mut tokens = &tokens[..];
+
+        let commands = parse_block(&mut tokens)?;
+        if let Some(t) = tokens.first() {
+            bail!("line {}: unexpected {:?}", t.line, t.kind);
+        }
+
+        Ok(Script { commands })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum TokenKind {
+    Word(String),
+
+    /// A double-quoted string, without the quotes.
+    Str(String),
+
+    /// One of `,`, `;`, or `!`, which end a command.
+    End,
+
+    Open,
+    Close,
+}
+
+#[derive(Debug)]
+struct Token {
+    kind: TokenKind,
+    line: usize,
+}
+
+/// Split the script into tokens, skipping whitespace and comments.
+fn tokenize(s: &str) -> Result<Vec<Token>> {
+    let mut tokens = vec![];
+    let mut line = 1;
+    let mut chars = s.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        let kind = match c {
+            '\n' => {
+                line += 1;
+                continue;
+            }
+            _ if c.is_whitespace() => continue,
+
+            '/' if chars.next_if_eq(&'/').is_some() => {
+                while chars.next_if(|&c| c != '\n').is_some() {}
+                continue;
+            }
+            '/' if chars.next_if_eq(&'*').is_some() => {
+                let start_line = line;
+                loop {
+                    match chars.next() {
+                        Some('*') if chars.next_if_eq(&'/').is_some() => break,
+                        Some('\n') => line += 1,
+                        Some(_) => (),
+                        None => bail!("line {start_line}: unterminated comment"),
+                    }
+                }
+                continue;
+            }
+
+            '"' => {
+                let mut string = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\n') | None => bail!("line {line}: unterminated string"),
+                        Some(c) => string.push(c),
+                    }
+                }
+                TokenKind::Str(string)
+            }
+
+            ',' | ';' | '!' => TokenKind::End,
+            '{' => TokenKind::Open,
+            '}' => TokenKind::Close,
+
+            _ => {
+                let mut word = String::from(c);
+                while let Some(c) = chars.next_if(|&c| !c.is_whitespace() && !",;!{}\"".contains(c))
+                {
+                    word.push(c);
+                }
+                TokenKind::Word(word)
+            }
+        };
+
+        tokens.push(Token { kind, line });
+    }
+
+    Ok(tokens)
+}
+
+/// Parse commands until the end of the block (or of the script).
+fn parse_block(tokens: &mut &[Token]) -> Result<Vec<Command>> {
+    let mut commands = vec![];
+
+    while let Some(t) = tokens.first() {
+        match t.kind {
+            TokenKind::Close => break,
+            // Empty command.
+            TokenKind::End => *tokens = &tokens[1..],
+            _ => commands.push(parse_command(tokens)?),
+        }
+    }
+
+    Ok(commands)
+}
+
+fn parse_command(tokens: &mut &[Token]) -> Result<Command> {
+    let line = tokens[0].line;
+    let TokenKind::Word(name) = &tokens[0].kind else {
+        bail!("line {line}: expected a command, got {:?}", tokens[0].kind);
+    };
+    *tokens = &tokens[1..];
+
+    // Loops are followed by a `{ block }`, instead of a terminator.
+    if name == "repeat" || name == "while" {
+        let header = take_until(tokens, &TokenKind::Open)
+            .with_context(|| format!("line {line}: expected '{{' after {name}"))?;
+        let body = parse_block(tokens)?;
+        expect(tokens, &TokenKind::Close)
+            .with_context(|| format!("line {line}: unclosed {name}"))?;
+
+        let command = if name == "repeat" {
+            parse_repeat(&header, body)
+        } else {
+            parse_while(&header, body)
+        };
+        return command.with_context(|| format!("line {line}: invalid {name} loop"));
+    }
+
+    let args = take_until(tokens, &TokenKind::End)
+        .with_context(|| format!("line {line}: missing terminator after {name:?}"))?;
+
+    parse_simple_command(name, &args).with_context(|| format!("line {line}: invalid command"))
+}
+
+/// Consume tokens up to (and including) `end`, and return the ones before it.
+fn take_until(tokens: &mut &[Token], end: &TokenKind) -> Result<Vec<TokenKind>> {
+    let idx = tokens
+        .iter()
+        .position(|t| &t.kind == end)
+        .context("unexpected end of script")?;
+
+    let taken = tokens[..idx].iter().map(|t| t.kind.clone()).collect();
+    *tokens = &tokens[idx + 1..];
+    Ok(taken)
+}
+
+fn expect(tokens: &mut &[Token], kind: &TokenKind) -> Result<()> {
+    match tokens.first() {
+        Some(t) if &t.kind == kind => {
+            *tokens = &tokens[1..];
+            Ok(())
+        }
+        Some(t) => bail!("expected {kind:?}, got {:?}", t.kind),
+        None => bail!("expected {kind:?}, got end of script"),
+    }
+}
+
+fn parse_repeat(header: &[TokenKind], body: Vec<Command>) -> Result<Command> {
+    let count = match header {
+        [TokenKind::Word(n)] => n.parse().with_context(|| format!("invalid count {n:?}"))?,
+        [] => bail!("unbounded repeat is not supported: use `repeat N {{` or `while`"),
+        _ => bail!("expected `repeat N {{`, got {header:?}"),
+    };
+
+    Ok(Command::Repeat(count, body))
+}
+
+fn parse_while(header: &[TokenKind], body: Vec<Command>) -> Result<Command> {
+    let [TokenKind::Word(variable), TokenKind::Word(op), TokenKind::Word(value)] = header else {
+        bail!("expected `while VARIABLE OP VALUE {{`, got {header:?}");
+    };
+
+    let variable = variable.parse()?;
+    let op = match op.as_str() {
+        "=" => CompareOp::Equal,
+        "<>" => CompareOp::NotEqual,
+        "<" => CompareOp::Less,
+        "<=" => CompareOp::LessEqual,
+        ">" => CompareOp::Greater,
+        ">=" => CompareOp::GreaterEqual,
+        _ => bail!("unrecognized comparison {op:?}"),
+    };
+    let value = i32::from(parse_value(value)? as i16);
+
+    Ok(Command::While(
+        Condition {
+            variable,
+            op,
+            value,
+        },
+        body,
+    ))
+}
+
+fn parse_simple_command(name: &str, args: &[TokenKind]) -> Result<Command> {
+    let words: Vec<&str> = args
+        .iter()
+        .map(|arg| match arg {
+            TokenKind::Word(w) | TokenKind::Str(w) => Ok(w.as_str()),
+            _ => bail!("unexpected {arg:?} in arguments to {name}"),
+        })
+        .collect::<Result<_>>()?;
+
+    let command = match (name, &words[..]) {
+        ("load", [file]) => Command::Load(file.to_string()),
+        ("output-file", [file]) => Command::OutputFile(file.to_string()),
+        ("compare-to", [file]) => Command::CompareTo(file.to_string()),
+        ("output-list", columns) => {
+            let columns = columns
+                .iter()
+                .map(|c| parse_column(c))
+                .collect::<Result<_>>()?;
+            Command::OutputList(columns)
+        }
+        ("set", [variable, value]) => {
+            let variable: Variable = variable.parse()?;
+            ensure!(
+                variable != Variable::Time,
+                "can't set read-only variable time"
+            );
+            Command::Set(variable, parse_value(value)?)
+        }
+        ("ticktock", []) => Command::TickTock,
+        ("output", []) => Command::Output,
+        ("echo", [text]) => Command::Echo(text.to_string()),
+        ("save-snapshot", [file]) => Command::SaveSnapshot(file.to_string()),
+        ("load-snapshot", [file]) => Command::LoadSnapshot(file.to_string()),
+        _ => bail!("unrecognized command {name:?} with arguments {words:?}"),
+    };
+
+    Ok(command)
+}
+
+impl FromStr for Variable {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let variable = match s {
+            "A" => Variable::A,
+            "D" => Variable::D,
+            "PC" => Variable::Pc,
+            "time" => Variable::Time,
+            _ => {
+                let (memory, rest) = s
+                    .split_once('[')
+                    .with_context(|| format!("unrecognized variable {s:?}"))?;
+                let index = rest
+                    .strip_suffix(']')
+                    .with_context(|| format!("missing ']' in {s:?}"))?;
+                let index: u16 = index
+                    .parse()
+                    .with_context(|| format!("invalid index in {s:?}"))?;
+                ensure!(
+                    index < assembler::ADDRESS_LIMIT,
+                    "index out of bounds in {s:?}"
+                );
+
+                match memory {
+                    "RAM" => Variable::Ram(index),
+                    "ROM" => Variable::Rom(index),
+                    _ => bail!("unrecognized variable {s:?}"),
+                }
+            }
+        };
+
+        Ok(variable)
+    }
+}
+
+/// Parse e.g. `RAM[0]%D2.6.2`.
+fn parse_column(s: &str) -> Result<Column> {
+    let (name, format) = match s.split_once('%') {
+        Some((name, format)) => (name, Some(format)),
+        None => (s, None),
+    };
+
+    let variable = name.parse()?;
+
+    let (format, pad_left, len, pad_right) = match format {
+        None => DEFAULT_FORMAT,
+        Some(spec) => {
+            let mut chars = spec.chars();
+            let format = match chars.next() {
+                Some('D') => Format::Decimal,
+                Some('X') => Format::Hex,
+                Some('B') => Format::Binary,
+                Some('S') => Format::String,
+                _ => bail!("unrecognized format in {s:?}"),
+            };
+
+            let widths: Vec<usize> = chars
+                .as_str()
+                .split('.')
+                .map(str::parse)
+                .collect::<Result<_, _>>()
+                .with_context(|| format!("invalid widths in {s:?}"))?;
+            let [pad_left, len, pad_right] = widths[..] else {
+                bail!("expected three widths in {s:?}");
+            };
+
+            (format, pad_left, len, pad_right)
+        }
+    };
+
+    Ok(Column {
+        variable,
+        name: name.to_string(),
+        format,
+        pad_left,
+        len,
+        pad_right,
+    })
+}
+
+/// Parse a 16-bit value: decimal (possibly negative), or with a `%D`, `%X`,
+/// or `%B` prefix.
+fn parse_value(s: &str) -> Result<u16> {
+    let (radix, digits) = match s.get(..2) {
+        Some("%D") => (10, &s[2..]),
+        Some("%X") => (16, &s[2..]),
+        Some("%B") => (2, &s[2..]),
+        _ => (10, s),
+    };
+
+    let value =
+        i32::from_str_radix(digits, radix).with_context(|| format!("invalid value {s:?}"))?;
+    ensure!(
+        (i32::from(i16::MIN)..=i32::from(u16::MAX)).contains(&value),
+        "value out of range: {s:?}"
+    );
+
+    Ok(value as u16)
+}
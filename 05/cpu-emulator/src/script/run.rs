@@ -0,0 +1,403 @@
+//! Run a test script against the emulator.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use itertools::Itertools;
+
+use super::{Column, Command, CompareOp, Condition, Format, Script, Variable};
+use crate::{program::Program, Computer, Coverage, MEMORY_SIZE};
+
+/// Run the test script at `path`.
+///
+/// File names in the script are relative to the script's directory. Fails if
+/// the output doesn't match the compare file. Either way, the output file (if
+/// any) is written.
+pub fn run_script(path: impl AsRef<Path>) -> Result<()> {
+    run(path.as_ref(), None)
+}
+
+/// Like `run_script`, but also record which lines of each loaded program's
+/// assembly source were executed.
+pub fn run_script_with_coverage(path: impl AsRef<Path>, coverage: &mut Coverage) -> Result<()> {
+    run(path.as_ref(), Some(coverage))
+}
+
+fn run(path: &Path, coverage: Option<&mut Coverage>) -> Result<()> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("couldn't read file {}", path.display()))?;
+    let script: Script = text
+        .parse()
+        .with_context(|| format!("failed to parse script {}", path.display()))?;
+
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let mut runner = Runner {
+        dir,
+        computer: None,
+        output_list: &[],
+        output_path: None,
+        output: vec![],
+        compare: None,
+        coverage: coverage.map(|coverage| Recorder {
+            coverage,
+            program: None,
+            hits: vec![0; MEMORY_SIZE],
+        }),
+    };
+
+    let mut result = runner.run(&script.commands);
+    if let Some(recorder) = &mut runner.coverage {
+        result = result.and(recorder.flush());
+    }
+
+    if let Some(output_path) = &runner.output_path {
+        let mut contents = runner.output.join("\n");
+        contents.push('\n');
+        fs::write(output_path, contents)
+            .with_context(|| format!("couldn't write file {}", output_path.display()))?;
+    }
+
+    result
+}
+
+struct Runner<'a> {
+    /// The directory containing the script.
+    dir: &'a Path,
+
+    /// `None` until a program is loaded.
+    computer: Option<Computer>,
+
+    output_list: &'a [Column],
+    output_path: Option<PathBuf>,
+
+    /// Lines of output so far, including the header.
+    output: Vec<String>,
+
+    /// The expected output lines, if there's a compare file.
+    compare: Option<Vec<String>>,
+
+    coverage: Option<Recorder<'a>>,
+}
+
+/// Counts executed instructions, for coverage.
+struct Recorder<'a> {
+    coverage: &'a mut Coverage,
+
+    /// The currently loaded program, and where it was loaded from.
+    program: Option<(PathBuf, Program)>,
+
+    /// Indexed by ROM address.
+    hits: Vec<u64>,
+}
+
+impl Recorder<'_> {
+    /// Add the counts for the current program to the coverage, and reset them.
+    fn flush(&mut self) -> Result<()> {
+        if let Some((path, program)) = self.program.take() {
+            self.coverage.add(&path, &program, &self.hits)?;
+            self.hits.fill(0);
+        }
+        Ok(())
+    }
+}
+
+impl<'a> Runner<'a> {
+    fn run(&mut self, commands: &'a [Command]) -> Result<()> {
+        for command in commands {
+            self.run_command(command)?;
+        }
+
+        Ok(())
+    }
+
+    fn run_command(&mut self, command: &'a Command) -> Result<()> {
+        match command {
+            Command::Load(file) => {
+                let path = self.dir.join(file);
+                let program = Program::from_file(&path)?;
+                self.computer = Some(Computer::new(&program.words)?);
+
+                if let Some(recorder) = &mut self.coverage {
+                    recorder.flush()?;
+                    recorder.program = Some((path, program));
+                }
+            }
+            Command::OutputFile(file) => {
+                self.output_path = Some(self.dir.join(file));
+                self.output.clear();
+            }
+            Command::CompareTo(file) => {
+                let path = self.dir.join(file);
+                let contents = fs::read_to_string(&path)
+                    .with_context(|| format!("couldn't read file {}", path.display()))?;
+                self.compare = Some(contents.lines().map(String::from).collect());
+            }
+            Command::OutputList(columns) => {
+                self.output_list = columns;
+                let header = format_row(columns.iter().map(header_cell));
+                self.emit(header)?;
+            }
+            Command::Set(variable, value) => self.set(*variable, *value)?,
+            Command::Repeat(count, body) => {
+                for _ in 0..*count {
+                    self.run(body)?;
+                }
+            }
+            Command::While(condition, body) => {
+                while self.test(condition)? {
+                    self.run(body)?;
+                }
+            }
+            Command::TickTock => {
+                let computer = self.computer.as_mut().context("no program loaded")?;
+                if let Some(recorder) = &mut self.coverage {
+                    recorder.hits[usize::from(computer.pc())] += 1;
+                }
+                computer.step()?;
+            }
+            Command::Output => {
+                let cells: Vec<_> = self
+                    .output_list
+                    .iter()
+                    .map(|column| Ok(value_cell(column, self.get(column.variable)?)))
+                    .collect::<Result<_>>()?;
+                self.emit(format_row(cells.into_iter()))?;
+            }
+            Command::Echo(text) => println!("{text}"),
+            Command::SaveSnapshot(file) => {
+                self.computer()?.save_snapshot(self.dir.join(file))?;
+            }
+            Command::LoadSnapshot(file) => {
+                let computer = match &mut self.computer {
+                    Some(computer) => computer,
+                    None => self.computer.insert(Computer::new(&[])?),
+                };
+                computer.load_snapshot(self.dir.join(file))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn computer(&self) -> Result<&Computer> {
+        self.computer.as_ref().context("no program loaded")
+    }
+
+    fn computer_mut(&mut self) -> Result<&mut Computer> {
+        self.computer.as_mut().context("no program loaded")
+    }
+
+    /// Read a variable. Registers and memory are interpreted as signed.
+    fn get(&self, variable: Variable) -> Result<i64> {
+        let computer = self.computer()?;
+
+        let value = match variable {
+            Variable::A => computer.a(),
+            Variable::D => computer.d(),
+            Variable::Pc => computer.pc(),
+            Variable::Ram(i) => computer.ram()[usize::from(i)],
+            Variable::Rom(i) => computer.rom()[usize::from(i)],
+            Variable::Time => return Ok(computer.cycles() as i64),
+        };
+
+        Ok(i64::from(value as i16))
+    }
+
+    fn set(&mut self, variable: Variable, value: u16) -> Result<()> {
+        let computer = self.computer_mut()?;
+
+        match variable {
+            Variable::A => computer.set_a(value),
+            Variable::D => computer.set_d(value),
+            Variable::Pc => computer.set_pc(value),
+            Variable::Ram(i) => computer.ram_mut()[usize::from(i)] = value,
+            Variable::Rom(i) => computer.rom_mut()[usize::from(i)] = value,
+            Variable::Time => bail!("can't set read-only variable time"),
+        }
+
+        Ok(())
+    }
+
+    fn test(&self, condition: &Condition) -> Result<bool> {
+        let lhs = self.get(condition.variable)?;
+        let rhs = i64::from(condition.value);
+
+        let result = match condition.op {
+            CompareOp::Equal => lhs == rhs,
+            CompareOp::NotEqual => lhs != rhs,
+            CompareOp::Less => lhs < rhs,
+            CompareOp::LessEqual => lhs <= rhs,
+            CompareOp::Greater => lhs > rhs,
+            CompareOp::GreaterEqual => lhs >= rhs,
+        };
+
+        Ok(result)
+    }
+
+    /// Append a line to the output, and check it against the compare file.
+    fn emit(&mut self, line: String) -> Result<()> {
+        let line_number = self.output.len() + 1;
+        self.output.push(line);
+        let line = &self.output[line_number - 1];
+
+        let Some(compare) = &self.compare else {
+            return Ok(());
+        };
+
+        match compare.get(line_number - 1) {
+            Some(expected) if lines_match(expected, line) => Ok(()),
+            Some(expected) => bail!(
+                "comparison failure at line {line_number}\n    expected: {expected}\n    actual:   {line}"
+            ),
+            None => bail!("comparison failure at line {line_number}: compare file has no more lines"),
+        }
+    }
+}
+
+/// Compare an output line against a line of the compare file, in which `*`
+/// matches any character. Trailing whitespace is ignored.
+fn lines_match(expected: &str, actual: &str) -> bool {
+    let expected = expected.trim_end();
+    let actual = actual.trim_end();
+
+    expected.chars().count() == actual.chars().count()
+        && expected
+            .chars()
+            .zip(actual.chars())
+            .all(|(e, a)| e == '*' || e == a)
+}
+
+fn format_row(cells: impl Iterator<Item = String>) -> String {
+    format!("|{}|", cells.format("|"))
+}
+
+/// The variable's name, centered in the column.
+fn header_cell(column: &Column) -> String {
+    let width = column.pad_left + column.len + column.pad_right;
+    let name: String = column.name.chars().take(width).collect();
+
+    let space = width - name.chars().count();
+    let left = space / 2;
+    let right = space - left;
+    format!("{}{name}{}", " ".repeat(left), " ".repeat(right))
+}
+
+fn value_cell(column: &Column, value: i64) -> String {
+    let len = column.len;
+
+    let text = match column.format {
+        Format::Decimal => format!("{value:>len$}"),
+        Format::String => format!("{value:<len$}"),
+        Format::Hex => last_chars(format!("{:0len$X}", value as u16), len),
+        Format::Binary => last_chars(format!("{:0len$b}", value as u16), len),
+    };
+
+    format!(
+        "{}{text}{}",
+        " ".repeat(column.pad_left),
+        " ".repeat(column.pad_right)
+    )
+}
+
+fn last_chars(s: String, n: usize) -> String {
+    let skip = s.len().saturating_sub(n);
+    s[skip..].to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
+
+    const SCRIPT: &str = "\
+load Mult.asm,
+output-file Mult.out,
+compare-to Mult.cmp,
+output-list RAM[0]%D2.6.2 RAM[1]%D2.6.2 RAM[2]%D2.6.2 time%S1.4.1;
+
+set RAM[0] 3,   // Set test arguments
+set RAM[1] 5,
+set RAM[2] -1;
+/* Run until done. */
+while PC <> 14 {
+    ticktock;
+}
+set RAM[0] 3,
+output;
+";
+
+    /// Write the script, Mult.asm, and the compare file to a fresh directory,
+    /// then run the script.
+    fn run_mult(name: &str, cmp: &str) -> Result<String> {
+        let dir = TempDir::new(name)?;
+        fs::write(dir.join("Mult.tst"), SCRIPT)?;
+        fs::write(dir.join("Mult.cmp"), cmp)?;
+        fs::write(
+            dir.join("Mult.asm"),
+            include_str!("../../../../04/mult/Mult.asm"),
+        )?;
+
+        run_script(dir.join("Mult.tst"))?;
+        Ok(fs::read_to_string(dir.join("Mult.out"))?)
+    }
+
+    #[test]
+    fn mult() -> Result<()> {
+        let expected = "\
+|  RAM[0]  |  RAM[1]  |  RAM[2]  | time |
+|       3  |       5  |      15  | 42   |
+";
+        assert_eq!(run_mult("mult", expected)?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn wildcards() -> Result<()> {
+        let cmp = "\
+|  RAM[0]  |  RAM[1]  |  RAM[2]  | time |
+|       3  |       5  |      15  |******|
+";
+        run_mult("wildcards", cmp)?;
+
+        let cmp = cmp.replace("15", "16");
+        let err = run_mult("mismatch", &cmp).unwrap_err();
+        assert!(err.to_string().contains("comparison failure at line 2"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn unbounded_repeat() {
+        let err = "repeat { ticktock; }".parse::<Script>().unwrap_err();
+        assert!(format!("{err:#}").contains("unbounded repeat is not supported"));
+    }
+
+    #[test]
+    fn snapshots() -> Result<()> {
+        let dir = TempDir::new("snapshots")?;
+        fs::write(
+            dir.join("Mult.asm"),
+            include_str!("../../../../04/mult/Mult.asm"),
+        )?;
+        // Stop part way through, then finish from the snapshot.
+        fs::write(
+            dir.join("Setup.tst"),
+            "load Mult.asm, set RAM[0] 3, set RAM[1] 5; \
+             repeat 20 { ticktock; } save-snapshot Mult.snap;",
+        )?;
+        fs::write(
+            dir.join("Finish.tst"),
+            "load-snapshot Mult.snap, output-file Finish.out, output-list RAM[2]%D1.6.1 time%D1.6.1; \
+             while PC <> 14 { ticktock; } output;",
+        )?;
+
+        run_script(dir.join("Setup.tst"))?;
+        run_script(dir.join("Finish.tst"))?;
+        let output = fs::read_to_string(dir.join("Finish.out"))?;
+        assert_eq!(output, "| RAM[2] |  time  |\n|     15 |     42 |\n");
+        Ok(())
+    }
+}
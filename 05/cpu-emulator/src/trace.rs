@@ -0,0 +1,307 @@
+//! Traces of every executed instruction, as JSON Lines or VCD.
+//!
+//! JSON Lines traces have one object per instruction, e.g.
+//!
+//! ```text
+//! {"a":1,"cycle":7,"d":3,"instr":"D=M","pc":5,"word":64528,"writes":[]}
+//! ```
+//!
+//! where `a` and `d` are the registers after the instruction executes. Keys
+//! are in alphabetical order.
+//!
+//! VCD traces show the pins of `05/CPU.hdl`, along with the A and D
+//! registers, so they can be viewed alongside waveforms from the HDL
+//! simulator. As in the hardware, the registers show their values during the
+//! cycle, before the clock edge. There's one time step per cycle.
+
+use std::{io::Write, ops::Range, path::Path};
+
+use anyhow::{bail, ensure, Context, Result};
+use assembler::Instr;
+use serde_json::json;
+
+use crate::{program::Program, Computer};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceFormat {
+    JsonLines,
+    Vcd,
+}
+
+impl TraceFormat {
+    /// Choose a format based on the file extension: `.jsonl` or `.vcd`.
+    pub fn from_path(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("jsonl") => Ok(Self::JsonLines),
+            Some("vcd") => Ok(Self::Vcd),
+            _ => bail!(
+                "trace must have .jsonl or .vcd extension: {}",
+                path.display()
+            ),
+        }
+    }
+}
+
+/// Which ROM addresses to trace.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceFilter {
+    ranges: Vec<Range<u16>>,
+}
+
+impl TraceFilter {
+    /// Parse a comma-separated list of ROM address ranges. Each is either
+    /// `START..END` (excluding `END`, which must be after `START`), where each
+    /// end is a number or a label, or a label on its own, meaning all the code
+    /// from it up to the next label.
+    pub fn parse(text: &str, program: &Program) -> Result<Self> {
+        let ranges = text
+            .split(',')
+            .map(|part| {
+                let part = part.trim();
+                let range = match part.split_once("..") {
+                    Some((start, end)) => {
+                        let range = program.rom_address(start)?..program.rom_address(end)?;
+                        ensure!(!range.is_empty(), "empty range {part:?}");
+                        range
+                    }
+                    None => label_region(part, program)?,
+                };
+                Ok(range)
+            })
+            .collect::<Result<_>>()
+            .with_context(|| format!("invalid trace filter {text:?}"))?;
+
+        Ok(Self { ranges })
+    }
+
+    pub fn contains(&self, pc: u16) -> bool {
+        self.ranges.iter().any(|range| range.contains(&pc))
+    }
+}
+
+/// The code from `label` up to the next label, or to the end of the program.
+fn label_region(label: &str, program: &Program) -> Result<Range<u16>> {
+    let start = program.label(label)?;
+    let labels = program.labels();
+    let end = labels
+        .iter()
+        .map(|&(_, address)| address)
+        .find(|&address| address > start)
+        .unwrap_or(program.words.len() as u16);
+    Ok(start..end)
+}
+
+pub struct Tracer<W: Write> {
+    out: W,
+    format: TraceFormat,
+    filter: Option<TraceFilter>,
+
+    /// The VCD values last written, in the order of `VCD_SIGNALS`. `None`
+    /// until the first time step.
+    prev: Option<[Option<u16>; VCD_SIGNALS.len()]>,
+}
+
+/// The name, width, and identifier code of each VCD signal.
+const VCD_SIGNALS: [(&str, u8, char); 8] = [
+    ("pc", 15, 'p'),
+    ("instruction", 16, 'i'),
+    ("inM", 16, 'n'),
+    ("outM", 16, 'o'),
+    ("writeM", 1, 'w'),
+    ("addressM", 15, 'm'),
+    ("A", 16, 'a'),
+    ("D", 16, 'd'),
+];
+
+impl<W: Write> Tracer<W> {
+    pub fn new(mut out: W, format: TraceFormat, filter: Option<TraceFilter>) -> Result<Self> {
+        if format == TraceFormat::Vcd {
+            writeln!(out, "$version Hack CPU emulator $end")?;
+            writeln!(out, "$timescale 1 ns $end")?;
+            writeln!(out, "$scope module CPU $end")?;
+            for (name, width, id) in VCD_SIGNALS {
+                writeln!(out, "$var wire {width} {id} {name} $end")?;
+            }
+            writeln!(out, "$upscope $end")?;
+            writeln!(out, "$enddefinitions $end")?;
+        }
+
+        Ok(Self {
+            out,
+            format,
+            filter,
+            prev: None,
+        })
+    }
+
+    /// Execute one instruction, tracing it unless it's filtered out.
+    pub fn step(&mut self, computer: &mut Computer) -> Result<()> {
+        let pc = computer.pc();
+        if self
+            .filter
+            .as_ref()
+            .is_some_and(|filter| !filter.contains(pc))
+        {
+            return computer.step();
+        }
+
+        let cycle = computer.cycles();
+        let word = computer.rom()[usize::from(pc)];
+        let (a, d) = (computer.a(), computer.d());
+        let address_m = a % 0x8000;
+        let stored = computer.ram()[usize::from(address_m)];
+
+        computer.step()?;
+
+        // If the instruction read `M`, that's what the CPU saw, which may
+        // have come from a device rather than RAM.
+        let access = computer.last_access();
+        let in_m = access.read.unwrap_or(stored);
+        let write = access.write.map(|(address, _)| address);
+        let out_m = access.write.map(|(_, value)| value);
+        match self.format {
+            TraceFormat::JsonLines => {
+                let instr = Instr::decode(word)
+                    .map_or_else(|_| "<invalid>".to_owned(), |instr| instr.to_string());
+                let writes: Vec<_> = (access.write.iter())
+                    .map(|&(address, value)| json!({ "address": address, "value": value }))
+                    .collect();
+                let record = json!({
+                    "cycle": cycle,
+                    "pc": pc,
+                    "word": word,
+                    "instr": instr,
+                    "a": computer.a(),
+                    "d": computer.d(),
+                    "writes": writes,
+                });
+                serde_json::to_writer(&mut self.out, &record)?;
+                writeln!(self.out)?;
+            }
+            TraceFormat::Vcd => {
+                let values = [
+                    Some(pc),
+                    Some(word),
+                    Some(in_m),
+                    out_m,
+                    Some(u16::from(write.is_some())),
+                    Some(address_m),
+                    Some(a),
+                    Some(d),
+                ];
+                self.write_vcd(cycle, values)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write the signals that changed since the last time step.
+    fn write_vcd(&mut self, cycle: u64, values: [Option<u16>; VCD_SIGNALS.len()]) -> Result<()> {
+        writeln!(self.out, "#{cycle}")?;
+        for (i, (_, width, id)) in VCD_SIGNALS.into_iter().enumerate() {
+            if self.prev.is_some_and(|prev| prev[i] == values[i]) {
+                continue;
+            }
+            match (values[i], width) {
+                (Some(value), 1) => writeln!(self.out, "{value}{id}")?,
+                (Some(value), _) => writeln!(self.out, "b{value:b} {id}")?,
+                (None, 1) => writeln!(self.out, "x{id}")?,
+                (None, _) => writeln!(self.out, "bx {id}")?,
+            }
+        }
+        self.prev = Some(values);
+        Ok(())
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Device, Timer, TIMER};
+
+    fn trace(format: TraceFormat, filter: Option<&str>, cycles: u64) -> Result<String> {
+        let program = Program::from_asm(include_str!("../../../04/mult/Mult.asm"))?;
+        let mut computer = Computer::new(&program.words)?;
+        computer.ram_mut()[0] = 2;
+        computer.ram_mut()[1] = 3;
+
+        let filter = filter
+            .map(|filter| TraceFilter::parse(filter, &program))
+            .transpose()?;
+        let mut tracer = Tracer::new(vec![], format, filter)?;
+        for _ in 0..cycles {
+            tracer.step(&mut computer)?;
+        }
+        Ok(String::from_utf8(tracer.into_inner())?)
+    }
+
+    #[test]
+    fn json_lines() -> Result<()> {
+        let trace = trace(TraceFormat::JsonLines, None, 2)?;
+        assert_eq!(
+            trace,
+            r#"{"a":2,"cycle":0,"d":0,"instr":"@2","pc":0,"word":2,"writes":[]}
+{"a":2,"cycle":1,"d":0,"instr":"M=0","pc":1,"word":60040,"writes":[{"address":2,"value":0}]}
+"#
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn filter() -> Result<()> {
+        // Only the two instructions that decrement R0.
+        let decrements = trace(TraceFormat::JsonLines, Some("10..12"), 100)?;
+        assert_eq!(decrements.lines().count(), 4);
+        assert!(decrements.contains(r#""instr":"M=M-1""#));
+
+        // The loop runs twice, then exits at the third check.
+        let start = trace(TraceFormat::JsonLines, Some("END, 0..2"), 28)?;
+        assert_eq!(start.lines().count(), 2);
+        let body = trace(TraceFormat::JsonLines, Some("LOOP"), 42)?;
+        assert_eq!(body.lines().count(), 12 * 2 + 4);
+
+        for filter in ["12..10", "10..10", "R0", "0..SP"] {
+            assert!(
+                trace(TraceFormat::JsonLines, Some(filter), 0).is_err(),
+                "{filter}"
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn vcd() -> Result<()> {
+        let trace = trace(TraceFormat::Vcd, None, 2)?;
+        let (header, body) = trace.split_once("$enddefinitions $end\n").unwrap();
+        assert!(header.contains("$var wire 15 p pc $end"));
+        assert_eq!(
+            body,
+            "#0\nb0 p\nb10 i\nb10 n\nbx o\n0w\nb0 m\nb0 a\nb0 d\n\
+             #1\nb1 p\nb1110101010001000 i\nb0 n\nb0 o\n1w\nb10 m\nb10 a\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn device_reads() -> Result<()> {
+        let devices: Vec<Box<dyn Device>> = vec![Box::new(Timer::new(TIMER, 1)?)];
+        let program = Program::from_asm_with_devices("@TIMER\nD=M\n", &devices)?;
+        let mut computer = Computer::with_devices(&program.words, devices)?;
+
+        let mut tracer = Tracer::new(vec![], TraceFormat::Vcd, None)?;
+        tracer.step(&mut computer)?;
+        tracer.step(&mut computer)?;
+        let trace = String::from_utf8(tracer.into_inner())?;
+
+        // inM is the timer's count, not the zero stored in RAM.
+        let (_, last) = trace.split_once("#1\n").unwrap();
+        assert!(last.contains("b1 n\n"), "{last}");
+        Ok(())
+    }
+}
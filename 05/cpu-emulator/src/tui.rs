@@ -0,0 +1,493 @@
+//! A terminal user interface, for running programs without a desktop.
+//!
+//! Shows the screen (scaled down, using braille or half-block characters),
+//! the registers, a disassembly around the PC, and a panel of watched RAM
+//! addresses. Keys typed in the terminal are pressed on the Hack keyboard.
+//!
+//! Terminals don't report key releases, so each key is held down for a short
+//! time after it's typed, or for as long as it auto-repeats.
+
+use std::time::{Duration, Instant};
+
+use anyhow::Result;
+use assembler::{Instr, SymbolKind};
+use ratatui::{
+    buffer::Buffer,
+    crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    layout::{Constraint, Layout, Rect},
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+    widgets::{Block, Paragraph, Widget},
+    DefaultTerminal, Frame,
+};
+
+use crate::{
+    keyboard::{parse_key, KBD},
+    program::Program,
+    screen::{HEIGHT, WIDTH},
+    Computer, Screen,
+};
+
+/// How long to spend running the program between frames.
+const FRAME_TIME: Duration = Duration::from_millis(30);
+
+/// How many instructions to run at a time, while running.
+const CHUNK: u64 = 10_000;
+
+/// How long a key stays pressed after it's typed.
+const KEY_HOLD: Duration = Duration::from_millis(150);
+
+const HELP: &str = "^R run/pause  ^N step  ^B braille/half-block  ^Q quit  other keys: keyboard";
+
+pub struct Tui {
+    program: Program,
+    computer: Computer,
+
+    /// RAM addresses to show in the watch panel.
+    watches: Vec<u16>,
+
+    running: bool,
+    braille: bool,
+
+    /// When to release the key currently pressed, if any.
+    release_at: Option<Instant>,
+
+    /// The last error, e.g. from an invalid instruction.
+    error: Option<String>,
+}
+
+impl Tui {
+    /// Watches `R0` to `R15` and the program's variables, to begin with.
+    pub fn new(program: Program) -> Result<Self> {
+        let computer = Computer::new(&program.words)?;
+        let mut watches: Vec<u16> = (0..16).collect();
+        if let Some(symbol_table) = &program.symbol_table {
+            watches.extend(
+                symbol_table
+                    .symbols(SymbolKind::Variable)
+                    .into_iter()
+                    .map(|(_, address)| address),
+            );
+        }
+        Ok(Self {
+            program,
+            computer,
+            watches,
+            running: false,
+            braille: true,
+            release_at: None,
+            error: None,
+        })
+    }
+
+    /// Replace the RAM addresses shown in the watch panel.
+    pub fn set_watches(&mut self, watches: Vec<u16>) {
+        self.watches = watches;
+    }
+
+    /// Take over the terminal until the user quits.
+    pub fn run(mut self) -> Result<()> {
+        let mut terminal = ratatui::try_init()?;
+        let result = self.event_loop(&mut terminal);
+        ratatui::try_restore()?;
+        result
+    }
+
+    fn event_loop(&mut self, terminal: &mut DefaultTerminal) -> Result<()> {
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+
+            if self.running {
+                let start = Instant::now();
+                while self.running && start.elapsed() < FRAME_TIME {
+                    if let Err(e) = self.computer.run_fast(CHUNK) {
+                        self.error = Some(format!("{e:#}"));
+                        self.running = false;
+                    }
+                }
+            }
+
+            let timeout = if self.running {
+                Duration::ZERO
+            } else {
+                FRAME_TIME
+            };
+            while event::poll(timeout)? {
+                if let Event::Key(key) = event::read()? {
+                    if !self.handle_key(key) {
+                        return Ok(());
+                    }
+                }
+                if !self.running {
+                    break;
+                }
+            }
+
+            if self.release_at.is_some_and(|at| Instant::now() >= at) {
+                self.set_key(0);
+                self.release_at = None;
+            }
+        }
+    }
+
+    /// Returns false to quit.
+    fn handle_key(&mut self, key: KeyEvent) -> bool {
+        if key.modifiers.contains(KeyModifiers::CONTROL) {
+            match key.code {
+                KeyCode::Char('q' | 'c') => return false,
+                KeyCode::Char('r') => {
+                    self.running = !self.running;
+                    self.error = None;
+                }
+                KeyCode::Char('n') if !self.running => {
+                    self.error = self.computer.step().err().map(|e| format!("{e:#}"));
+                }
+                KeyCode::Char('b') => self.braille = !self.braille,
+                _ => {}
+            }
+            return true;
+        }
+
+        let Some(code) = hack_key(key.code) else {
+            return true;
+        };
+        if key.kind == KeyEventKind::Release {
+            self.set_key(0);
+            self.release_at = None;
+        } else {
+            self.set_key(code);
+            self.release_at = Some(Instant::now() + KEY_HOLD);
+        }
+        true
+    }
+
+    fn set_key(&mut self, code: u16) {
+        self.computer.ram_mut()[usize::from(KBD)] = code;
+    }
+
+    fn draw(&self, frame: &mut Frame) {
+        let [main, help_area] =
+            Layout::vertical([Constraint::Fill(1), Constraint::Length(1)]).areas(frame.area());
+        let [screen, side] =
+            Layout::horizontal([Constraint::Fill(2), Constraint::Fill(1)]).areas(main);
+        let [registers, code, watches] = Layout::vertical([
+            Constraint::Length(7),
+            Constraint::Fill(2),
+            Constraint::Fill(1),
+        ])
+        .areas(side);
+
+        let block = Block::bordered().title(" Screen ");
+        frame.render_widget(
+            ScreenView {
+                screen: self.computer.screen(),
+                braille: self.braille,
+            },
+            block.inner(screen),
+        );
+        frame.render_widget(block, screen);
+
+        frame.render_widget(self.registers(), registers);
+        frame.render_widget(self.disassembly(code.height.saturating_sub(2)), code);
+        frame.render_widget(self.watches(), watches);
+
+        let help = match &self.error {
+            Some(error) => Line::styled(error.as_str(), Style::new().fg(Color::Red)),
+            None => Line::styled(HELP, Style::new().add_modifier(Modifier::DIM)),
+        };
+        frame.render_widget(help, help_area);
+    }
+
+    fn registers(&self) -> Paragraph<'_> {
+        let computer = &self.computer;
+        let state = if self.running { "running" } else { "paused" };
+        let pc = computer.pc();
+        let lines = vec![
+            Line::raw(format!(
+                "A  = {:6} ({:#06x})",
+                computer.a() as i16,
+                computer.a()
+            )),
+            Line::raw(format!(
+                "D  = {:6} ({:#06x})",
+                computer.d() as i16,
+                computer.d()
+            )),
+            Line::raw(format!("PC = {pc:6} ({})", self.program.describe_rom(pc))),
+            Line::raw(format!("KBD = {}", computer.ram()[usize::from(KBD)])),
+            Line::raw(format!("{} cycles, {state}", computer.cycles())),
+        ];
+        Paragraph::new(lines).block(Block::bordered().title(" Registers "))
+    }
+
+    /// The instructions around the PC, with label names. `height` is how
+    /// many lines fit.
+    fn disassembly(&self, height: u16) -> Paragraph<'_> {
+        let pc = self.computer.pc();
+        let rom = self.computer.rom();
+        let labels = self.program.labels();
+
+        // Start a little before the PC, so there's some context.
+        let start = pc.saturating_sub(height / 3);
+        let mut lines = vec![];
+        let end = self
+            .program
+            .words
+            .len()
+            .max(usize::from(pc) + 1)
+            .min(rom.len());
+        for address in start..end as u16 {
+            if lines.len() >= usize::from(height) {
+                break;
+            }
+            for (label, _) in labels.iter().filter(|&&(_, a)| a == address) {
+                lines.push(Line::styled(
+                    format!("({label})"),
+                    Style::new().fg(Color::Yellow),
+                ));
+            }
+
+            let word = rom[usize::from(address)];
+            let mut text = match Instr::decode(word) {
+                Ok(instr) => instr.to_string(),
+                Err(_) => "<invalid>".to_owned(),
+            };
+            if let Some(name) = self.a_instr_name(address) {
+                text = format!("{text:12} // {name}");
+            }
+
+            let marker = if address == pc { ">" } else { " " };
+            let line = format!("{marker}{address:5}  {text}");
+            lines.push(if address == pc {
+                Line::styled(line, Style::new().add_modifier(Modifier::REVERSED))
+            } else {
+                Line::raw(line)
+            });
+        }
+
+        Paragraph::new(lines).block(Block::bordered().title(" Code "))
+    }
+
+    /// A name for the value loaded by an A-instruction: a label if the next
+    /// instruction jumps, otherwise a RAM address.
+    fn a_instr_name(&self, address: u16) -> Option<String> {
+        let rom = self.computer.rom();
+        let value = rom[usize::from(address)];
+        if value & 0x8000 != 0 {
+            return None;
+        }
+
+        let next = rom.get(usize::from(address) + 1).copied().unwrap_or(0);
+        let jumps = next & 0x8000 != 0 && next & 0b111 != 0;
+        if jumps {
+            let labels = self.program.labels();
+            let (label, _) = labels.iter().find(|&&(_, a)| a == value)?;
+            return Some(label.clone());
+        }
+        let names = self.program.ram_names(value);
+        (!names.is_empty()).then(|| names.join("/"))
+    }
+
+    fn watches(&self) -> Paragraph<'_> {
+        let ram = self.computer.ram();
+        let lines: Vec<_> = self
+            .watches
+            .iter()
+            .map(|&address| {
+                let names = self.program.ram_names(address);
+                let name = if names.is_empty() {
+                    format!("RAM[{address}]")
+                } else {
+                    names.join("/")
+                };
+                let value = ram[usize::from(address)];
+                Line::from(vec![
+                    Span::styled(format!("{name:>12}"), Style::new().fg(Color::Cyan)),
+                    Span::raw(format!(" = {}", value as i16)),
+                ])
+            })
+            .collect();
+        Paragraph::new(lines).block(Block::bordered().title(" RAM "))
+    }
+}
+
+/// The Hack key code for a terminal key, if it has one.
+fn hack_key(code: KeyCode) -> Option<u16> {
+    let name = match code {
+        KeyCode::Char(c) if c == ' ' || c.is_ascii_graphic() => return Some(c as u16),
+        KeyCode::Enter => "NEWLINE",
+        KeyCode::Backspace => "BACKSPACE",
+        KeyCode::Left => "LEFT",
+        KeyCode::Up => "UP",
+        KeyCode::Right => "RIGHT",
+        KeyCode::Down => "DOWN",
+        KeyCode::Home => "HOME",
+        KeyCode::End => "END",
+        KeyCode::PageUp => "PAGEUP",
+        KeyCode::PageDown => "PAGEDOWN",
+        KeyCode::Insert => "INSERT",
+        KeyCode::Delete => "DELETE",
+        KeyCode::Esc => "ESC",
+        KeyCode::F(n) => return parse_key(&format!("F{n}")).ok(),
+        _ => return None,
+    };
+    parse_key(name).ok()
+}
+
+/// The screen, scaled down to fit. Each character shows a block of pixels,
+/// which is drawn black if any of its pixels are.
+struct ScreenView<'a> {
+    screen: Screen<'a>,
+
+    /// Braille characters have 2 x 4 dots; half blocks have 1 x 2.
+    braille: bool,
+}
+
+impl Widget for ScreenView<'_> {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let (dots_x, dots_y) = if self.braille { (2, 4) } else { (1, 2) };
+        let width = usize::from(area.width) * dots_x;
+        let height = usize::from(area.height) * dots_y;
+        if width == 0 || height == 0 {
+            return;
+        }
+
+        // Pixels per dot, in each direction.
+        let scale = WIDTH.div_ceil(width).max(HEIGHT.div_ceil(height)).max(1);
+        let dot = |x: usize, y: usize| {
+            (y * scale..((y + 1) * scale).min(HEIGHT)).any(|py| {
+                (x * scale..((x + 1) * scale).min(WIDTH)).any(|px| self.screen.pixel(px, py))
+            })
+        };
+
+        // The screen is black on white, whatever the terminal's colors.
+        let style = Style::new().fg(Color::Black).bg(Color::White);
+        let columns = (WIDTH.div_ceil(scale))
+            .div_ceil(dots_x)
+            .min(usize::from(area.width));
+        let rows = (HEIGHT.div_ceil(scale))
+            .div_ceil(dots_y)
+            .min(usize::from(area.height));
+        for row in 0..rows {
+            for column in 0..columns {
+                let (x, y) = (column * dots_x, row * dots_y);
+                let c = if self.braille {
+                    braille(|dx, dy| dot(x + dx, y + dy))
+                } else {
+                    match (dot(x, y), dot(x, y + 1)) {
+                        (false, false) => ' ',
+                        (true, false) => '▀',
+                        (false, true) => '▄',
+                        (true, true) => '█',
+                    }
+                };
+                buf[(area.x + column as u16, area.y + row as u16)]
+                    .set_char(c)
+                    .set_style(style);
+            }
+        }
+    }
+}
+
+/// A braille character, with the dots for which `dot(x, y)` is true, where
+/// `x` is 0 or 1, and `y` is 0 to 3.
+fn braille(dot: impl Fn(usize, usize) -> bool) -> char {
+    const BITS: [[u32; 4]; 2] = [[0x01, 0x02, 0x04, 0x40], [0x08, 0x10, 0x20, 0x80]];
+
+    let mut bits = 0;
+    for (x, column) in BITS.iter().enumerate() {
+        for (y, bit) in column.iter().enumerate() {
+            if dot(x, y) {
+                bits |= bit;
+            }
+        }
+    }
+    char::from_u32(0x2800 + bits).expect("braille patterns are valid characters")
+}
+
+#[cfg(test)]
+mod tests {
+    use ratatui::{backend::TestBackend, Terminal};
+
+    use super::*;
+    use crate::screen::SCREEN;
+
+    fn render(tui: &Tui, width: u16, height: u16) -> Result<String> {
+        let mut terminal = Terminal::new(TestBackend::new(width, height))?;
+        terminal.draw(|frame| tui.draw(frame))?;
+        let buffer = terminal.backend().buffer();
+        let lines: Vec<String> = (0..buffer.area.height)
+            .map(|y| {
+                (0..buffer.area.width)
+                    .map(|x| buffer[(x, y)].symbol())
+                    .collect()
+            })
+            .collect();
+        Ok(lines.join("\n"))
+    }
+
+    #[test]
+    fn screen() -> Result<()> {
+        let mut ram = vec![0; crate::MEMORY_SIZE];
+        // The top-left pixel, and the whole first row of the right half.
+        let start = usize::from(SCREEN);
+        ram[start] = 1;
+        ram[start + 16..start + 32].fill(0xffff);
+
+        // 512 x 256 pixels, at 2 x 4 dots per character, fits 256 x 64
+        // characters without scaling.
+        let mut buf = Buffer::empty(Rect::new(0, 0, 256, 64));
+        let view = ScreenView {
+            screen: Screen::new(&ram),
+            braille: true,
+        };
+        view.render(buf.area, &mut buf);
+        assert_eq!(buf[(0, 0)].symbol(), "\u{2801}");
+        assert_eq!(buf[(1, 0)].symbol(), "\u{2800}");
+        assert_eq!(buf[(128, 0)].symbol(), "\u{2809}");
+        assert_eq!(buf[(255, 0)].symbol(), "\u{2809}");
+        assert_eq!(buf[(0, 1)].symbol(), "\u{2800}");
+
+        // Scaled down by 4, with half blocks, any black pixel in a 4 x 4
+        // block shows.
+        let mut buf = Buffer::empty(Rect::new(0, 0, 128, 32));
+        let view = ScreenView {
+            screen: Screen::new(&ram),
+            braille: false,
+        };
+        view.render(buf.area, &mut buf);
+        assert_eq!(buf[(0, 0)].symbol(), "▀");
+        assert_eq!(buf[(1, 0)].symbol(), " ");
+        assert_eq!(buf[(64, 0)].symbol(), "▀");
+        assert_eq!(buf[(0, 31)].symbol(), " ");
+        Ok(())
+    }
+
+    #[test]
+    fn panels() -> Result<()> {
+        let program = Program::from_asm("@i\nM=1\n(LOOP)\n@LOOP\n0;JMP\n")?;
+        let mut tui = Tui::new(program)?;
+        assert_eq!(tui.watches.len(), 17);
+        tui.computer.run(3)?;
+
+        let text = render(&tui, 120, 40)?;
+        assert!(text.contains("PC =      3 (LOOP+1)"), "{text}");
+        assert!(text.contains("(LOOP)"), "{text}");
+        assert!(text.contains(">    3  0;JMP"), "{text}");
+        assert!(text.contains("@2           // LOOP"), "{text}");
+        assert!(text.contains("@16          // i"), "{text}");
+        assert!(!text.contains("     4  "), "{text}");
+
+        tui.set_watches(vec![16]);
+        let text = render(&tui, 120, 40)?;
+        assert!(text.contains("           i = 1"), "{text}");
+
+        // Keys typed are pressed on the keyboard.
+        tui.handle_key(KeyEvent::from(KeyCode::Char('x')));
+        assert_eq!(tui.computer.ram()[usize::from(KBD)], u16::from(b'x'));
+        tui.handle_key(KeyEvent::from(KeyCode::Left));
+        assert_eq!(tui.computer.ram()[usize::from(KBD)], 130);
+        assert!(!tui.handle_key(KeyEvent::new(KeyCode::Char('q'), KeyModifiers::CONTROL)));
+        Ok(())
+    }
+}
@@ -0,0 +1,101 @@
+//! Noticing when a program is stuck in a loop.
+//!
+//! A program which loops without writing to RAM can never do anything else:
+//! if the registers ever repeat, everything after that repeats too. That
+//! assumes its input doesn't change, so it doesn't apply while keys are being
+//! pressed, or with devices such as the timer.
+//!
+//! Repeats are found with Brent's cycle detection algorithm, which compares
+//! the registers against a checkpoint, moved at doubling intervals. So a loop
+//! is noticed within a few times its length, using constant memory.
+
+use assembler::Instr;
+
+use crate::Computer;
+
+#[derive(Default)]
+pub struct Watchdog {
+    /// The PC, A and D at the checkpoint, taken since the last RAM write.
+    checkpoint: Option<(u16, u16, u16)>,
+
+    /// How many instructions have run since the checkpoint.
+    steps: u64,
+
+    /// How many instructions to run before moving the checkpoint.
+    window: u64,
+}
+
+impl Watchdog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Call before each instruction is executed. Returns whether the program
+    /// has looped back to an earlier state, without writing to RAM since.
+    pub fn is_stuck(&mut self, computer: &Computer) -> bool {
+        let pc = computer.pc();
+        let writes_ram = Instr::decode(computer.rom()[usize::from(pc)])
+            .is_ok_and(|instr| instr.c_instr().is_some_and(|c| c.dest().m));
+        if writes_ram {
+            self.checkpoint = None;
+            return false;
+        }
+
+        let state = (pc, computer.a(), computer.d());
+        let Some(checkpoint) = self.checkpoint else {
+            self.checkpoint = Some(state);
+            self.steps = 0;
+            self.window = 1;
+            return false;
+        };
+        if state == checkpoint {
+            return true;
+        }
+
+        self.steps += 1;
+        if self.steps == self.window {
+            self.checkpoint = Some(state);
+            self.steps = 0;
+            self.window *= 2;
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::*;
+    use crate::program::Program;
+
+    /// Run until the watchdog notices, returning the cycle.
+    fn stuck_at(source: &str, max_cycles: u64) -> Result<Option<u64>> {
+        let program = Program::from_asm(source)?;
+        let mut computer = Computer::new(&program.words)?;
+        let mut watchdog = Watchdog::new();
+        for _ in 0..max_cycles {
+            if watchdog.is_stuck(&computer) {
+                return Ok(Some(computer.cycles()));
+            }
+            computer.step()?;
+        }
+        Ok(None)
+    }
+
+    #[test]
+    fn loops() -> Result<()> {
+        // Counts down, and then waits for a key forever.
+        let source = "\
+            @100\nD=A\n\
+            (COUNT)\nD=D-1\n@COUNT\nD;JGT\n\
+            (WAIT)\n@KBD\nD=M\n@WAIT\nD;JEQ\n";
+        let cycle = stuck_at(source, 10_000)?.expect("waiting forever");
+        assert!((302..1000).contains(&cycle), "{cycle}");
+
+        // Writing to RAM is progress, even if it's the same value.
+        let source = "(LOOP)\n@x\nM=0\n@LOOP\n0;JMP\n";
+        assert_eq!(stuck_at(source, 10_000)?, None);
+        Ok(())
+    }
+}
@@ -0,0 +1,74 @@
+//! Benchmark assembling a large program, similar in size and shape to the
+//! output of the VM translator.
+//!
+//! As a baseline, the same program is also assembled from file to file the
+//! way the assembler used to: reading and parsing the source in both passes,
+//! and writing each instruction straight to the output file.
+
+#[path = "../tests/support/mod.rs"]
+mod support;
+
+use std::{
+    env,
+    fs::{self, File},
+    io::{prelude::*, BufReader, BufWriter},
+    path::Path,
+    process,
+};
+
+use anyhow::Result;
+use assembler::Isa;
+use criterion::{criterion_group, criterion_main, Criterion};
+
+/// Assemble `in_path` into `out_path`, as `main` does.
+fn assemble_file(in_path: &Path, out_path: &Path, isa: Isa) -> Result<()> {
+    let in_file = BufReader::new(File::open(in_path)?);
+    let assembled = assembler::assemble(in_file, isa)?;
+
+    let mut out = BufWriter::new(File::create(out_path)?);
+    for code in &assembled.words {
+        writeln!(out, "{code:0>16b}")?;
+    }
+    out.flush()?;
+    Ok(())
+}
+
+/// Assemble `in_path` into `out_path` in two passes, each of which reads and
+/// parses the whole file.
+fn assemble_file_two_pass(in_path: &Path, out_path: &Path, isa: Isa) -> Result<()> {
+    let in_file = BufReader::new(File::open(in_path)?);
+    let out_file = BufWriter::new(File::create(out_path)?);
+    support::assemble_two_pass(in_file, out_file, isa)
+}
+
+fn assemble(c: &mut Criterion) {
+    let dir = env::temp_dir().join(format!("assembler-bench-{}", process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let in_path = dir.join("Large.asm");
+    let out_path = dir.join("Large.hack");
+    let program = support::large_program();
+    fs::write(&in_path, &program).unwrap();
+
+    // Both write the same output.
+    assemble_file_two_pass(&in_path, &out_path, Isa::Strict).unwrap();
+    let expected = fs::read(&out_path).unwrap();
+    assemble_file(&in_path, &out_path, Isa::Strict).unwrap();
+    assert!(fs::read(&out_path).unwrap() == expected);
+
+    let mut group = c.benchmark_group("assemble 30K lines");
+    group.bench_function("in memory", |b| {
+        b.iter(|| assembler::assemble(program.as_bytes(), Isa::Strict).unwrap())
+    });
+    group.bench_function("file to file", |b| {
+        b.iter(|| assemble_file(&in_path, &out_path, Isa::Strict).unwrap())
+    });
+    group.bench_function("file to file, two passes (baseline)", |b| {
+        b.iter(|| assemble_file_two_pass(&in_path, &out_path, Isa::Strict).unwrap())
+    });
+    group.finish();
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+criterion_group!(benches, assemble);
+criterion_main!(benches);
@@ -0,0 +1,420 @@
+//! Runtime assertions, written as structured comments.
+//!
+//! A comment of the form `//! assert CONDITION` attaches a condition to the
+//! next instruction, e.g.
+//!
+//! ```text
+//! //! assert RAM[SP] == 256
+//! @SP
+//! D=M   //! assert D >= 0
+//! ```
+//!
+//! An emulator can check each condition whenever the PC reaches its
+//! instruction, before executing it. So an assertion at the end of a line
+//! applies after that line's instruction, if execution falls through.
+//!
+//! A condition compares two expressions with `==`, `!=`, `<`, `<=`, `>` or
+//! `>=`, treating values as signed 16-bit numbers. Expressions are built from
+//! numbers, symbols, the registers `A`, `D` and `M` (which is `RAM[A]`),
+//! `RAM[expr]`, `+`, `-` and parentheses. Other `//!` comments are ignored.
+
+use std::{collections::BTreeMap, fmt};
+
+use anyhow::{bail, ensure, Context, Result};
+
+use crate::{instruction::ADDRESS_LIMIT, symbol_table::SymbolTable};
+
+/// A condition which should hold whenever the PC reaches an instruction.
+#[derive(Debug, Clone)]
+pub struct Assertion {
+    /// The source line the assertion was written on, 1-based.
+    pub line: usize,
+
+    /// The condition, as written.
+    pub text: String,
+
+    left: Expr,
+    op: CmpOp,
+    right: Expr,
+}
+
+/// The assertions in a program, keyed by ROM address.
+#[derive(Debug, Default)]
+pub struct Assertions {
+    by_address: BTreeMap<u16, Vec<Assertion>>,
+}
+
+#[derive(Debug, Clone)]
+enum Expr {
+    Number(u16),
+    A,
+    D,
+    M,
+    Ram(Box<Expr>),
+    Neg(Box<Expr>),
+    Add(Box<Expr>, Box<Expr>),
+    Sub(Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Assertion {
+    /// Parse the text after `//!`, e.g. `assert D >= 0`, replacing symbols
+    /// with their addresses.
+    pub fn parse(annotation: &str, line: usize, symbol_table: &SymbolTable) -> Result<Self> {
+        let Some(condition) = annotation.strip_prefix("assert") else {
+            let word = annotation.split_whitespace().next().unwrap_or_default();
+            bail!("unknown annotation {word:?}: expected `//! assert CONDITION`");
+        };
+        ensure!(
+            !condition.trim().is_empty(),
+            "expected a condition after `assert`"
+        );
+        ensure!(
+            condition.starts_with(char::is_whitespace),
+            "expected a space after `assert`"
+        );
+        let text = condition.trim().to_owned();
+
+        let tokens = tokenize(&text)?;
+        let mut parser = Parser {
+            tokens: &tokens,
+            pos: 0,
+            symbol_table,
+        };
+        let left = parser.expr()?;
+        let op = match parser.next() {
+            Some(Token::Cmp(op)) => op,
+            Some(token) => bail!("expected a comparison, found {token}"),
+            None => bail!("expected a comparison, e.g. `D >= 0`"),
+        };
+        let right = parser.expr()?;
+        if let Some(token) = parser.next() {
+            bail!("unexpected {token} after the condition");
+        }
+
+        Ok(Self {
+            line,
+            text,
+            left,
+            op,
+            right,
+        })
+    }
+
+    /// Whether the condition holds, given the CPU's registers and RAM.
+    pub fn holds(&self, a: u16, d: u16, ram: &[u16]) -> bool {
+        let (left, right) = self.values(a, d, ram);
+        match self.op {
+            CmpOp::Eq => left == right,
+            CmpOp::Ne => left != right,
+            CmpOp::Lt => left < right,
+            CmpOp::Le => left <= right,
+            CmpOp::Gt => left > right,
+            CmpOp::Ge => left >= right,
+        }
+    }
+
+    /// The condition with the values of both sides substituted, e.g.
+    /// `-3 >= 0`, to explain a failure.
+    pub fn explain(&self, a: u16, d: u16, ram: &[u16]) -> String {
+        let (left, right) = self.values(a, d, ram);
+        format!("{left} {} {right}", self.op)
+    }
+
+    fn values(&self, a: u16, d: u16, ram: &[u16]) -> (i16, i16) {
+        let left = self.left.evaluate(a, d, ram) as i16;
+        let right = self.right.evaluate(a, d, ram) as i16;
+        (left, right)
+    }
+}
+
+impl Assertions {
+    pub fn push(&mut self, address: u16, assertion: Assertion) {
+        self.by_address.entry(address).or_default().push(assertion);
+    }
+
+    /// The assertions to check when the PC reaches `address`.
+    pub fn get(&self, address: u16) -> &[Assertion] {
+        self.by_address.get(&address).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_address.is_empty()
+    }
+
+    /// Every assertion, with its ROM address, in address order.
+    pub fn iter(&self) -> impl Iterator<Item = (u16, &Assertion)> {
+        self.by_address
+            .iter()
+            .flat_map(|(&address, assertions)| assertions.iter().map(move |a| (address, a)))
+    }
+}
+
+impl Expr {
+    fn evaluate(&self, a: u16, d: u16, ram: &[u16]) -> u16 {
+        // Like the CPU, ignore the highest bit of the address.
+        let read = |address: u16| {
+            let address = usize::from(address % ADDRESS_LIMIT);
+            ram.get(address).copied().unwrap_or(0)
+        };
+        match self {
+            Expr::Number(n) => *n,
+            Expr::A => a,
+            Expr::D => d,
+            Expr::M => read(a),
+            Expr::Ram(e) => read(e.evaluate(a, d, ram)),
+            Expr::Neg(e) => e.evaluate(a, d, ram).wrapping_neg(),
+            Expr::Add(l, r) => l.evaluate(a, d, ram).wrapping_add(r.evaluate(a, d, ram)),
+            Expr::Sub(l, r) => l.evaluate(a, d, ram).wrapping_sub(r.evaluate(a, d, ram)),
+        }
+    }
+}
+
+impl fmt::Display for CmpOp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let op = match self {
+            CmpOp::Eq => "==",
+            CmpOp::Ne => "!=",
+            CmpOp::Lt => "<",
+            CmpOp::Le => "<=",
+            CmpOp::Gt => ">",
+            CmpOp::Ge => ">=",
+        };
+        f.write_str(op)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Number(u16),
+
+    /// A symbol or register name.
+    Word(String),
+
+    /// One of `(`, `)`, `[`, `]`, `+` or `-`.
+    Punct(char),
+
+    Cmp(CmpOp),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Number(n) => write!(f, "`{n}`"),
+            Token::Word(word) => write!(f, "`{word}`"),
+            Token::Punct(c) => write!(f, "`{c}`"),
+            Token::Cmp(op) => write!(f, "`{op}`"),
+        }
+    }
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>> {
+    let mut tokens = vec![];
+    let mut chars = text.char_indices().peekable();
+
+    while let Some((start, c)) = chars.next() {
+        let token = match c {
+            _ if c.is_whitespace() => continue,
+            '(' | ')' | '[' | ']' | '+' | '-' => Token::Punct(c),
+            '=' | '!' | '<' | '>' => {
+                let equals = chars.next_if(|&(_, next)| next == '=').is_some();
+                Token::Cmp(match (c, equals) {
+                    ('=', true) => CmpOp::Eq,
+                    ('!', true) => CmpOp::Ne,
+                    ('<', false) => CmpOp::Lt,
+                    ('<', true) => CmpOp::Le,
+                    ('>', false) => CmpOp::Gt,
+                    ('>', true) => CmpOp::Ge,
+                    _ => bail!("unexpected {c:?} in assertion: did you mean `{c}=`?"),
+                })
+            }
+            _ if is_word_char(c) => {
+                while chars.next_if(|&(_, c)| is_word_char(c)).is_some() {}
+                let end = chars.peek().map_or(text.len(), |&(i, _)| i);
+                let word = &text[start..end];
+                if c.is_ascii_digit() {
+                    let n: u16 = word
+                        .parse()
+                        .with_context(|| format!("invalid number {word:?} in assertion"))?;
+                    Token::Number(n)
+                } else {
+                    Token::Word(word.to_owned())
+                }
+            }
+            _ => bail!("unexpected character {c:?} in assertion"),
+        };
+        tokens.push(token);
+    }
+
+    Ok(tokens)
+}
+
+/// The characters allowed in symbols and numbers.
+fn is_word_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '$' | ':')
+}
+
+/// A recursive-descent parser for expressions.
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+    symbol_table: &'a SymbolTable,
+}
+
+impl Parser<'_> {
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn expect(&mut self, c: char) -> Result<()> {
+        match self.next() {
+            Some(Token::Punct(p)) if p == c => Ok(()),
+            Some(token) => bail!("expected `{c}`, found {token}"),
+            None => bail!("expected `{c}`"),
+        }
+    }
+
+    /// `term (('+' | '-') term)*`
+    fn expr(&mut self) -> Result<Expr> {
+        let mut expr = self.term()?;
+        loop {
+            match self.peek() {
+                Some(Token::Punct('+')) => {
+                    self.pos += 1;
+                    expr = Expr::Add(Box::new(expr), Box::new(self.term()?));
+                }
+                Some(Token::Punct('-')) => {
+                    self.pos += 1;
+                    expr = Expr::Sub(Box::new(expr), Box::new(self.term()?));
+                }
+                _ => return Ok(expr),
+            }
+        }
+    }
+
+    fn term(&mut self) -> Result<Expr> {
+        let expr = match self.next() {
+            Some(Token::Number(n)) => Expr::Number(n),
+            Some(Token::Word(word)) => match word.as_str() {
+                "A" => Expr::A,
+                "D" => Expr::D,
+                "M" => Expr::M,
+                "RAM" => {
+                    self.expect('[')?;
+                    let address = self.expr()?;
+                    self.expect(']')?;
+                    Expr::Ram(Box::new(address))
+                }
+                _ => {
+                    let address = self
+                        .symbol_table
+                        .lookup_symbol(&word)
+                        .with_context(|| format!("unknown symbol {word:?}"))?;
+                    Expr::Number(address)
+                }
+            },
+            Some(Token::Punct('-')) => Expr::Neg(Box::new(self.term()?)),
+            Some(Token::Punct('(')) => {
+                let expr = self.expr()?;
+                self.expect(')')?;
+                expr
+            }
+            Some(token) => bail!("expected a value, found {token}"),
+            None => bail!("expected a value"),
+        };
+        Ok(expr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(condition: &str) -> Result<Assertion> {
+        Assertion::parse(&format!("assert {condition}"), 1, &SymbolTable::new())
+    }
+
+    #[test]
+    fn evaluate() -> Result<()> {
+        let mut ram = vec![0; 300];
+        ram[0] = 256;
+        ram[256] = 7;
+        ram[5] = 0xffff;
+
+        assert!(parse("RAM[SP] == 256")?.holds(0, 0, &ram));
+        assert!(parse("RAM[RAM[SP]] - 1 == 6")?.holds(0, 0, &ram));
+        assert!(parse("M == 256")?.holds(0, 0, &ram));
+        assert!(parse("A + 1 == D")?.holds(4, 5, &ram));
+        assert!(parse("RAM[5] < 0")?.holds(0, 0, &ram));
+        assert!(parse("D >= -(2)")?.holds(0, 0xfffe, &ram));
+        assert!(!parse("D >= 0")?.holds(0, 0xfffd, &ram));
+        assert_eq!(parse("D >= 0")?.explain(0, 0xfffd, &ram), "-3 >= 0");
+        assert!(parse("RAM[R5] == -1")?.holds(0, 0, &ram));
+
+        // The highest bit of A is ignored, as it is by the CPU.
+        assert!(parse("M == 256")?.holds(0x8000, 0, &ram));
+        Ok(())
+    }
+
+    #[test]
+    fn other_comments() -> Result<()> {
+        // Only `//! assert` comments are assertions; other text is prose.
+        let source = "\
+            //! entry point\n\
+            //! Asserts the stack is empty\n\
+            //! assertion: D is zero\n\
+            @0\n\
+            D=A  //! asserts D is zero\n";
+        let assembled = crate::assemble(source.as_bytes(), crate::Isa::Strict)?;
+        assert_eq!(assembled.words.len(), 2);
+        assert!(assembled.assertions.is_empty());
+
+        let Err(err) = crate::assemble("@0\n//! assert\nD=A\n".as_bytes(), crate::Isa::Strict)
+        else {
+            panic!("an empty assertion should be an error");
+        };
+        assert!(format!("{err:#}").contains("line 2: expected a condition"));
+        Ok(())
+    }
+
+    #[test]
+    fn trailing() {
+        for source in ["@0\n//! assert D == 0\n", "@0\nD=A  //! assert D == 0\n"] {
+            let Err(err) = crate::assemble(source.as_bytes(), crate::Isa::Strict) else {
+                panic!("{source:?} should be an error");
+            };
+            assert_eq!(
+                err.to_string(),
+                "assertion after the last instruction, on line 2"
+            );
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let err = |condition: &str| format!("{:#}", parse(condition).unwrap_err());
+        assert!(err("D").contains("expected a comparison"));
+        assert!(err("D = 1").contains("did you mean `==`?"));
+        assert!(err("RAM[SP == 1").contains("expected `]`"));
+        assert!(err("D == 1 2").contains("unexpected `2`"));
+        assert!(err("nope == 1").contains("unknown symbol \"nope\""));
+        let symbol_table = SymbolTable::new();
+        assert!(Assertion::parse("assume D == 1", 1, &symbol_table).is_err());
+        assert!(Assertion::parse("assertD == 1", 1, &symbol_table).is_err());
+    }
+}
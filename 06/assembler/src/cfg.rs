@@ -0,0 +1,355 @@
+//! Control-flow graphs, recovered from machine code.
+//!
+//! Hack jumps go to whatever address is in A, so finding their targets
+//! means tracking the value loaded by the last `@` instruction. That's only
+//! done within a basic block: at the start of a block, A is unknown. Jumps
+//! whose target can't be found this way, e.g. returns, which load the address
+//! from RAM, are flagged as unresolved.
+
+use std::io::Write;
+
+use anyhow::{Context, Result};
+
+use crate::{instruction::Instr, Assembled, Jump, SymbolKind};
+
+/// A control-flow graph: the program, split into basic blocks.
+pub struct Cfg {
+    instrs: Vec<Instr>,
+
+    /// The source text of each instruction, or its disassembly.
+    text: Vec<String>,
+
+    blocks: Vec<Block>,
+
+    /// The index of the block holding each instruction.
+    block_of: Vec<usize>,
+}
+
+/// A run of instructions which is only entered at the start, and only left
+/// at the end.
+#[derive(Debug)]
+pub struct Block {
+    /// The ROM addresses of the instructions, `start..end`.
+    pub start: u16,
+    pub end: u16,
+
+    /// The labels of the first instruction.
+    pub labels: Vec<String>,
+
+    pub successors: Vec<Edge>,
+
+    /// Whether the block ends with a jump whose target is unknown, or outside
+    /// the program.
+    pub unresolved_jump: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Edge {
+    /// The index of the block.
+    pub target: usize,
+    pub kind: EdgeKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EdgeKind {
+    /// On to the next instruction, either because there's no jump, or because
+    /// a conditional jump isn't taken.
+    FallThrough,
+    Jump(Jump),
+}
+
+impl Cfg {
+    /// Build the graph for an assembled program, with label names and source
+    /// text.
+    pub fn from_assembled(assembled: &Assembled) -> Result<Self> {
+        let labels = assembled
+            .symbol_table
+            .symbols(SymbolKind::Label)
+            .into_iter()
+            .map(|(label, address)| (label.to_owned(), address))
+            .collect();
+        let text = (0..assembled.words.len())
+            .map(|address| {
+                let line = assembled.source_map.get(address as u16)?;
+                Some(line.text.trim().to_owned())
+            })
+            .collect::<Option<_>>();
+        Self::build(&assembled.words, labels, text)
+    }
+
+    /// Build the graph for machine code, e.g. from a `.hack` file.
+    pub fn from_words(words: &[u16]) -> Result<Self> {
+        Self::build(words, vec![], None)
+    }
+
+    fn build(words: &[u16], labels: Vec<(String, u16)>, text: Option<Vec<String>>) -> Result<Self> {
+        let instrs = words
+            .iter()
+            .enumerate()
+            .map(|(address, &word)| {
+                Instr::decode(word)
+                    .with_context(|| format!("invalid instruction at ROM[{address}]"))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let text = text.unwrap_or_else(|| instrs.iter().map(Instr::to_string).collect());
+        let len = instrs.len();
+
+        // Blocks start at the beginning, at labels, after jumps, and at the
+        // targets of jumps. Finding a target adds a block, which may hide the
+        // value of A from a later jump, so repeat until nothing changes.
+        let mut is_leader = vec![false; len + 1];
+        is_leader[0] = true;
+        is_leader[len] = true;
+        for &(_, address) in &labels {
+            if let Some(leader) = is_leader.get_mut(usize::from(address)) {
+                *leader = true;
+            }
+        }
+        for (address, instr) in instrs.iter().enumerate() {
+            if is_jump(instr) {
+                is_leader[address + 1] = true;
+            }
+        }
+        let a_values = loop {
+            let a_values = a_values(&instrs, &is_leader);
+            let mut changed = false;
+            for (address, instr) in instrs.iter().enumerate() {
+                if let Some(target) = a_values[address].filter(|_| is_jump(instr)) {
+                    let target = usize::from(target);
+                    if target < len && !is_leader[target] {
+                        is_leader[target] = true;
+                        changed = true;
+                    }
+                }
+            }
+            if !changed {
+                break a_values;
+            }
+        };
+
+        let mut blocks = vec![];
+        let mut block_of = vec![0; len];
+        let mut start = 0;
+        for end in 1..=len {
+            if !is_leader[end] {
+                continue;
+            }
+            block_of[start..end].fill(blocks.len());
+            blocks.push(Block {
+                start: start as u16,
+                end: end as u16,
+                labels: labels
+                    .iter()
+                    .filter(|&&(_, address)| usize::from(address) == start)
+                    .map(|(label, _)| label.clone())
+                    .collect(),
+                successors: vec![],
+                unresolved_jump: false,
+            });
+            start = end;
+        }
+
+        for block in &mut blocks {
+            let last = usize::from(block.end) - 1;
+            let jump = instrs[last].c_instr().map_or(Jump::Never, |c| c.jump());
+
+            if jump != Jump::Never {
+                match a_values[last].map(usize::from) {
+                    Some(target) if target < len => block.successors.push(Edge {
+                        target: block_of[target],
+                        kind: EdgeKind::Jump(jump),
+                    }),
+                    _ => block.unresolved_jump = true,
+                }
+            }
+            if jump != Jump::Always && last + 1 < len {
+                block.successors.push(Edge {
+                    target: block_of[last + 1],
+                    kind: EdgeKind::FallThrough,
+                });
+            }
+        }
+
+        Ok(Self {
+            instrs,
+            text,
+            blocks,
+            block_of,
+        })
+    }
+
+    pub fn blocks(&self) -> &[Block] {
+        &self.blocks
+    }
+
+    /// The instructions, indexed by ROM address.
+    pub fn instrs(&self) -> &[Instr] {
+        &self.instrs
+    }
+
+    /// The index of the block holding the instruction at `address`.
+    pub fn block_of(&self, address: u16) -> Option<usize> {
+        self.block_of.get(usize::from(address)).copied()
+    }
+
+    /// The ROM addresses of jumps whose target is unknown, or outside the
+    /// program.
+    pub fn unresolved_jumps(&self) -> impl Iterator<Item = u16> + '_ {
+        self.blocks
+            .iter()
+            .filter(|block| block.unresolved_jump)
+            .map(|block| block.end - 1)
+    }
+
+    /// Write the graph in Graphviz's DOT language. Each block is a node,
+    /// listing its labels and instructions. Unresolved jumps lead to a node
+    /// named `unknown`.
+    pub fn write_dot(&self, mut out: impl Write) -> Result<()> {
+        writeln!(out, "digraph cfg {{")?;
+        writeln!(out, "    node [shape=box, fontname=\"monospace\"];")?;
+
+        for (i, block) in self.blocks.iter().enumerate() {
+            let mut label = String::new();
+            for name in &block.labels {
+                label += &format!("({})\\l", escape(name));
+            }
+            for address in block.start..block.end {
+                let text = escape(&self.text[usize::from(address)]);
+                label += &format!("{address}: {text}\\l");
+            }
+            let color = if block.unresolved_jump {
+                ", color=red"
+            } else {
+                ""
+            };
+            writeln!(out, "    b{i} [label=\"{label}\"{color}];")?;
+
+            for edge in &block.successors {
+                match edge.kind {
+                    EdgeKind::FallThrough => writeln!(out, "    b{i} -> b{};", edge.target)?,
+                    EdgeKind::Jump(jump) => {
+                        writeln!(out, "    b{i} -> b{} [label=\"{jump}\"];", edge.target)?
+                    }
+                }
+            }
+        }
+
+        let unresolved: Vec<_> = self
+            .blocks
+            .iter()
+            .enumerate()
+            .filter(|(_, b)| b.unresolved_jump)
+            .collect();
+        if !unresolved.is_empty() {
+            writeln!(out, "    unknown [label=\"?\", shape=octagon, color=red];")?;
+            for (i, _) in unresolved {
+                writeln!(out, "    b{i} -> unknown [style=dashed, color=red];")?;
+            }
+        }
+
+        writeln!(out, "}}")?;
+        Ok(())
+    }
+}
+
+fn is_jump(instr: &Instr) -> bool {
+    instr.c_instr().is_some_and(|c| c.jump() != Jump::Never)
+}
+
+/// The value of A before each instruction, if it's known from an earlier `@`
+/// in the same block.
+fn a_values(instrs: &[Instr], is_leader: &[bool]) -> Vec<Option<u16>> {
+    let mut a = None;
+    let mut values = Vec::with_capacity(instrs.len());
+    for (address, instr) in instrs.iter().enumerate() {
+        if is_leader[address] {
+            a = None;
+        }
+        values.push(a);
+        a = match instr.c_instr() {
+            None => instr.a_literal(),
+            Some(c) if c.dest().a => None,
+            Some(_) => a,
+        };
+    }
+    values
+}
+
+/// Escape text for a quoted DOT string.
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assemble, Isa};
+
+    fn cfg(source: &str) -> Result<Cfg> {
+        Cfg::from_assembled(&assemble(source.as_bytes(), Isa::Strict)?)
+    }
+
+    #[test]
+    fn blocks() -> Result<()> {
+        let cfg = cfg("\
+            @10\n\
+            D=A\n\
+            (LOOP)\n\
+            D=D-1\n\
+            @LOOP\n\
+            D;JGT\n\
+            @R13\n\
+            A=M\n\
+            0;JMP\n\
+            (END)\n\
+            @END\n\
+            0;JMP\n")?;
+
+        let ranges: Vec<_> = cfg.blocks().iter().map(|b| (b.start, b.end)).collect();
+        assert_eq!(ranges, [(0, 2), (2, 5), (5, 8), (8, 10)]);
+        assert_eq!(cfg.blocks()[1].labels, ["LOOP"]);
+
+        let jump = |target, jump| Edge {
+            target,
+            kind: EdgeKind::Jump(jump),
+        };
+        let fall = |target| Edge {
+            target,
+            kind: EdgeKind::FallThrough,
+        };
+        assert_eq!(cfg.blocks()[0].successors, [fall(1)]);
+        assert_eq!(
+            cfg.blocks()[1].successors,
+            [jump(1, Jump::Greater), fall(2)]
+        );
+        // The return address comes from RAM.
+        assert!(cfg.blocks()[2].successors.is_empty());
+        assert_eq!(cfg.unresolved_jumps().collect::<Vec<_>>(), [7]);
+        // A halt loop jumps to itself.
+        assert_eq!(cfg.blocks()[3].successors, [jump(3, Jump::Always)]);
+        Ok(())
+    }
+
+    #[test]
+    fn targets_without_labels() -> Result<()> {
+        // Jumping into the middle of straight-line code splits it, and A
+        // isn't known at the start of the new block.
+        let words = assemble("@3\nD;JEQ\nD=A\nD=D+1\n0;JMP\n".as_bytes(), Isa::Strict)?.words;
+        let cfg = Cfg::from_words(&words)?;
+        let ranges: Vec<_> = cfg.blocks().iter().map(|b| (b.start, b.end)).collect();
+        assert_eq!(ranges, [(0, 2), (2, 3), (3, 5)]);
+        assert_eq!(cfg.unresolved_jumps().collect::<Vec<_>>(), [4]);
+
+        let mut dot = vec![];
+        cfg.write_dot(&mut dot)?;
+        let dot = String::from_utf8(dot)?;
+        assert!(dot.contains("b0 [label=\"0: @3\\l1: D;JEQ\\l\"];"), "{dot}");
+        assert!(dot.contains("b0 -> b2 [label=\"JEQ\"];"), "{dot}");
+        assert!(dot.contains("b0 -> b1;"), "{dot}");
+        assert!(
+            dot.contains("b2 -> unknown [style=dashed, color=red];"),
+            "{dot}"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,171 @@
+//! Compare assembled code against an expected `.hack` file.
+
+use std::io::Write;
+
+use anyhow::Result;
+
+use crate::{hack::parse_hack, instruction::Instr, source_map::SourceMap};
+
+/// Report each ROM address where `words` differs from `expected` (the
+/// contents of a `.hack` file), followed by a summary.
+///
+/// Returns the number of mismatches.
+pub fn check(
+    words: &[u16],
+    source_map: &SourceMap,
+    expected: &str,
+    mut out: impl Write,
+) -> Result<usize> {
+    let expected = parse_hack(expected)?;
+    let len = words.len().max(expected.len());
+
+    let mut num_mismatches = 0;
+
+    for address in 0..len {
+        let actual = words.get(address).copied();
+        let wanted = expected.get(address).copied();
+        if actual == wanted {
+            continue;
+        }
+        num_mismatches += 1;
+
+        writeln!(out, "ROM[{address}]:")?;
+        writeln!(out, "    expected: {}", describe(wanted))?;
+        writeln!(out, "    actual:   {}", describe(actual))?;
+
+        // `address` is less than `ADDRESS_LIMIT`, if it's in `words`.
+        if let Some(line) = u16::try_from(address).ok().and_then(|a| source_map.get(a)) {
+            writeln!(
+                out,
+                "    source:   line {}: {}",
+                line.number,
+                line.text.trim()
+            )?;
+        }
+    }
+
+    if num_mismatches == 0 {
+        writeln!(out, "all {len} words match")?;
+    } else {
+        writeln!(out, "{num_mismatches} of {len} words differ")?;
+    }
+
+    Ok(num_mismatches)
+}
+
+/// A word in binary, along with its disassembly.
+fn describe(word: Option<u16>) -> String {
+    let Some(word) = word else {
+        return String::from("(none)");
+    };
+
+    match Instr::decode(word) {
+        Ok(instr) => format!("{word:016b}  {instr}"),
+        Err(_) => format!("{word:016b}  (invalid instruction)"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assemble, Isa};
+
+    const SOURCE: &str = "@2\nD=A\n@3\nD=D+A\n";
+
+    fn run(expected: &str) -> Result<(usize, String)> {
+        let assembled = assemble(SOURCE.as_bytes(), Isa::Strict)?;
+        let mut report = vec![];
+        let num_mismatches = check(
+            &assembled.words,
+            &assembled.source_map,
+            expected,
+            &mut report,
+        )?;
+        Ok((num_mismatches, String::from_utf8(report)?))
+    }
+
+    #[test]
+    fn matching() -> Result<()> {
+        let expected = "\
+0000000000000010
+1110110000010000
+0000000000000011
+1110000010010000
+";
+        assert_eq!(run(expected)?, (0, String::from("all 4 words match\n")));
+        Ok(())
+    }
+
+    #[test]
+    fn wrong_word() -> Result<()> {
+        let expected = "\
+0000000000000010
+1110110000010000
+0000000000000100
+1110000010010000
+";
+        let (num_mismatches, report) = run(expected)?;
+        assert_eq!(num_mismatches, 1);
+        assert_eq!(
+            report,
+            "\
+ROM[2]:
+    expected: 0000000000000100  @4
+    actual:   0000000000000011  @3
+    source:   line 3: @3
+1 of 4 words differ
+"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn missing_word() -> Result<()> {
+        let expected = "\
+0000000000000010
+1110110000010000
+0000000000000011
+";
+        let (num_mismatches, report) = run(expected)?;
+        assert_eq!(num_mismatches, 1);
+        assert_eq!(
+            report,
+            "\
+ROM[3]:
+    expected: (none)
+    actual:   1110000010010000  D=D+A
+    source:   line 4: D=D+A
+1 of 4 words differ
+"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn extra_words() -> Result<()> {
+        let expected = "\
+0000000000000010
+1110110000010000
+0000000000000011
+1110000010010000
+0000000000000000
+1000000000000000
+";
+        let (num_mismatches, report) = run(expected)?;
+        assert_eq!(num_mismatches, 2);
+        // Addresses past the end of the program have no source line.
+        assert_eq!(
+            report,
+            "\
+ROM[4]:
+    expected: 0000000000000000  @0
+    actual:   (none)
+ROM[5]:
+    expected: 1000000000000000  (invalid instruction)
+    actual:   (none)
+2 of 6 words differ
+"
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,318 @@
+//! Dataflow analysis, to find common bugs without running the program.
+//!
+//! Tracks, at each instruction, whether `A` and `D` have been set on every
+//! path that reaches it, and what kind of address `A` holds: a label (a ROM
+//! address), or a variable or predefined symbol (a RAM address). This finds:
+//!
+//! * reads of `A`, `D` or `M` before the register has been set on some path,
+//! * uses of `M` while `A` holds a label, and
+//! * jumps while `A` holds a RAM address.
+//!
+//! Jumps whose target is unknown, e.g. returns, might go to any label, so
+//! blocks starting with a label are also assumed to be reachable with
+//! everything set.
+
+use std::fmt;
+
+use anyhow::Result;
+
+use crate::{cfg::Cfg, instruction::Instr, Assembled, Jump, SymbolKind};
+
+/// A likely bug, found by `analyze`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    pub address: u16,
+
+    /// The source line, 1-based.
+    pub line: usize,
+
+    /// The instruction, as written.
+    pub text: String,
+
+    pub message: String,
+}
+
+/// What `A` holds, as far as we can tell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    /// A label.
+    Rom,
+
+    /// A variable, or a predefined symbol.
+    Ram,
+
+    /// A number, or something computed.
+    Unknown,
+}
+
+/// What we know before an instruction, on every path that reaches it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct State {
+    a_set: bool,
+    d_set: bool,
+    a_kind: Kind,
+}
+
+impl State {
+    /// The state at the start of the program.
+    const ENTRY: State = State {
+        a_set: false,
+        d_set: false,
+        a_kind: Kind::Unknown,
+    };
+
+    /// The state after an unresolved jump, which might come from anywhere.
+    const ANYWHERE: State = State {
+        a_set: true,
+        d_set: true,
+        a_kind: Kind::Unknown,
+    };
+
+    /// What holds on both paths.
+    fn meet(self, other: State) -> State {
+        State {
+            a_set: self.a_set && other.a_set,
+            d_set: self.d_set && other.d_set,
+            a_kind: if self.a_kind == other.a_kind {
+                self.a_kind
+            } else {
+                Kind::Unknown
+            },
+        }
+    }
+}
+
+/// Look for likely bugs in an assembled program.
+///
+/// Problems are sorted by address, and unreachable code isn't checked.
+pub fn analyze(assembled: &Assembled) -> Result<Vec<Problem>> {
+    let cfg = Cfg::from_assembled(assembled)?;
+    let blocks = cfg.blocks();
+    let text = |address: u16| {
+        let line = assembled
+            .source_map
+            .get(address)
+            .expect("every instruction has a source line");
+        (line.number, line.text.trim().to_owned())
+    };
+
+    // The kind of address loaded by each A-instruction, from its symbol.
+    let kinds: Vec<Kind> = (0..cfg.instrs().len() as u16)
+        .map(|address| {
+            let symbol = assembled
+                .source_map
+                .get(address)
+                .and_then(|line| line.symbol.as_deref());
+            match symbol.and_then(|symbol| assembled.symbol_table.lookup_kind(symbol)) {
+                Some(SymbolKind::Label) => Kind::Rom,
+                Some(SymbolKind::Variable | SymbolKind::Predefined) => Kind::Ram,
+                None => Kind::Unknown,
+            }
+        })
+        .collect();
+
+    // Find the state at the start of each block, by iterating until nothing
+    // changes. `None` means the block hasn't been reached (yet).
+    let mut entry: Vec<Option<State>> = vec![None; blocks.len()];
+    let has_unresolved_jumps = cfg.unresolved_jumps().next().is_some();
+    for (i, block) in blocks.iter().enumerate() {
+        if i == 0 {
+            entry[i] = Some(State::ENTRY);
+        }
+        if has_unresolved_jumps && !block.labels.is_empty() {
+            let state = entry[i].map_or(State::ANYWHERE, |s| s.meet(State::ANYWHERE));
+            entry[i] = Some(state);
+        }
+    }
+
+    let mut worklist: Vec<usize> = (0..blocks.len()).rev().collect();
+    while let Some(i) = worklist.pop() {
+        let Some(mut state) = entry[i] else {
+            continue;
+        };
+        let block = &blocks[i];
+        for address in block.start..block.end {
+            state = transfer(
+                state,
+                &cfg.instrs()[usize::from(address)],
+                kinds[usize::from(address)],
+            );
+        }
+        for edge in &block.successors {
+            let new = match entry[edge.target] {
+                Some(old) => old.meet(state),
+                None => state,
+            };
+            if entry[edge.target] != Some(new) {
+                entry[edge.target] = Some(new);
+                if !worklist.contains(&edge.target) {
+                    worklist.push(edge.target);
+                }
+            }
+        }
+    }
+
+    // Now check each instruction, given the state before it.
+    let mut problems = vec![];
+    for (block, state) in blocks.iter().zip(entry) {
+        let Some(mut state) = state else {
+            continue;
+        };
+        for address in block.start..block.end {
+            let instr = &cfg.instrs()[usize::from(address)];
+            for message in check(state, instr) {
+                let (line, text) = text(address);
+                problems.push(Problem {
+                    address,
+                    line,
+                    text,
+                    message,
+                });
+            }
+            state = transfer(state, instr, kinds[usize::from(address)]);
+        }
+    }
+
+    Ok(problems)
+}
+
+/// The state after an instruction. For A-instructions, `kind` is what it
+/// loads.
+fn transfer(mut state: State, instr: &Instr, kind: Kind) -> State {
+    match instr.c_instr() {
+        None => {
+            state.a_set = true;
+            state.a_kind = kind;
+        }
+        Some(c) => {
+            if c.dest().d {
+                state.d_set = true;
+            }
+            if c.dest().a {
+                state.a_set = true;
+                state.a_kind = Kind::Unknown;
+            }
+        }
+    }
+    state
+}
+
+/// Describe any problems with an instruction, given the state before it.
+fn check(state: State, instr: &Instr) -> Vec<String> {
+    let Some(c) = instr.c_instr() else {
+        return vec![];
+    };
+    let comp = c.comp();
+    let uses_m = comp.reads_m() || c.dest().m;
+    let jumps = c.jump() != Jump::Never;
+
+    let mut problems = vec![];
+    if comp.reads_d() && !state.d_set {
+        problems.push("D is read, but may not have been set".to_owned());
+    }
+    if (comp.reads_a() || uses_m || jumps) && !state.a_set {
+        problems.push("A is used, but may not have been set".to_owned());
+    }
+    if uses_m && state.a_kind == Kind::Rom {
+        problems.push("M is used, but A holds a label, which is a ROM address".to_owned());
+    }
+    if jumps && state.a_kind == Kind::Ram {
+        problems.push("jumps to a RAM address, rather than a label".to_owned());
+    }
+    problems
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}: `{}`", self.line, self.message, self.text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assemble, Isa};
+
+    fn problems(source: &str) -> Result<Vec<String>> {
+        let assembled = assemble(source.as_bytes(), Isa::Strict)?;
+        let problems = analyze(&assembled)?;
+        Ok(problems.iter().map(ToString::to_string).collect())
+    }
+
+    #[test]
+    fn uninitialized() -> Result<()> {
+        let source = include_str!("../../../04/fill/Fill.asm");
+        assert_eq!(problems(source)?, Vec::<String>::new());
+
+        let source = "\
+            @START\n\
+            D;JEQ\n\
+            D=1\n\
+            (START)\n\
+            @x\n\
+            M=D\n";
+        assert_eq!(
+            problems(source)?,
+            [
+                "line 2: D is read, but may not have been set: `D;JEQ`",
+                "line 6: D is read, but may not have been set: `M=D`",
+            ]
+        );
+
+        let source = "M=1\n";
+        assert_eq!(
+            problems(source)?,
+            ["line 1: A is used, but may not have been set: `M=1`"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn address_kinds() -> Result<()> {
+        let source = "\
+            (LOOP)\n\
+            @LOOP\n\
+            M=M+1\n\
+            @i\n\
+            D=M\n\
+            @i\n\
+            D;JGT\n\
+            @5\n\
+            D=M\n\
+            @LOOP\n\
+            0;JMP\n";
+        assert_eq!(
+            problems(source)?,
+            [
+                "line 3: M is used, but A holds a label, which is a ROM address: `M=M+1`",
+                "line 7: jumps to a RAM address, rather than a label: `D;JGT`",
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn unresolved_jumps() -> Result<()> {
+        // A return address might be reached from the `JMP` through R13, with
+        // D set.
+        let source = "\
+            @RET\n\
+            D=A\n\
+            @R13\n\
+            M=D\n\
+            @FUNC\n\
+            0;JMP\n\
+            (RET)\n\
+            @x\n\
+            M=D\n\
+            (END)\n\
+            @END\n\
+            0;JMP\n\
+            (FUNC)\n\
+            @R13\n\
+            A=M\n\
+            0;JMP\n";
+        assert_eq!(problems(source)?, Vec::<String>::new());
+        Ok(())
+    }
+}
@@ -0,0 +1,36 @@
+//! Read and write `.hack` files: one 16-digit binary number per line.
+
+use std::io::Write;
+
+use anyhow::{bail, Context, Result};
+
+/// Parse the contents of a `.hack` file. Blank lines are ignored.
+pub fn parse_hack(contents: &str) -> Result<Vec<u16>> {
+    let mut words = vec![];
+
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if line.len() != 16 {
+            bail!("line {}: expected 16 binary digits, got {line:?}", i + 1);
+        }
+        let word = u16::from_str_radix(line, 2)
+            .with_context(|| format!("line {}: invalid binary word {line:?}", i + 1))?;
+
+        words.push(word);
+    }
+
+    Ok(words)
+}
+
+pub fn write_hack(words: &[u16], mut out: impl Write) -> Result<()> {
+    for code in words {
+        writeln!(out, "{code:0>16b}")?;
+    }
+    out.flush()?;
+
+    Ok(())
+}
@@ -1,13 +1,28 @@
 //! Data types to represent assembly instructions.
 
+mod lex;
 mod parse;
 mod code_gen;
+mod decode;
+mod display;
 
 /// All memory addresses must be strictly less than this limit.
 ///
 /// This applies to both RAM (data memory) and ROM (instruction memory).
 pub const ADDRESS_LIMIT: u16 = 2u16.pow(15);
 
+/// Which variant of the Hack instruction set to accept.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Isa {
+    /// The instruction set from the book.
+    #[default]
+    Strict,
+
+    /// Also allows the shift operations (`D<<`, `A>>`, etc.) supported by the
+    /// official CPU emulator.
+    Extended,
+}
+
 /// Either an instruction, or a "label" pseudo-instruction.
 #[derive(Debug)]
 pub enum Line {
@@ -37,30 +52,94 @@ enum AInstr {
     Literal(u16),
 }
 
-#[derive(Debug)]
-struct CInstr {
+/// A C-instruction: `dest=comp;jump`.
+#[derive(Debug, Clone, Copy)]
+pub struct CInstr {
     dest: Dest,
     comp: Comp,
     jump: Jump,
 }
 
-#[derive(Debug, Default)]
-struct Dest {
-    a: bool,
-    d: bool,
-    m: bool,
+/// Which registers to store the result in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Dest {
+    pub a: bool,
+    pub d: bool,
+    pub m: bool,
 }
 
-#[derive(Debug)]
-struct Comp {
+/// What to compute; the "control bits" for the ALU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Comp {
     a_bit: bool,
 
     /// Note: only certain combinations of (a_bit, c_bits) are valid.
     c_bits: [bool; 6],
+
+    /// Shift operations (extended ISA only) are encoded with a `101` prefix,
+    /// instead of the usual `111`.
+    shift: bool,
 }
 
-#[derive(Debug)]
-enum Jump {
+/// Every comp expression, in its canonical form, along with its
+/// `(a_bit, c_bits)` encoding.
+const COMPS: [(&str, u8, [u8; 6]); 28] = [
+    ("0", 0, [1, 0, 1, 0, 1, 0]),
+    ("1", 0, [1, 1, 1, 1, 1, 1]),
+    ("-1", 0, [1, 1, 1, 0, 1, 0]),
+    ("D", 0, [0, 0, 1, 1, 0, 0]),
+    ("A", 0, [1, 1, 0, 0, 0, 0]),
+    ("M", 1, [1, 1, 0, 0, 0, 0]),
+    ("!D", 0, [0, 0, 1, 1, 0, 1]),
+    ("!A", 0, [1, 1, 0, 0, 0, 1]),
+    ("!M", 1, [1, 1, 0, 0, 0, 1]),
+    ("-D", 0, [0, 0, 1, 1, 1, 1]),
+    ("-A", 0, [1, 1, 0, 0, 1, 1]),
+    ("-M", 1, [1, 1, 0, 0, 1, 1]),
+    ("D+1", 0, [0, 1, 1, 1, 1, 1]),
+    ("A+1", 0, [1, 1, 0, 1, 1, 1]),
+    ("M+1", 1, [1, 1, 0, 1, 1, 1]),
+    ("D-1", 0, [0, 0, 1, 1, 1, 0]),
+    ("A-1", 0, [1, 1, 0, 0, 1, 0]),
+    ("M-1", 1, [1, 1, 0, 0, 1, 0]),
+    ("D+A", 0, [0, 0, 0, 0, 1, 0]),
+    ("D+M", 1, [0, 0, 0, 0, 1, 0]),
+    ("D-A", 0, [0, 1, 0, 0, 1, 1]),
+    ("D-M", 1, [0, 1, 0, 0, 1, 1]),
+    ("A-D", 0, [0, 0, 0, 1, 1, 1]),
+    ("M-D", 1, [0, 0, 0, 1, 1, 1]),
+    ("D&A", 0, [0, 0, 0, 0, 0, 0]),
+    ("D&M", 1, [0, 0, 0, 0, 0, 0]),
+    ("D|A", 0, [0, 1, 0, 1, 0, 1]),
+    ("D|M", 1, [0, 1, 0, 1, 0, 1]),
+];
+
+/// Alternative spellings of commutative comp expressions, along with their
+/// canonical forms.
+const COMP_ALIASES: [(&str, &str); 6] = [
+    ("A+D", "D+A"),
+    ("M+D", "D+M"),
+    ("A&D", "D&A"),
+    ("M&D", "D&M"),
+    ("A|D", "D|A"),
+    ("M|D", "D|M"),
+];
+
+/// The shift operations of the extended ISA, in the same format as `COMPS`.
+///
+/// These use the same encoding as the official nand2tetris CPU emulator.
+const SHIFTS: [(&str, u8, [u8; 6]); 6] = [
+    ("A<<", 0, [0, 0, 0, 0, 1, 0]),
+    ("D<<", 0, [0, 0, 0, 1, 1, 0]),
+    ("M<<", 1, [0, 0, 0, 0, 1, 0]),
+    ("A>>", 0, [0, 0, 0, 0, 0, 0]),
+    ("D>>", 0, [0, 0, 0, 1, 0, 0]),
+    ("M>>", 1, [0, 0, 0, 0, 0, 0]),
+];
+
+/// When to jump, based on the computed value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Jump {
     Never,
     Greater,
     Equal,
@@ -70,3 +149,109 @@ enum Jump {
     LessEqual,
     Always,
 }
+
+impl Instr {
+    /// The fields of a C-instruction, or `None` for an A-instruction.
+    pub fn c_instr(&self) -> Option<&CInstr> {
+        match &self.inner {
+            InstrInner::AInstr(_) => None,
+            InstrInner::CInstr(c) => Some(c),
+        }
+    }
+
+    /// The value loaded by an A-instruction, if it's a literal rather than a
+    /// symbol.
+    pub fn a_literal(&self) -> Option<u16> {
+        match &self.inner {
+            InstrInner::AInstr(AInstr::Literal(value)) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// The symbol loaded by an A-instruction, if it isn't a literal.
+    pub fn a_symbol(&self) -> Option<&str> {
+        match &self.inner {
+            InstrInner::AInstr(AInstr::Symbol(symbol)) => Some(symbol),
+            _ => None,
+        }
+    }
+}
+
+impl CInstr {
+    pub fn dest(&self) -> Dest {
+        self.dest
+    }
+
+    pub fn comp(&self) -> Comp {
+        self.comp
+    }
+
+    pub fn jump(&self) -> Jump {
+        self.jump
+    }
+}
+
+impl Comp {
+    /// If set, the ALU's `y` input is `M`; otherwise it's `A`.
+    pub fn a_bit(&self) -> bool {
+        self.a_bit
+    }
+
+    /// The ALU control bits: `[zx, nx, zy, ny, f, no]`.
+    ///
+    /// For shift operations, these don't correspond to ALU inputs. Instead,
+    /// `c_bits[3]` selects `D` (rather than `A`/`M`) as the operand, and
+    /// `c_bits[4]` selects a left (rather than right) shift.
+    pub fn c_bits(&self) -> [bool; 6] {
+        self.c_bits
+    }
+
+    /// Is this one of the extended ISA's shift operations?
+    pub fn is_shift(&self) -> bool {
+        self.shift
+    }
+
+    /// Does this read `D`?
+    pub fn reads_d(&self) -> bool {
+        if self.shift {
+            self.c_bits[3]
+        } else {
+            !self.c_bits[0]
+        }
+    }
+
+    /// Does this read `A`, as a value rather than an address?
+    pub fn reads_a(&self) -> bool {
+        self.reads_y() && !self.a_bit
+    }
+
+    /// Does this read `M`?
+    pub fn reads_m(&self) -> bool {
+        self.reads_y() && self.a_bit
+    }
+
+    /// Does this read the ALU's `y` input, `A` or `M`?
+    fn reads_y(&self) -> bool {
+        if self.shift {
+            !self.c_bits[3]
+        } else {
+            !self.c_bits[2]
+        }
+    }
+}
+
+impl Jump {
+    /// Should we jump, given the computed `value`?
+    pub fn test(self, value: i16) -> bool {
+        match self {
+            Jump::Never => false,
+            Jump::Greater => value > 0,
+            Jump::Equal => value == 0,
+            Jump::GreaterEqual => value >= 0,
+            Jump::Less => value < 0,
+            Jump::NotEqual => value != 0,
+            Jump::LessEqual => value <= 0,
+            Jump::Always => true,
+        }
+    }
+}
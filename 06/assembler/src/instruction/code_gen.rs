@@ -33,7 +33,7 @@ impl CInstr {
         let mut code = 0;
 
         code <<= 3;
-        code |= 0b111;
+        code |= if self.comp.shift { 0b101 } else { 0b111 };
 
         code <<= 7;
         code |= self.comp.code_gen();
@@ -0,0 +1,125 @@
+//! Decode binary machine code into an instruction.
+//!
+//! This is the inverse of `code_gen`, except that A-instructions always decode
+//! to literals, since symbol names aren't present in machine code.
+
+use anyhow::{bail, ensure, Result};
+
+use super::{AInstr, CInstr, Comp, Dest, Instr, InstrInner, Jump, COMPS, SHIFTS};
+
+impl Instr {
+    pub fn decode(word: u16) -> Result<Self> {
+        let inner = if word & 0x8000 == 0 {
+            InstrInner::AInstr(AInstr::Literal(word))
+        } else {
+            InstrInner::CInstr(CInstr::decode(word)?)
+        };
+
+        Ok(Instr { inner })
+    }
+}
+
+impl CInstr {
+    fn decode(word: u16) -> Result<Self> {
+        let shift = match word >> 13 {
+            0b111 => false,
+            0b101 => true,
+            prefix => bail!("invalid C-instruction prefix {prefix:03b} in {word:016b}"),
+        };
+
+        let comp = Comp::decode((word >> 6) & 0b111_1111, shift)?;
+        let dest = Dest::decode((word >> 3) & 0b111);
+        let jump = Jump::decode(word & 0b111);
+
+        Ok(CInstr { dest, comp, jump })
+    }
+}
+
+impl Comp {
+    fn decode(code: u16, shift: bool) -> Result<Self> {
+        let [a_bit, c_bits @ ..] = u16_to_bits::<7>(code);
+
+        let table = if shift { &SHIFTS[..] } else { &COMPS[..] };
+        let is_valid = table
+            .iter()
+            .any(|&(_, a, c)| (a != 0) == a_bit && c.map(|bit| bit != 0) == c_bits);
+        ensure!(is_valid, "invalid comp bits {code:07b}");
+
+        Ok(Comp {
+            a_bit,
+            c_bits,
+            shift,
+        })
+    }
+}
+
+impl Dest {
+    fn decode(code: u16) -> Self {
+        let [a, d, m] = u16_to_bits(code);
+        Dest { a, d, m }
+    }
+}
+
+impl Jump {
+    fn decode(code: u16) -> Self {
+        match code {
+            0b000 => Jump::Never,
+            0b001 => Jump::Greater,
+            0b010 => Jump::Equal,
+            0b011 => Jump::GreaterEqual,
+            0b100 => Jump::Less,
+            0b101 => Jump::NotEqual,
+            0b110 => Jump::LessEqual,
+            0b111 => Jump::Always,
+            _ => unreachable!("jump field is 3 bits"),
+        }
+    }
+}
+
+/// The lowest `N` bits of `code`, most significant first.
+fn u16_to_bits<const N: usize>(code: u16) -> [bool; N] {
+    let mut bits = [false; N];
+
+    for (i, bit) in bits.iter_mut().enumerate() {
+        let shift = N - 1 - i;
+        *bit = (code >> shift) & 1 != 0;
+    }
+
+    bits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        instruction::{Isa, Line},
+        symbol_table::SymbolTable,
+    };
+
+    /// Decoding generated code, and printing it, should give back the
+    /// (canonical) source.
+    #[test]
+    fn round_trip() -> Result<()> {
+        let comps = COMPS.iter().chain(&SHIFTS).map(|&(expr, ..)| expr);
+        let lines = comps
+            .map(|comp| format!("AMD={comp};JLE"))
+            .chain(["@123", "M=D", "0;JMP", "D;JNE"].map(String::from));
+
+        for line in lines {
+            let Line::Instr(instr) = Line::parse(&line, Isa::Extended)? else {
+                unreachable!("not a label");
+            };
+            let word = instr.code_gen(&mut SymbolTable::new())?;
+            assert_eq!(Instr::decode(word)?.to_string(), line);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn invalid() {
+        assert!(Instr::decode(0b1100_0000_0000_0000).is_err());
+        assert!(Instr::decode(0b1110_1111_1100_0000).is_ok());
+        assert!(Instr::decode(0b1110_1111_1000_0000).is_err());
+    }
+}
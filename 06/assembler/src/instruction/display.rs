@@ -0,0 +1,118 @@
+//! Format an instruction as assembly code.
+
+use std::fmt::{self, Display};
+
+use super::{AInstr, CInstr, Comp, Dest, Instr, InstrInner, Jump, COMPS, SHIFTS};
+
+impl Display for Instr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.inner {
+            InstrInner::AInstr(a) => write!(f, "{a}"),
+            InstrInner::CInstr(c) => write!(f, "{c}"),
+        }
+    }
+}
+
+impl Instr {
+    /// The `[dest, comp, jump]` fields of a C-instruction, or `None` for an
+    /// A-instruction.
+    ///
+    /// Omitted fields are spelled `null`, as in the book.
+    pub fn c_fields(&self) -> Option<[String; 3]> {
+        let InstrInner::CInstr(c) = &self.inner else {
+            return None;
+        };
+
+        let or_null = |field: String| {
+            if field.is_empty() {
+                String::from("null")
+            } else {
+                field
+            }
+        };
+
+        Some([
+            or_null(c.dest.to_string()),
+            c.comp.to_string(),
+            or_null(c.jump.to_string()),
+        ])
+    }
+}
+
+impl Display for AInstr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AInstr::Symbol(symbol) => write!(f, "@{symbol}"),
+            AInstr::Literal(value) => write!(f, "@{value}"),
+        }
+    }
+}
+
+impl Display for CInstr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Self { dest, comp, jump } = self;
+
+        if !matches!(
+            dest,
+            Dest {
+                a: false,
+                d: false,
+                m: false
+            }
+        ) {
+            write!(f, "{dest}=")?;
+        }
+        write!(f, "{comp}")?;
+        if !matches!(jump, Jump::Never) {
+            write!(f, ";{jump}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Empty if there's no destination.
+impl Display for Dest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // The book's order is A, M, D; e.g. `AMD`, or `MD`.
+        for (is_set, c) in [(self.a, 'A'), (self.m, 'M'), (self.d, 'D')] {
+            if is_set {
+                write!(f, "{c}")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Display for Comp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let table = if self.shift { &SHIFTS[..] } else { &COMPS[..] };
+
+        let expr = table.iter().find_map(|&(expr, a_bit, c_bits)| {
+            let matches = (a_bit != 0) == self.a_bit && c_bits.map(|bit| bit != 0) == self.c_bits;
+            matches.then_some(expr)
+        });
+
+        // Every `Comp` is constructed from one of the table entries.
+        let expr = expr.expect("invalid comp bits");
+        write!(f, "{expr}")
+    }
+}
+
+/// Empty if there's no jump.
+impl Display for Jump {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Jump::Never => "",
+            Jump::Greater => "JGT",
+            Jump::Equal => "JEQ",
+            Jump::GreaterEqual => "JGE",
+            Jump::Less => "JLT",
+            Jump::NotEqual => "JNE",
+            Jump::LessEqual => "JLE",
+            Jump::Always => "JMP",
+        };
+        write!(f, "{s}")
+    }
+}
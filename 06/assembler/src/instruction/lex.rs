@@ -0,0 +1,97 @@
+//! Split a C-instruction into tokens.
+//!
+//! Whitespace between tokens is ignored, so `D = M + 1` and `D=M+1` produce
+//! the same tokens (with different spans).
+
+use anyhow::{bail, Result};
+
+/// A range of byte offsets into the line being tokenized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    /// The smallest span covering both `self` and `other`.
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            start: self.start.min(other.start),
+            end: self.end.max(other.end),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    /// A run of letters and digits, e.g. `AM`, `JGT`, or `1`.
+    Word,
+
+    /// `=`
+    Equals,
+
+    /// `;`
+    Semicolon,
+
+    /// An operator, e.g. `+` or `!`.
+    Op,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Token<'a> {
+    pub kind: TokenKind,
+    pub text: &'a str,
+    pub span: Span,
+}
+
+pub fn tokenize(line: &str) -> Result<Vec<Token<'_>>> {
+    let mut tokens = vec![];
+    let mut chars = line.char_indices().peekable();
+
+    while let Some((start, c)) = chars.next() {
+        let kind = match c {
+            _ if c.is_whitespace() => continue,
+            '=' => TokenKind::Equals,
+            ';' => TokenKind::Semicolon,
+            '+' | '-' | '!' | '&' | '|' => TokenKind::Op,
+            // Shift operators, `<<` and `>>`.
+            '<' | '>' if chars.next_if(|&(_, next)| next == c).is_some() => TokenKind::Op,
+            _ if c.is_ascii_alphanumeric() => {
+                while chars.next_if(|(_, c)| c.is_ascii_alphanumeric()).is_some() {}
+                TokenKind::Word
+            }
+            _ => {
+                let span = Span {
+                    start,
+                    end: start + c.len_utf8(),
+                };
+                bail!(
+                    "unexpected character {c:?} in C-instruction\n{}",
+                    underline(line, span)
+                );
+            }
+        };
+
+        let end = chars.peek().map_or(line.len(), |&(idx, _)| idx);
+        let span = Span { start, end };
+        tokens.push(Token {
+            kind,
+            text: &line[start..end],
+            span,
+        });
+    }
+
+    Ok(tokens)
+}
+
+/// Show `line` with the characters in `span` underlined, for use in error
+/// messages.
+pub fn underline(line: &str, span: Span) -> String {
+    let indent = line[..span.start].chars().count();
+    let width = line[span.start..span.end].chars().count().max(1);
+    format!(
+        "    {line}\n    {}{}",
+        " ".repeat(indent),
+        "^".repeat(width)
+    )
+}
@@ -1,19 +1,38 @@
 //! Parse an instruction from a string.
 
+use std::str::FromStr;
+
 use anyhow::{bail, ensure, Context, Result};
 
-use super::{AInstr, CInstr, Comp, Dest, Instr, InstrInner, Jump, Line};
+use super::{
+    lex::{self, underline, Span, Token, TokenKind},
+    AInstr, CInstr, Comp, Dest, Instr, InstrInner, Isa, Jump, Line, COMPS, COMP_ALIASES, SHIFTS,
+};
 use crate::instruction::ADDRESS_LIMIT;
 
+impl FromStr for Isa {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let isa = match s {
+            "strict" => Self::Strict,
+            "extended" => Self::Extended,
+            _ => bail!("ISA must be one of {{strict, extended}}; got: {s:?}"),
+        };
+
+        Ok(isa)
+    }
+}
+
 impl Line {
-    pub fn parse(line: &str) -> Result<Self> {
+    pub fn parse(line: &str, isa: Isa) -> Result<Self> {
         let line = line.trim();
 
         if line.starts_with('(') {
             let label = parse_label(line)?;
             Ok(Line::Label(label))
         } else {
-            let instr = Instr::parse(line)?;
+            let instr = Instr::parse(line, isa)?;
             Ok(Line::Instr(instr))
         }
     }
@@ -56,13 +75,13 @@ fn is_valid_char(c: char) -> bool {
 }
 
 impl Instr {
-    fn parse(line: &str) -> Result<Self> {
+    fn parse(line: &str, isa: Isa) -> Result<Self> {
         debug_assert_eq!(line, line.trim());
 
         let inner = if line.starts_with('@') {
             InstrInner::AInstr(AInstr::parse(line)?)
         } else {
-            InstrInner::CInstr(CInstr::parse(line)?)
+            InstrInner::CInstr(CInstr::parse(line, isa)?)
         };
 
         Ok(Instr { inner })
@@ -99,60 +118,83 @@ impl AInstr {
 }
 
 impl CInstr {
-    fn parse(mut line: &str) -> Result<Self> {
+    fn parse(line: &str, isa: Isa) -> Result<Self> {
         debug_assert_eq!(line, line.trim());
 
-        let dest = Dest::parse(&mut line)?;
-        let jump = Jump::parse(&mut line)?;
-        let comp = Comp::parse(&line)?;
+        let tokens = lex::tokenize(line)?;
+        let mut tokens = &tokens[..];
+
+        let dest = Dest::parse(&mut tokens, line)?;
+        let jump = Jump::parse(&mut tokens, line)?;
+        let comp = Comp::parse(tokens, line, isa)?;
 
         Ok(CInstr { dest, comp, jump })
     }
 }
 
 impl Dest {
-    /// Consume the `dest=` prefix of `line`, and parse it into a `Dest`.
+    /// Consume the `dest =` prefix of `tokens`, and parse it into a `Dest`.
     ///
-    /// If the line doesn't start with `dest=`, return `Dest::default()`.
-    fn parse(line: &mut &str) -> Result<Self> {
-        debug_assert_eq!(*line, line.trim());
-
-        let Some((dest, rest)) = line.split_once('=') else {
+    /// If there's no `=` token, return `Dest::default()`.
+    fn parse(tokens: &mut &[Token], line: &str) -> Result<Self> {
+        let Some(eq_idx) = tokens.iter().position(|t| t.kind == TokenKind::Equals) else {
             return Ok(Dest::default());
         };
+        let (dest, rest) = (&tokens[..eq_idx], &tokens[eq_idx + 1..]);
+
+        let dest = match dest {
+            [t] if t.kind == TokenKind::Word => t,
+            [] => bail!("empty dest field\n{}", underline(line, tokens[eq_idx].span)),
+            _ => bail!(
+                "dest field must be some combination of A, D, and M\n{}",
+                underline(line, span_of(dest))
+            ),
+        };
 
-        ensure!(!dest.is_empty(), "empty dest field in line {line:?}");
-        for c in dest.chars() {
+        for c in dest.text.chars() {
             if !"ADM".contains(c) {
-                bail!("invalid dest char {c:?} in line {line:?}");
+                bail!("invalid dest char {c:?}\n{}", underline(line, dest.span));
             }
         }
         ensure!(
-            dest.len() <= 3,
-            "repeated char in dest field {dest:?}. line: {line:?}"
+            dest.text.len() <= 3,
+            "repeated char in dest field {:?}\n{}",
+            dest.text,
+            underline(line, dest.span)
         );
 
-        let a = dest.contains('A');
-        let d = dest.contains('D');
-        let m = dest.contains('M');
+        let a = dest.text.contains('A');
+        let d = dest.text.contains('D');
+        let m = dest.text.contains('M');
 
-        *line = rest;
+        *tokens = rest;
         Ok(Dest { a, d, m })
     }
 }
 
 impl Jump {
-    /// Consume the `;jump` suffix of `line`, and parse it into a `Jump`.
+    /// Consume the `; jump` suffix of `tokens`, and parse it into a `Jump`.
     ///
-    /// If the line doesn't end with `;jump`, return `Jump::Never`.
-    fn parse(line: &mut &str) -> Result<Self> {
-        debug_assert_eq!(*line, line.trim());
-
-        let Some((rest, jump)) = line.split_once(';') else {
+    /// If there's no `;` token, return `Jump::Never`.
+    fn parse(tokens: &mut &[Token], line: &str) -> Result<Self> {
+        let Some(semi_idx) = tokens.iter().position(|t| t.kind == TokenKind::Semicolon) else {
             return Ok(Jump::Never);
         };
+        let (rest, jump) = (&tokens[..semi_idx], &tokens[semi_idx + 1..]);
 
         let jump = match jump {
+            [t] if t.kind == TokenKind::Word => t,
+            [] => bail!(
+                "empty jump field\n{}",
+                underline(line, tokens[semi_idx].span)
+            ),
+            _ => bail!(
+                "jump field must be a single word\n{}",
+                underline(line, span_of(jump))
+            ),
+        };
+
+        let parsed = match jump.text {
             "JGT" => Jump::Greater,
             "JEQ" => Jump::Equal,
             "JGE" => Jump::GreaterEqual,
@@ -160,60 +202,188 @@ impl Jump {
             "JNE" => Jump::NotEqual,
             "JLE" => Jump::LessEqual,
             "JMP" => Jump::Always,
-            _ => bail!("jump must be one of {{JGT, JEQ, JGE, JLT, JNE, JLE, JMP}}; got: {jump:?}"),
+            _ => bail!(
+                "jump must be one of {{JGT, JEQ, JGE, JLT, JNE, JLE, JMP}}; got: {:?}\n{}",
+                jump.text,
+                underline(line, jump.span)
+            ),
         };
 
-        *line = rest;
-        Ok(jump)
+        *tokens = rest;
+        Ok(parsed)
     }
 }
 
 impl Comp {
-    /// Parse `expr` as a `comp` field.
+    /// Parse `tokens` as a `comp` field.
     ///
-    /// You must first strip the optional `dest=` and `;jump` before calling
+    /// You must first strip the optional `dest =` and `; jump` before calling
     /// this function.
-    fn parse(expr: &str) -> Result<Self> {
-        let (a_bit, c_bits) = match expr {
-            "0" => (0, [1, 0, 1, 0, 1, 0]),
-            "1" => (0, [1, 1, 1, 1, 1, 1]),
-            "-1" => (0, [1, 1, 1, 0, 1, 0]),
-
-            "D" => (0, [0, 0, 1, 1, 0, 0]),
-            "A" => (0, [1, 1, 0, 0, 0, 0]),
-            "M" => (1, [1, 1, 0, 0, 0, 0]),
-            "!D" => (0, [0, 0, 1, 1, 0, 1]),
-            "!A" => (0, [1, 1, 0, 0, 0, 1]),
-            "!M" => (1, [1, 1, 0, 0, 0, 1]),
-            "-D" => (0, [0, 0, 1, 1, 1, 1]),
-            "-A" => (0, [1, 1, 0, 0, 1, 1]),
-            "-M" => (1, [1, 1, 0, 0, 1, 1]),
-
-            "D+1" => (0, [0, 1, 1, 1, 1, 1]),
-            "A+1" => (0, [1, 1, 0, 1, 1, 1]),
-            "M+1" => (1, [1, 1, 0, 1, 1, 1]),
-            "D-1" => (0, [0, 0, 1, 1, 1, 0]),
-            "A-1" => (0, [1, 1, 0, 0, 1, 0]),
-            "M-1" => (1, [1, 1, 0, 0, 1, 0]),
-
-            "D+A" | "A+D" => (0, [0, 0, 0, 0, 1, 0]),
-            "D+M" | "M+D" => (1, [0, 0, 0, 0, 1, 0]),
-            "D-A" => (0, [0, 1, 0, 0, 1, 1]),
-            "D-M" => (1, [0, 1, 0, 0, 1, 1]),
-            "A-D" => (0, [0, 0, 0, 1, 1, 1]),
-            "M-D" => (1, [0, 0, 0, 1, 1, 1]),
-
-            "D&A" | "A&D" => (0, [0, 0, 0, 0, 0, 0]),
-            "D&M" | "M&D" => (1, [0, 0, 0, 0, 0, 0]),
-            "D|A" | "A|D" => (0, [0, 1, 0, 1, 0, 1]),
-            "D|M" | "M|D" => (1, [0, 1, 0, 1, 0, 1]),
-
-            _ => bail!("unrecognized comp expresion {expr:?}"),
+    fn parse(tokens: &[Token], line: &str, isa: Isa) -> Result<Self> {
+        ensure!(
+            !tokens.is_empty(),
+            "missing comp expression\n{}",
+            underline(
+                line,
+                Span {
+                    start: 0,
+                    end: line.len()
+                }
+            )
+        );
+
+        // Whitespace between tokens doesn't matter.
+        let expr: String = tokens.iter().map(|t| t.text).collect();
+
+        let shift = lookup_shift(&expr).is_some();
+        ensure!(
+            !shift || isa == Isa::Extended,
+            "shift operation {expr:?} requires the extended ISA (`--isa extended`)\n{}",
+            underline(line, span_of(tokens))
+        );
+
+        let lookup = if shift { lookup_shift } else { lookup_comp };
+        let Some((a_bit, c_bits)) = lookup(&expr) else {
+            let hint = match suggest_comp(tokens, &expr) {
+                Some(suggestion) => format!("; did you mean `{suggestion}`?"),
+                None => String::new(),
+            };
+            bail!(
+                "unrecognized comp expression {expr:?}{hint}\n{}",
+                underline(line, span_of(tokens))
+            );
         };
 
         let a_bit = a_bit != 0;
         let c_bits = c_bits.map(|bit| bit != 0);
 
-        Ok(Comp { a_bit, c_bits })
+        Ok(Comp {
+            a_bit,
+            c_bits,
+            shift,
+        })
+    }
+}
+
+/// Look up the `(a_bit, c_bits)` encoding of a comp expression, written
+/// without whitespace.
+fn lookup_comp(expr: &str) -> Option<(u8, [u8; 6])> {
+    let expr = COMP_ALIASES
+        .iter()
+        .find(|&&(alias, _)| alias == expr)
+        .map_or(expr, |&(_, canonical)| canonical);
+
+    lookup(&COMPS, expr)
+}
+
+/// Like `lookup_comp`, but for the shift operations of the extended ISA.
+fn lookup_shift(expr: &str) -> Option<(u8, [u8; 6])> {
+    lookup(&SHIFTS, expr)
+}
+
+fn lookup(table: &[(&str, u8, [u8; 6])], expr: &str) -> Option<(u8, [u8; 6])> {
+    table
+        .iter()
+        .find(|&&(e, ..)| e == expr)
+        .map(|&(_, a_bit, c_bits)| (a_bit, c_bits))
+}
+
+/// Guess what the user meant by an unrecognized comp expression.
+fn suggest_comp(tokens: &[Token], expr: &str) -> Option<String> {
+    // Operands of a commutative operator may be written in the "wrong" order,
+    // e.g. `1+D` instead of `D+1`.
+    if let [x, op, y] = tokens {
+        if op.kind == TokenKind::Op && ["+", "&", "|"].contains(&op.text) {
+            let swapped = format!("{}{}{}", y.text, op.text, x.text);
+            if lookup_comp(&swapped).is_some() {
+                return Some(swapped);
+            }
+        }
+    }
+
+    // Register names are case-sensitive.
+    let upper = expr.to_ascii_uppercase();
+    if lookup_comp(&upper).is_some() {
+        return Some(upper);
+    }
+
+    None
+}
+
+/// The span covering a non-empty sequence of tokens.
+fn span_of(tokens: &[Token]) -> Span {
+    let first = tokens.first().expect("no tokens");
+    let last = tokens.last().expect("no tokens");
+    first.span.to(last.span)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::symbol_table::SymbolTable;
+
+    fn assemble(line: &str) -> Result<u16> {
+        assemble_isa(line, Isa::Strict)
+    }
+
+    fn assemble_isa(line: &str, isa: Isa) -> Result<u16> {
+        Instr::parse(line, isa)?.code_gen(&mut SymbolTable::new())
+    }
+
+    #[test]
+    fn inner_whitespace() -> Result<()> {
+        assert_eq!(assemble("D = M + 1")?, assemble("D=M+1")?);
+        assert_eq!(assemble("D;  JGT")?, assemble("D;JGT")?);
+        assert_eq!(assemble("AM = ! D ; JMP")?, assemble("AM=!D;JMP")?);
+        assert_eq!(assemble("M = - 1")?, assemble("M=-1")?);
+        Ok(())
+    }
+
+    #[test]
+    fn non_canonical_comp() {
+        let err = assemble("D = 1 + D").unwrap_err().to_string();
+        assert!(err.contains("did you mean `D+1`?"), "{err}");
+        assert!(err.ends_with("\n    D = 1 + D\n        ^^^^^"), "{err}");
+
+        let err = assemble("m-1;JGT").unwrap_err().to_string();
+        assert!(err.contains("did you mean `M-1`?"), "{err}");
+    }
+
+    #[test]
+    fn comp_registers() -> Result<()> {
+        for (comp, _, _) in COMPS.iter().chain(&SHIFTS) {
+            let instr = Instr::parse(comp, Isa::Extended)?;
+            let comp = instr.c_instr().unwrap().comp();
+            let name = comp.to_string();
+            assert_eq!(comp.reads_a(), name.contains('A'), "{name}");
+            assert_eq!(comp.reads_d(), name.contains('D'), "{name}");
+            assert_eq!(comp.reads_m(), name.contains('M'), "{name}");
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn malformed_fields() {
+        assert!(assemble("= D").is_err());
+        assert!(assemble("D;").is_err());
+        assert!(assemble("D;JGT JMP").is_err());
+        assert!(assemble("A D=M").is_err());
+        assert!(assemble("D=M#").is_err());
+    }
+
+    #[test]
+    fn shifts() -> Result<()> {
+        assert_eq!(assemble_isa("D<<", Isa::Extended)?, 0xa180);
+        assert_eq!(assemble_isa("A>>", Isa::Extended)?, 0xa000);
+        assert_eq!(assemble_isa("M <<", Isa::Extended)?, 0xb080);
+        assert_eq!(
+            assemble_isa("AM=M>>;JGT", Isa::Extended)?,
+            0xb000 | 0b101_001
+        );
+
+        let err = assemble("D=D<<").unwrap_err().to_string();
+        assert!(err.contains("requires the extended ISA"), "{err}");
+
+        Ok(())
     }
 }
@@ -0,0 +1,231 @@
+//! An assembler for the hack assembly language.
+//!
+//! Translates high-level assembly code into binary machine instructions.
+
+mod assertion;
+mod cfg;
+mod check;
+mod dataflow;
+mod hack;
+mod instruction;
+mod source_map;
+mod stats;
+mod symbol_table;
+
+use std::io::BufRead;
+
+use anyhow::{ensure, Context, Result};
+use itertools::Itertools;
+
+pub use crate::{
+    assertion::{Assertion, Assertions},
+    cfg::{Block, Cfg, Edge, EdgeKind},
+    check::check,
+    dataflow::{analyze, Problem},
+    hack::{parse_hack, write_hack},
+    instruction::{CInstr, Comp, Dest, Instr, Isa, Jump, Line, ADDRESS_LIMIT},
+    source_map::{SourceLine, SourceMap},
+    stats::Stats,
+    symbol_table::{SymbolKind, SymbolTable},
+};
+
+/// Everything we learn while assembling a program.
+#[derive(Default)]
+pub struct Assembled {
+    /// The generated machine code, indexed by ROM address.
+    pub words: Vec<u16>,
+    pub source_map: SourceMap,
+    pub stats: Stats,
+
+    /// Every symbol, including labels, variables, and predefined symbols.
+    pub symbol_table: SymbolTable,
+
+    /// Conditions from `//! assert` comments, keyed by ROM address.
+    pub assertions: Assertions,
+}
+
+/// A line of source code, and its parsed form.
+struct ParsedLine {
+    source: SourceLine,
+
+    /// `None` for lines with only an assertion.
+    line: Option<Line>,
+
+    /// The text after `//!`, for `//! assert` comments.
+    assertion: Option<String>,
+}
+
+/// Translate assembly into binary format.
+///
+/// The source is parsed once, up front; the two passes then walk over the
+/// parsed lines.
+pub fn assemble(source: impl BufRead, isa: Isa) -> Result<Assembled> {
+    assemble_with_symbols(source, isa, SymbolTable::new())
+}
+
+/// Translate assembly into binary format, starting from the given
+/// predefined symbols, e.g. to name extra memory-mapped devices.
+pub fn assemble_with_symbols(
+    source: impl BufRead,
+    isa: Isa,
+    symbol_table: SymbolTable,
+) -> Result<Assembled> {
+    let lines = parse(source, isa)?;
+
+    let mut assembled = Assembled {
+        symbol_table,
+        ..Assembled::default()
+    };
+
+    first_pass(&lines, &mut assembled.symbol_table, &mut assembled.stats)?;
+    second_pass(lines, &mut assembled)?;
+
+    let num_variables = assembled.symbol_table.num_variables();
+    assembled.stats.record_variables(num_variables);
+    Ok(assembled)
+}
+
+/// Parse every line of source code, skipping comments and blank lines, but
+/// keeping `//! assert` comments.
+fn parse(source: impl BufRead, isa: Isa) -> Result<Vec<ParsedLine>> {
+    let lines = source.lines().map(|r| r.map_err(Into::into));
+
+    remove_comments(lines)
+        .map(|line| {
+            let (number, text, assertion) = line?;
+            let line = if text.trim().is_empty() {
+                None
+            } else {
+                Some(Line::parse(&text, isa)?)
+            };
+            let symbol = match &line {
+                Some(Line::Instr(instr)) => instr.a_symbol().map(str::to_owned),
+                _ => None,
+            };
+            let source = SourceLine {
+                number,
+                text,
+                symbol,
+            };
+            Ok(ParsedLine {
+                source,
+                line,
+                assertion,
+            })
+        })
+        .collect()
+}
+
+/// Read labels, of the form `(LABEL)`, and add them to the symbol table.
+fn first_pass(
+    lines: &[ParsedLine],
+    symbol_table: &mut SymbolTable,
+    stats: &mut Stats,
+) -> Result<()> {
+    let mut num_instructions = 0;
+
+    for ParsedLine { line, .. } in lines {
+        match line {
+            None => {}
+            Some(Line::Label(symbol)) => {
+                stats.record_label(symbol, num_instructions);
+                symbol_table.new_label(symbol.clone(), num_instructions)?;
+            }
+            Some(Line::Instr(_)) => {
+                ensure!(
+                    num_instructions < ADDRESS_LIMIT,
+                    "can't emit more than {ADDRESS_LIMIT} instructions"
+                );
+
+                num_instructions += 1;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Does the actual code-generation.
+///
+/// Unknown symbols are assumed to be new variables, and we generate new
+/// symbol-table entries accordingly.
+///
+/// Assertions apply to the next instruction, which for an assertion at the
+/// end of a line is the one after that line's instruction. They're parsed
+/// last, once every symbol is known.
+fn second_pass(lines: Vec<ParsedLine>, assembled: &mut Assembled) -> Result<()> {
+    let mut assertions = vec![];
+    for ParsedLine {
+        source,
+        line,
+        assertion,
+    } in lines
+    {
+        let number = source.number;
+        match line {
+            None | Some(Line::Label(_)) => (),
+            Some(Line::Instr(instr)) => {
+                assembled.stats.record_instr(&instr);
+                let code = instr.code_gen(&mut assembled.symbol_table)?;
+
+                assembled.words.push(code);
+                assembled.source_map.push(source);
+            }
+        }
+
+        if let Some(assertion) = assertion {
+            // There are fewer than `ADDRESS_LIMIT` instructions.
+            let address = assembled.words.len() as u16;
+            assertions.push((address, number, assertion));
+        }
+    }
+
+    for (address, number, text) in assertions {
+        // It would never be checked.
+        ensure!(
+            usize::from(address) < assembled.words.len(),
+            "assertion after the last instruction, on line {number}"
+        );
+        let assertion = Assertion::parse(&text, number, &assembled.symbol_table)
+            .with_context(|| format!("invalid assertion on line {number}"))?;
+        assembled.assertions.push(address, assertion);
+    }
+
+    Ok(())
+}
+
+/// Remove comments and blank lines.
+///
+/// Each remaining line is paired with its (1-based) line number, and the
+/// text after `//!`, if the comment is an assertion (`//! assert ...`).
+/// Lines with only an assertion are kept, with empty text.
+fn remove_comments(
+    lines: impl Iterator<Item = Result<String>>,
+) -> impl Iterator<Item = Result<(usize, String, Option<String>)>> {
+    let numbered = lines.enumerate().map(|(i, r)| r.map(|line| (i + 1, line)));
+
+    numbered.filter_map_ok(|(number, mut line)| {
+        // Remove everything after the first "//".
+        let mut assertion = None;
+        if let Some(idx) = line.find("//") {
+            if let Some(text) = line[idx..].strip_prefix("//!") {
+                if is_assertion(text) {
+                    assertion = Some(text.trim().to_owned());
+                }
+            }
+            line.truncate(idx);
+        }
+
+        // If this line is blank, filter it out.
+        if line.trim().is_empty() && assertion.is_none() {
+            None
+        } else {
+            Some((number, line, assertion))
+        }
+    })
+}
+
+/// Whether a `//!` comment is an assertion, i.e. its first word is `assert`.
+fn is_assertion(annotation: &str) -> bool {
+    annotation.split_whitespace().next() == Some("assert")
+}
@@ -1,41 +1,261 @@
-//! An assembler for the hack assembly language.
-//!
-//! Translates high-level assembly code into binary machine instructions.
-
-mod symbol_table;
-mod instruction;
+//! Command-line interface for the assembler.
 
 use std::{
+    collections::HashSet,
     env,
     ffi::OsStr,
     fs::{self, File},
-    io::{prelude::*, BufReader},
+    io::{self, BufReader, BufWriter, Write},
+    iter::zip,
     path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
 };
 
-use anyhow::{ensure, Context, Result};
-use instruction::Line;
-use itertools::Itertools;
+use anyhow::{bail, ensure, Context, Result};
+use assembler::{Assembled, Cfg, Isa, SourceMap};
 
-use crate::{instruction::ADDRESS_LIMIT, symbol_table::SymbolTable};
+#[cfg(test)]
+mod temp_dir;
 
-/// Expects one argmuent: the name of an assembly source file, with a `.asm`
-/// extension.
+/// Expects one or more arguments: the names of assembly source files, with a
+/// `.asm` extension, or directories containing such files.
 ///
-/// The output file will be in the same directory as the input file, and have
+/// Each output file will be in the same directory as its input file, and have
 /// the same name, except ending in `.hack` instead of `.asm`.
+///
+/// When there are several input files, they're assembled in parallel, and a
+/// summary is printed at the end.
+///
+/// Options:
+/// * `--isa strict|extended`: which instruction set to accept. Defaults to
+///   `strict`.
+/// * `--stats`: print statistics about each assembled program.
+/// * `--check expected.hack`: compare the output against an expected `.hack`
+///   file, and report any differences. Requires a single input file.
+/// * `--lint`: warn about likely bugs found by dataflow analysis, such as
+///   reading `D` before it's set, or using `M` while `A` holds a label.
+/// * `--cfg FILE`: write the program's control-flow graph to `FILE`, in
+///   Graphviz's DOT language, and warn about jumps whose target can't be
+///   determined. Requires a single input file, which may instead be a
+///   `.hack` file, in which case nothing is assembled.
 fn main() -> Result<()> {
-    let (_prog_name, in_path) = env::args().collect_tuple().with_context(|| {
-        let n = env::args().count().saturating_sub(1);
-        format!("expected one argument, got {n}")
-    })?;
-    let out_path = out_path(&in_path)?;
+    let args = Args::parse(env::args().skip(1))?;
+    let in_paths = find_asm_files(&args.in_paths)?;
+
+    if let [in_path] = &in_paths[..] {
+        if in_path.extension() == Some(OsStr::new("hack")) {
+            let cfg_path = args
+                .cfg
+                .as_ref()
+                .context("--cfg is required for .hack files")?;
+            let contents = fs::read_to_string(in_path)
+                .with_context(|| format!("couldn't read file {}", in_path.display()))?;
+            let cfg = Cfg::from_words(&assembler::parse_hack(&contents)?)?;
+            return write_cfg(&cfg, cfg_path, None);
+        }
+    }
+
+    match &in_paths[..] {
+        [in_path] => assemble_one(in_path, &args),
+        _ => assemble_many(&in_paths, &args, io::stdout().lock()),
+    }
+}
+
+fn assemble_one(in_path: &Path, args: &Args) -> Result<()> {
+    let assembled = assemble_file(in_path, args.isa)?;
+
+    if args.stats {
+        assembled.stats.report(io::stdout().lock())?;
+    }
+    if args.lint {
+        lint(in_path, &assembled)?;
+    }
+
+    if let Some(expected_path) = &args.check {
+        let expected = fs::read_to_string(expected_path)
+            .with_context(|| format!("couldn't read file {expected_path}"))?;
+
+        let num_mismatches = assembler::check(
+            &assembled.words,
+            &assembled.source_map,
+            &expected,
+            io::stdout().lock(),
+        )?;
+        ensure!(num_mismatches == 0, "output doesn't match {expected_path}");
+    }
+
+    if let Some(cfg_path) = &args.cfg {
+        let cfg = Cfg::from_assembled(&assembled)?;
+        write_cfg(&cfg, cfg_path, Some(&assembled.source_map))?;
+    }
+
+    Ok(())
+}
+
+/// Print a warning for each problem found by dataflow analysis.
+fn lint(in_path: &Path, assembled: &Assembled) -> Result<()> {
+    for problem in assembler::analyze(assembled)? {
+        eprintln!(
+            "{}:{}: warning: {}: `{}`",
+            in_path.display(),
+            problem.line,
+            problem.message,
+            problem.text
+        );
+    }
+    Ok(())
+}
+
+/// Write a control-flow graph as DOT, and warn about unresolved jumps.
+fn write_cfg(cfg: &Cfg, path: &str, source_map: Option<&SourceMap>) -> Result<()> {
+    let file = File::create(path).with_context(|| format!("couldn't create file {path}"))?;
+    let mut out = BufWriter::new(file);
+    cfg.write_dot(&mut out)?;
+    out.flush()?;
+
+    for address in cfg.unresolved_jumps() {
+        match source_map.and_then(|source_map| source_map.get(address)) {
+            Some(line) => eprintln!(
+                "warning: ROM[{address}], line {}: can't determine the target of `{}`",
+                line.number,
+                line.text.trim()
+            ),
+            None => eprintln!("warning: ROM[{address}]: can't determine the jump target"),
+        }
+    }
+
+    Ok(())
+}
+
+/// Assemble the files on a pool of threads, one per CPU, and then summarize
+/// the results to `out`.
+///
+/// Each file is assembled by a single thread, which only touches that file's
+/// output, so a failure in one file doesn't affect the others.
+fn assemble_many(in_paths: &[PathBuf], args: &Args, mut out: impl Write) -> Result<()> {
+    ensure!(
+        args.check.is_none() && args.cfg.is_none(),
+        "--check and --cfg require a single input file, got {}",
+        in_paths.len()
+    );
+
+    let num_threads = thread::available_parallelism()
+        .map_or(1, |n| n.get())
+        .min(in_paths.len());
+
+    // Each thread takes the next file, until there are none left.
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<_> = thread::scope(|s| {
+        let handles: Vec<_> = (0..num_threads)
+            .map(|_| {
+                s.spawn(|| {
+                    let mut results = vec![];
+                    loop {
+                        let i = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(in_path) = in_paths.get(i) else {
+                            return results;
+                        };
+                        results.push((i, assemble_file(in_path, args.isa)));
+                    }
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().expect("assembler thread panicked"))
+            .collect()
+    });
+    results.sort_by_key(|&(i, _)| i);
+    let results: Vec<_> = results.into_iter().map(|(_, result)| result).collect();
+
+    if args.stats {
+        for (in_path, result) in zip(in_paths, &results) {
+            if let Ok(assembled) = result {
+                writeln!(out, "=== {} ===", in_path.display())?;
+                assembled.stats.report(&mut out)?;
+                writeln!(out)?;
+            }
+        }
+    }
+    if args.lint {
+        for (in_path, result) in zip(in_paths, &results) {
+            if let Ok(assembled) = result {
+                lint(in_path, assembled)?;
+            }
+        }
+    }
+
+    let mut num_failed = 0;
+    for (in_path, result) in zip(in_paths, &results) {
+        match result {
+            Ok(_) => writeln!(out, "ok      {}", in_path.display())?,
+            Err(e) => {
+                num_failed += 1;
+                writeln!(out, "FAILED  {}: {e:#}", in_path.display())?;
+            }
+        }
+    }
+
+    let n = in_paths.len();
+    ensure!(
+        num_failed == 0,
+        "{num_failed} of {n} files failed to assemble"
+    );
+    writeln!(out, "all {n} files assembled successfully")?;
+
+    Ok(())
+}
+
+/// Replace each directory in `paths` with the `.asm` files it contains.
+///
+/// Duplicates are removed, so that no two threads write the same output file.
+fn find_asm_files(paths: &[String]) -> Result<Vec<PathBuf>> {
+    let mut files = vec![];
+
+    for path in paths.iter().map(PathBuf::from) {
+        if !path.is_dir() {
+            files.push(path);
+            continue;
+        }
 
-    let in_file = File::open(&in_path).with_context(|| format!("couldn't open file {in_path}"))?;
+        let entries = fs::read_dir(&path)
+            .with_context(|| format!("couldn't read directory {}", path.display()))?;
+        let mut asm_files = vec![];
+        for entry in entries {
+            let entry_path = entry?.path();
+            if entry_path.extension() == Some(OsStr::new("asm")) {
+                asm_files.push(entry_path);
+            }
+        }
+        ensure!(
+            !asm_files.is_empty(),
+            "no .asm files in directory {}",
+            path.display()
+        );
+
+        asm_files.sort();
+        files.extend(asm_files);
+    }
+
+    let mut seen = HashSet::new();
+    files.retain(|f| seen.insert(fs::canonicalize(f).unwrap_or_else(|_| f.clone())));
+
+    Ok(files)
+}
+
+/// Assemble `path/to/filename.asm`, writing the output to
+/// `path/to/filename.hack`.
+fn assemble_file(in_path: &Path, isa: Isa) -> Result<Assembled> {
+    let out_path = out_path(in_path)?;
+
+    let in_file =
+        File::open(in_path).with_context(|| format!("couldn't open file {}", in_path.display()))?;
     let out_file = File::create(&out_path)
         .with_context(|| format!("couldn't create file {}", out_path.display()))?;
 
-    let result = translate(in_file, out_file);
+    let result = translate(in_file, out_file, isa);
 
     // If translation fails, clean up the output file.
     if result.is_err() {
@@ -50,6 +270,59 @@ fn main() -> Result<()> {
     result
 }
 
+/// Command-line arguments.
+struct Args {
+    in_paths: Vec<String>,
+    isa: Isa,
+    stats: bool,
+    lint: bool,
+    check: Option<String>,
+    cfg: Option<String>,
+}
+
+impl Args {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self> {
+        let mut in_paths = vec![];
+        let mut isa = Isa::default();
+        let mut stats = false;
+        let mut lint = false;
+        let mut check = None;
+        let mut cfg = None;
+
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--isa" => {
+                    let value = args.next().context("missing value for --isa")?;
+                    isa = value.parse()?;
+                }
+                "--stats" => stats = true,
+                "--lint" => lint = true,
+                "--check" => {
+                    let value = args.next().context("missing value for --check")?;
+                    check = Some(value);
+                }
+                "--cfg" => {
+                    let value = args.next().context("missing value for --cfg")?;
+                    cfg = Some(value);
+                }
+                _ if arg.starts_with("--") => bail!("unrecognized option {arg:?}"),
+                _ => in_paths.push(arg),
+            }
+        }
+
+        ensure!(!in_paths.is_empty(), "expected at least one input file");
+
+        Ok(Self {
+            in_paths,
+            isa,
+            stats,
+            lint,
+            check,
+            cfg,
+        })
+    }
+}
+
 /// Convert `path/to/filename.asm` to `path/to/filename.hack`.
 fn out_path(path: impl AsRef<Path>) -> Result<PathBuf> {
     let path = path.as_ref();
@@ -70,85 +343,94 @@ fn out_path(path: impl AsRef<Path>) -> Result<PathBuf> {
     Ok(out_path)
 }
 
-/// Translate assembly into binary format.
-fn translate(mut in_file: File, out_file: File) -> Result<()> {
-    let mut symbol_table = SymbolTable::new();
-    first_pass(&mut in_file, &mut symbol_table)?;
-    second_pass(in_file, out_file, &mut symbol_table)
-}
+/// Translate assembly into binary format, writing one line of binary digits
+/// per instruction.
+fn translate(in_file: File, out_file: File, isa: Isa) -> Result<Assembled> {
+    let assembled = assembler::assemble(BufReader::new(in_file), isa)?;
+    assembler::write_hack(&assembled.words, BufWriter::new(out_file))?;
 
-/// Read labels, of the form `(LABEL)`, and add them to the symbol table.
-fn first_pass(in_file: &mut File, symbol_table: &mut SymbolTable) -> Result<()> {
-    debug_assert_eq!(in_file.stream_position()?, 0);
+    Ok(assembled)
+}
 
-    let lines = BufReader::new(in_file)
-        .lines()
-        .map(|r| r.map_err(Into::into));
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_dir::TempDir;
 
-    let mut num_instructions = 0;
+    #[test]
+    fn find_asm_files() -> Result<()> {
+        let dir = TempDir::new("find")?;
+        fs::create_dir_all(dir.join("sub"))?;
+        for name in ["sub/b.asm", "sub/a.asm", "sub/notes.txt", "c.asm"] {
+            fs::write(dir.join(name), "")?;
+        }
+        let path = |name: &str| dir.join(name).display().to_string();
 
-    for line in remove_comments(lines) {
-        match Line::parse(&line?)? {
-            Line::Label(symbol) => {
-                symbol_table.new_label(symbol, num_instructions)?;
-            }
-            Line::Instr(_) => {
-                ensure!(
-                    num_instructions < ADDRESS_LIMIT,
-                    "can't emit more than {ADDRESS_LIMIT} instructions"
-                );
+        // Directories are expanded in sorted order, without recursing, and
+        // files named twice, even by different paths, are only kept the
+        // first time.
+        let result = super::find_asm_files(&[
+            path("c.asm"),
+            path("sub"),
+            path("sub/../sub/a.asm"),
+            path("c.asm"),
+        ]);
+        let top_level = super::find_asm_files(&[dir.display().to_string(), path("sub/notes.txt")]);
+        fs::remove_file(dir.join("c.asm"))?;
+        let no_asm = super::find_asm_files(&[dir.display().to_string()]);
 
-                num_instructions += 1;
-            }
-        }
+        assert_eq!(
+            result?,
+            [
+                dir.join("c.asm"),
+                dir.join("sub/a.asm"),
+                dir.join("sub/b.asm")
+            ]
+        );
+        assert_eq!(top_level?, [dir.join("c.asm"), dir.join("sub/notes.txt")]);
+        assert!(no_asm.is_err());
+        Ok(())
     }
 
-    Ok(())
-}
+    #[test]
+    fn assemble_many() -> Result<()> {
+        let dir = TempDir::new("many")?;
+        fs::write(dir.join("a.asm"), "@1\nD=A\n")?;
+        fs::write(dir.join("b.asm"), "@1\nD=Q\n")?;
+        fs::write(dir.join("c.asm"), "@2\n")?;
+        let in_paths = super::find_asm_files(&[dir.display().to_string()])?;
+        let args = Args::parse(in_paths.iter().map(|path| path.display().to_string()))?;
 
-/// Does the actual code-generation.
-///
-/// Unknown symbols are assumed to be new variables, and we generate new
-/// symbol-table entries accordingly.
-fn second_pass(
-    mut in_file: File,
-    mut out_file: File,
-    symbol_table: &mut SymbolTable,
-) -> Result<()> {
-    in_file.rewind()?;
-
-    let lines = BufReader::new(in_file)
-        .lines()
-        .map(|r| r.map_err(Into::into));
-
-    for line in remove_comments(lines) {
-        match Line::parse(&line?)? {
-            Line::Label(_) => (),
-            Line::Instr(instr) => {
-                let code = instr.code_gen(symbol_table)?;
-                writeln!(out_file, "{code:0>16b}")?;
-            }
-        }
-    }
+        // The bad file doesn't stop the others from being assembled.
+        let mut out = vec![];
+        let result = super::assemble_many(&in_paths, &args, &mut out);
+        let out = String::from_utf8(out)?;
 
-    Ok(())
-}
+        let err = result.unwrap_err();
+        assert_eq!(err.to_string(), "1 of 3 files failed to assemble");
+        // Results are listed in input order.
+        let ok_a = format!("ok      {}\n", dir.join("a.asm").display());
+        let failed_b = format!("FAILED  {}: ", dir.join("b.asm").display());
+        let ok_c = format!("ok      {}\n", dir.join("c.asm").display());
+        assert!(out.starts_with(&format!("{ok_a}{failed_b}")), "{out}");
+        assert!(out.ends_with(&ok_c), "{out}");
 
-/// Remove comments and blank lines.
-fn remove_comments(
-    lines: impl Iterator<Item = Result<String>>,
-) -> impl Iterator<Item = Result<String>> {
-    lines.filter_map_ok(|mut line| {
-        // Remove everything after the first "//".
-        if let Some(idx) = line.find("//") {
-            line.truncate(idx);
-        }
+        assert_eq!(
+            fs::read_to_string(dir.join("a.hack"))?,
+            "0000000000000001\n1110110000010000\n"
+        );
+        assert!(!dir.join("b.hack").exists());
+        assert!(dir.join("c.hack").exists());
 
-        // If this line is blank, filter it out.
-        if line.trim().is_empty() {
-            None
-        } else {
-            Some(line)
-        }
-    })
+        // Once it's fixed, everything succeeds.
+        fs::write(dir.join("b.asm"), "@1\nD=M\n")?;
+        let mut out = vec![];
+        super::assemble_many(&in_paths, &args, &mut out)?;
+        let out = String::from_utf8(out)?;
+        assert!(
+            out.ends_with("all 3 files assembled successfully\n"),
+            "{out}"
+        );
+        Ok(())
+    }
 }
@@ -0,0 +1,29 @@
+//! Map ROM addresses back to the assembly code that produced them.
+
+/// A line of assembly source code, with comments removed.
+#[derive(Debug, Clone)]
+pub struct SourceLine {
+    /// 1-based, like in a text editor.
+    pub number: usize,
+    pub text: String,
+
+    /// The symbol loaded by an A-instruction, if any.
+    pub symbol: Option<String>,
+}
+
+/// The source line of each instruction, indexed by ROM address.
+#[derive(Debug, Default)]
+pub struct SourceMap {
+    lines: Vec<SourceLine>,
+}
+
+impl SourceMap {
+    /// Record the source line of the next instruction.
+    pub fn push(&mut self, line: SourceLine) {
+        self.lines.push(line);
+    }
+
+    pub fn get(&self, address: u16) -> Option<&SourceLine> {
+        self.lines.get(usize::from(address))
+    }
+}
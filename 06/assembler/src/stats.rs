@@ -0,0 +1,202 @@
+//! Statistics about an assembled program, for the `--stats` report.
+
+use std::{collections::HashMap, io::Write};
+
+use anyhow::Result;
+use itertools::Itertools;
+
+use crate::instruction::{Instr, ADDRESS_LIMIT};
+
+/// How many of the largest code regions to report.
+const NUM_REGIONS: usize = 5;
+
+/// The widest bar to draw in a histogram.
+const MAX_BAR_WIDTH: usize = 40;
+
+#[derive(Default)]
+pub struct Stats {
+    num_a_instrs: u16,
+    num_c_instrs: u16,
+    num_variables: u16,
+
+    /// Each label, along with its ROM address, in program order.
+    labels: Vec<(String, u16)>,
+
+    comps: HashMap<String, usize>,
+    dests: HashMap<String, usize>,
+    jumps: HashMap<String, usize>,
+}
+
+impl Stats {
+    pub fn record_label(&mut self, label: &str, address: u16) {
+        self.labels.push((label.to_owned(), address));
+    }
+
+    pub fn record_instr(&mut self, instr: &Instr) {
+        let Some([dest, comp, jump]) = instr.c_fields() else {
+            self.num_a_instrs += 1;
+            return;
+        };
+
+        self.num_c_instrs += 1;
+        *self.dests.entry(dest).or_default() += 1;
+        *self.comps.entry(comp).or_default() += 1;
+        *self.jumps.entry(jump).or_default() += 1;
+    }
+
+    pub fn record_variables(&mut self, num_variables: u16) {
+        self.num_variables = num_variables;
+    }
+
+    pub fn report(&self, mut out: impl Write) -> Result<()> {
+        let rom_words = self.num_a_instrs + self.num_c_instrs;
+        let percent = 100.0 * f64::from(rom_words) / f64::from(ADDRESS_LIMIT);
+        writeln!(
+            out,
+            "ROM usage: {rom_words} of {ADDRESS_LIMIT} words ({percent:.2}%)"
+        )?;
+
+        write!(out, "variables: {}", self.num_variables)?;
+        if self.num_variables != 0 {
+            let first = 16;
+            let last = first + self.num_variables - 1;
+            write!(out, " (RAM addresses {first}..={last})")?;
+        }
+        writeln!(out)?;
+
+        writeln!(
+            out,
+            "instructions: {} A, {} C",
+            self.num_a_instrs, self.num_c_instrs
+        )?;
+
+        for (name, histogram) in [
+            ("comp", &self.comps),
+            ("dest", &self.dests),
+            ("jump", &self.jumps),
+        ] {
+            writeln!(out, "\n{name} usage:")?;
+            write_histogram(&mut out, histogram)?;
+        }
+
+        writeln!(out, "\nlargest code regions:")?;
+        for (label, start, end) in self.largest_regions(rom_words) {
+            let len = end - start;
+            writeln!(out, "{len:>7} words  {label} ({start}..{end})")?;
+        }
+
+        Ok(())
+    }
+
+    /// The `NUM_REGIONS` largest spans of code between consecutive labels, as
+    /// `(label, start, end)`.
+    ///
+    /// Code before the first label is attributed to a pseudo-label, `<start>`.
+    fn largest_regions(&self, rom_words: u16) -> Vec<(&str, u16, u16)> {
+        let starts = [("<start>", 0)]
+            .into_iter()
+            .chain(self.labels.iter().map(|(l, addr)| (l.as_str(), *addr)));
+        let ends = self.labels.iter().map(|(_, addr)| *addr).chain([rom_words]);
+
+        let regions = starts
+            .zip(ends)
+            .map(|((label, start), end)| (label, start, end));
+
+        regions
+            .filter(|&(_, start, end)| start < end)
+            .sorted_by_key(|&(_, start, end)| (std::cmp::Reverse(end - start), start))
+            .take(NUM_REGIONS)
+            .collect()
+    }
+}
+
+/// Print one line per entry, most frequent first, with a bar proportional to
+/// its count.
+fn write_histogram(mut out: impl Write, histogram: &HashMap<String, usize>) -> Result<()> {
+    let max_count = histogram.values().copied().max().unwrap_or(0);
+    let width = histogram.keys().map(String::len).max().unwrap_or(0);
+
+    let entries = histogram
+        .iter()
+        .sorted_by_key(|&(key, count)| (std::cmp::Reverse(*count), key));
+
+    for (key, &count) in entries {
+        let bar_len = (count * MAX_BAR_WIDTH).div_ceil(max_count);
+        let bar = "#".repeat(bar_len);
+        writeln!(out, "{key:<width$} {count:>7}  {bar}")?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{assemble, Isa};
+
+    #[test]
+    fn report() -> Result<()> {
+        let source = "\
+            @i\n\
+            M=0\n\
+            (LOOP)\n\
+            @i\n\
+            M=M+1\n\
+            D=M\n\
+            @10\n\
+            D=D-A\n\
+            @LOOP\n\
+            D;JLT\n\
+            @sum\n\
+            M=D\n\
+            (END)\n\
+            @END\n\
+            0;JMP\n";
+        let stats = assemble(source.as_bytes(), Isa::Strict)?.stats;
+
+        assert_eq!((stats.num_a_instrs, stats.num_c_instrs), (6, 7));
+        assert_eq!(stats.num_variables, 2);
+        assert_eq!(stats.comps["D"], 2);
+        assert_eq!(stats.jumps["JLT"], 1);
+        // The last region runs to the end of the program.
+        assert_eq!(
+            stats.largest_regions(13),
+            [("LOOP", 2, 11), ("<start>", 0, 2), ("END", 11, 13)]
+        );
+
+        let mut report = vec![];
+        stats.report(&mut report)?;
+        let report = String::from_utf8(report)?;
+        assert_eq!(
+            report,
+            "\
+ROM usage: 13 of 32768 words (0.04%)
+variables: 2 (RAM addresses 16..=17)
+instructions: 6 A, 7 C
+
+comp usage:
+0         2  ########################################
+D         2  ########################################
+D-A       1  ####################
+M         1  ####################
+M+1       1  ####################
+
+dest usage:
+M          3  ########################################
+D          2  ###########################
+null       2  ###########################
+
+jump usage:
+null       5  ########################################
+JLT        1  ########
+JMP        1  ########
+
+largest code regions:
+      9 words  LOOP (2..11)
+      2 words  <start> (0..2)
+      2 words  END (11..13)
+"
+        );
+        Ok(())
+    }
+}
@@ -8,8 +8,9 @@ use anyhow::{bail, ensure, Result};
 use crate::instruction::ADDRESS_LIMIT;
 
 /// A mapping from symbols to the memory addresses they correspond to.
+#[derive(Clone)]
 pub struct SymbolTable {
-    mapping: HashMap<String, u16>,
+    mapping: HashMap<String, (u16, SymbolKind)>,
 
     /// How many distict _variables_ have been assigned?
     ///
@@ -17,24 +18,70 @@ pub struct SymbolTable {
     num_variables: u16,
 }
 
+/// What a symbol refers to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolKind {
+    /// Built in, e.g. `R0`, `SP`, or `SCREEN`, including any extra devices.
+    Predefined,
+
+    /// A ROM address, defined by `(LABEL)`.
+    Label,
+
+    /// A RAM address, allocated when the symbol is first used.
+    Variable,
+}
+
 impl SymbolTable {
     /// Create a new symbol table, including all pre-defined symbols.
     pub fn new() -> Self {
+        let mut symbol_table = Self::without_devices();
+        let devices = [
+            ("SCREEN", 0b_0100_0000_0000_0000),
+            ("KBD", 0b_0110_0000_0000_0000),
+        ];
+        for (symbol, address) in devices {
+            symbol_table
+                .new_device(symbol.into(), address)
+                .expect("devices have distinct names");
+        }
+        symbol_table
+    }
+
+    /// Create a new symbol table with the registers, but no memory-mapped
+    /// devices, not even `SCREEN` and `KBD`. Add them with `new_device`.
+    pub fn without_devices() -> Self {
         let registers = (0..16).map(|i| (format!("R{i}"), i));
         let aliases = zip(["SP", "LCL", "ARG", "THIS", "THAT"].map(String::from), 0..);
-        let hardware = [
-            ("SCREEN".into(), 0b_0100_0000_0000_0000),
-            ("KBD".into(), 0b_0110_0000_0000_0000),
-        ];
+
+        let mapping = registers
+            .chain(aliases)
+            .map(|(symbol, address)| (symbol, (address, SymbolKind::Predefined)))
+            .collect();
 
         Self {
-            mapping: registers.chain(aliases).chain(hardware).collect(),
+            mapping,
             num_variables: 0,
         }
     }
 
     pub fn lookup_symbol(&self, symbol: &str) -> Option<u16> {
-        self.mapping.get(symbol).copied()
+        self.mapping.get(symbol).map(|&(address, _)| address)
+    }
+
+    pub fn lookup_kind(&self, symbol: &str) -> Option<SymbolKind> {
+        self.mapping.get(symbol).map(|&(_, kind)| kind)
+    }
+
+    /// All symbols of the given kind, with their addresses, in address order.
+    pub fn symbols(&self, kind: SymbolKind) -> Vec<(&str, u16)> {
+        let mut symbols: Vec<_> = self
+            .mapping
+            .iter()
+            .filter(|(_, &(_, k))| k == kind)
+            .map(|(symbol, &(address, _))| (symbol.as_str(), address))
+            .collect();
+        symbols.sort_by_key(|&(symbol, address)| (address, symbol));
+        symbols
     }
 
     /// Variables are assigned increasing memory addresses, starting from 16.
@@ -46,26 +93,39 @@ impl SymbolTable {
             "can't allocate more than {ADDRESS_LIMIT} variables"
         );
 
-        self.try_insert(symbol, address)?;
+        self.try_insert(symbol, address, SymbolKind::Variable)?;
 
         self.num_variables += 1;
         Ok(address)
     }
 
+    pub fn num_variables(&self) -> u16 {
+        self.num_variables
+    }
+
     pub fn new_label(&mut self, symbol: String, instruction_offset: u16) -> Result<()> {
-        self.try_insert(symbol, instruction_offset)
+        self.try_insert(symbol, instruction_offset, SymbolKind::Label)
+    }
+
+    /// Define a predefined symbol for a memory-mapped device, e.g. `SCREEN`.
+    pub fn new_device(&mut self, symbol: String, address: u16) -> Result<()> {
+        ensure!(
+            address < ADDRESS_LIMIT,
+            "device {symbol} is outside of memory: {address}"
+        );
+        self.try_insert(symbol, address, SymbolKind::Predefined)
     }
 
     /// Fails if the symbol already exists.
-    fn try_insert(&mut self, symbol: String, value: u16) -> Result<()> {
+    fn try_insert(&mut self, symbol: String, value: u16, kind: SymbolKind) -> Result<()> {
         // The `Entry` API lets us avoid cloning `symbol` in the happy path.
         match self.mapping.entry(symbol) {
             Entry::Vacant(e) => {
-                e.insert(value);
+                e.insert((value, kind));
             }
             Entry::Occupied(e) => {
                 let symbol = e.key();
-                let prev_val = e.get();
+                let (prev_val, _) = e.get();
                 bail!("attempt to re-define symbol {symbol:?}. previous value: {prev_val}, new value: {value}");
             }
         }
@@ -73,3 +133,9 @@ impl SymbolTable {
         Ok(())
     }
 }
+
+impl Default for SymbolTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
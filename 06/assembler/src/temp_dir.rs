@@ -0,0 +1,43 @@
+//! Temporary directories for tests.
+//!
+//! Shared with the CPU emulator's tests, which include this file by path.
+
+use std::{
+    env, fs, io,
+    ops::Deref,
+    path::{Path, PathBuf},
+    process,
+};
+
+/// A fresh directory, which is removed when dropped, even if a test fails.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    /// Create an empty directory, named after the crate, `name`, and this
+    /// process.
+    pub fn new(name: &str) -> io::Result<Self> {
+        let crate_name = env!("CARGO_PKG_NAME");
+        let path = env::temp_dir().join(format!("{crate_name}-{name}-{}", process::id()));
+        // There may be one left over from a process with the same ID.
+        if path.exists() {
+            fs::remove_dir_all(&path)?;
+        }
+        fs::create_dir_all(&path)?;
+        Ok(Self(path))
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        // There's no way to report an error, and nothing to do about it.
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
@@ -0,0 +1,27 @@
+//! Check that single-pass assembly matches the old two-pass assembler.
+
+mod support;
+
+use std::io::Cursor;
+
+use anyhow::Result;
+use assembler::Isa;
+
+#[test]
+fn large_program() -> Result<()> {
+    let source = support::large_program();
+
+    let mut expected = vec![];
+    support::assemble_two_pass(Cursor::new(&source), &mut expected, Isa::Strict)?;
+
+    let assembled = assembler::assemble(source.as_bytes(), Isa::Strict)?;
+    let output: String = (assembled.words.iter())
+        .map(|code| format!("{code:0>16b}\n"))
+        .collect();
+
+    assert!(
+        output.as_bytes() == expected,
+        "output differs from the two-pass assembler"
+    );
+    Ok(())
+}
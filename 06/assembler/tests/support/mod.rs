@@ -0,0 +1,95 @@
+//! A large program to assemble, and the old two-pass assembler to compare
+//! against. Shared by the tests and the benchmark.
+
+use std::io::{BufRead, Seek, Write};
+
+use anyhow::{ensure, Result};
+use assembler::{Isa, Line, SymbolTable, ADDRESS_LIMIT};
+
+/// Roughly 30K lines of assembly code, similar in shape to the output of the
+/// VM translator. Each loop jumps forward to the next one's label.
+pub fn large_program() -> String {
+    let mut program = String::new();
+
+    for i in 0..1500 {
+        program.push_str(&format!(
+            "\
+// *** push local {i} ***
+
+// D := *(LCL + {i})
+@LCL
+D=M
+@{i}
+A=D+A
+D=M
+
+// *SP := D
+@SP
+A=M
+M=D
+
+// SP++
+@SP
+M=M+1
+
+(LOOP_{i})
+@var_{n}
+D = D + M
+@LOOP_{next}
+D; JGT
+",
+            n = i % 100,
+            next = (i + 1) % 1500,
+        ));
+    }
+
+    program
+}
+
+/// Assemble `input` the way the assembler used to: in two passes, each of
+/// which reads and parses the whole source, writing each instruction
+/// straight to `out`.
+pub fn assemble_two_pass(
+    mut input: impl BufRead + Seek,
+    mut out: impl Write,
+    isa: Isa,
+) -> Result<()> {
+    let mut symbol_table = SymbolTable::new();
+
+    let mut num_instructions = 0;
+    for line in code_lines(&mut input)? {
+        match Line::parse(&line, isa)? {
+            Line::Label(symbol) => symbol_table.new_label(symbol, num_instructions)?,
+            Line::Instr(_) => {
+                ensure!(num_instructions < ADDRESS_LIMIT, "too many instructions");
+                num_instructions += 1;
+            }
+        }
+    }
+
+    input.rewind()?;
+    for line in code_lines(&mut input)? {
+        if let Line::Instr(instr) = Line::parse(&line, isa)? {
+            let code = instr.code_gen(&mut symbol_table)?;
+            writeln!(out, "{code:0>16b}")?;
+        }
+    }
+
+    out.flush()?;
+    Ok(())
+}
+
+/// The lines of `input` with code on them, without comments.
+fn code_lines(input: impl BufRead) -> Result<Vec<String>> {
+    let mut lines = vec![];
+    for line in input.lines() {
+        let mut line = line?;
+        if let Some(idx) = line.find("//") {
+            line.truncate(idx);
+        }
+        if !line.trim().is_empty() {
+            lines.push(line);
+        }
+    }
+    Ok(lines)
+}
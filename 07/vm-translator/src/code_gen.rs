@@ -5,7 +5,7 @@ use std::io::Write;
 
 use anyhow::{bail, Result};
 
-use crate::vm_command::{Command, Segment, VirtualMemoryAddr};
+use crate::vm_command::{Command, Segment, SourceLocation, VirtualMemoryAddr};
 
 pub fn halt(mut out: impl Write) -> Result<()> {
     write!(
@@ -21,8 +21,8 @@ pub fn halt(mut out: impl Write) -> Result<()> {
 }
 
 impl Command {
-    pub fn code_gen(self, mut out: impl Write) -> Result<()> {
-        write!(out, "// *** {self:?} ***\n\n")?;
+    pub fn code_gen(self, location: SourceLocation, mut out: impl Write) -> Result<()> {
+        write!(out, "// *** {self:?} *** {location}\n\n")?;
 
         match self {
             Self::Push { source } => push(source, &mut out)?,
@@ -67,7 +67,7 @@ D=A
             let this_that = match index {
                 0 => "THIS",
                 1 => "THAT",
-                i @ _ => bail!("invalid index into `pointer` virtual memory segment: {i}"),
+                i => bail!("invalid index into `pointer` virtual memory segment: {i}"),
             };
 
             write!(
@@ -327,7 +327,11 @@ mod tests {
 
     fn assert_code_gen(command: Command, expected_code: &str) -> Result<()> {
         let mut generated = vec![];
-        command.code_gen(&mut generated)?;
+        let location = SourceLocation {
+            file: "Test.vm",
+            line: 1,
+        };
+        command.code_gen(location, &mut generated)?;
         assert_eq!(str::from_utf8(&generated)?, expected_code);
         Ok(())
     }
@@ -342,7 +346,7 @@ mod tests {
         // * option 2: keep track of the current instruction address during codegen,
         //   and emit those as jump targets
         let code = "\
-// *** eq ***
+// *** eq *** Test.vm:1
 
 // SP--
 @SP
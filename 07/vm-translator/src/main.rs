@@ -15,7 +15,7 @@ use std::{
 use anyhow::{ensure, Context, Result};
 use itertools::Itertools;
 
-use crate::vm_command::Command;
+use crate::vm_command::{Command, SourceLocation};
 
 /// Expects one argmuent: a filename with a `.vm` extension.
 ///
@@ -28,12 +28,18 @@ fn main() -> Result<()> {
     })?;
     let out_path = out_path(&in_path)?;
 
+    // Used to mark where each command came from.
+    let file_name = Path::new(&in_path)
+        .file_name()
+        .and_then(OsStr::to_str)
+        .context("input file name must be valid unicode")?;
+
     let in_file =
         File::open(&in_path).with_context(|| format!("couldn't open file {in_path:?}"))?;
     let out_file =
         File::create(&out_path).with_context(|| format!("couldn't create file {out_path:?}"))?;
 
-    let result = translate(in_file, out_file);
+    let result = translate(in_file, file_name, out_file);
 
     // If translation fails, clean up the output file.
     if result.is_err() {
@@ -70,14 +76,20 @@ fn out_path(path: impl AsRef<Path>) -> Result<PathBuf> {
 }
 
 /// Translate VM language into assembly language.
-fn translate(in_file: File, mut out_file: File) -> Result<()> {
+fn translate(in_file: File, file_name: &str, mut out_file: File) -> Result<()> {
     let lines = BufReader::new(in_file)
         .lines()
-        .map(|r| r.map_err(Into::into));
+        .enumerate()
+        .map(|(i, r)| r.map(|line| (i + 1, line)).map_err(Into::into));
 
     for line in remove_comments_and_blanks(lines) {
-        let command: Command = line?.parse()?;
-        command.code_gen(&mut out_file)?;
+        let (line_number, line) = line?;
+        let command: Command = line.parse()?;
+        let location = SourceLocation {
+            file: file_name,
+            line: line_number,
+        };
+        command.code_gen(location, &mut out_file)?;
     }
 
     // Terminate the assembly program with an infinite loop.
@@ -86,11 +98,12 @@ fn translate(in_file: File, mut out_file: File) -> Result<()> {
     Ok(())
 }
 
-/// Trim comments, and then remove any blank lines.
+/// Trim comments, and then remove any blank lines. Lines are numbered, so
+/// that line numbers are kept.
 fn remove_comments_and_blanks(
-    lines: impl Iterator<Item = Result<String>>,
-) -> impl Iterator<Item = Result<String>> {
-    lines.filter_map_ok(|mut line| {
+    lines: impl Iterator<Item = Result<(usize, String)>>,
+) -> impl Iterator<Item = Result<(usize, String)>> {
+    lines.filter_map_ok(|(number, mut line)| {
         // Remove everything after the first "//".
         if let Some(idx) = line.find("//") {
             line.truncate(idx);
@@ -100,7 +113,7 @@ fn remove_comments_and_blanks(
         if line.trim().is_empty() {
             None
         } else {
-            Some(line)
+            Some((number, line))
         }
     })
 }
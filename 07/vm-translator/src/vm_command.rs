@@ -27,6 +27,18 @@ pub enum Command {
     Not,
 }
 
+/// Where a command appears in the VM source, as `File.vm:line`.
+///
+/// This is written after each command's marker comment in the generated
+/// assembly, so that debuggers can map instructions back to VM source.
+#[derive(Clone, Copy)]
+pub struct SourceLocation<'a> {
+    pub file: &'a str,
+
+    /// 1-based, like in a text editor.
+    pub line: usize,
+}
+
 /// A location in the nand2tetris VM's virtual memory.
 #[derive(Clone, Copy)]
 pub struct VirtualMemoryAddr {
@@ -65,6 +77,12 @@ impl Debug for Command {
     }
 }
 
+impl fmt::Display for SourceLocation<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)
+    }
+}
+
 impl Debug for VirtualMemoryAddr {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{:?} {}", self.segment, self.index)